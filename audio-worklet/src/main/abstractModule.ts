@@ -2,6 +2,9 @@ import { SobakaContext } from './sobaka.node'
 import { AudioModuleType } from '../../bindings/AudioModuleType'
 import { AudioModuleEvent } from '../../bindings/AudioModuleEvent'
 import { AudioModuleCommand } from '../../bindings/AudioModuleCommand'
+import { CommandTime } from '../../bindings/CommandTime'
+import { Ramp } from '../../bindings/Ramp'
+import { Preset } from '../../bindings/Preset'
 import { Subscriber, Unsubscriber } from './interface'
 
 export type NodeType = AudioModuleType['node_type']
@@ -17,10 +20,10 @@ export abstract class AbstractModule<T extends NodeType> {
   private context: SobakaContext
   private unsubscribe_handles: Unsubscriber[] = []
   address: Promise<string>
-  constructor(context: SobakaContext, type: T, state: Params<T>) {
+  constructor(context: SobakaContext, type: T, state: Params<T>, parent?: AbstractModule<'Subpatch'>) {
     this.context = context
     this.type = type
-    this.address = this.create(context, state)
+    this.address = this.create(context, state, parent)
   }
 
   get_address() {
@@ -31,10 +34,12 @@ export abstract class AbstractModule<T extends NodeType> {
     return this.context
   }
 
-  async create(context: SobakaContext, params: Params<T>): Promise<string> {
+  async create(context: SobakaContext, params: Params<T>, parent?: AbstractModule<'Subpatch'>): Promise<string> {
+    const parent_address = parent ? [await parent.get_address()] : []
+
     return context.client.request({
       method: 'create',
-      params: [this.to_module_dto(params)]
+      params: [this.to_module_dto(params), ...parent_address]
     }) as Promise<string>
   }
 
@@ -52,15 +57,70 @@ export abstract class AbstractModule<T extends NodeType> {
     return result
   }
 
-  async message(command: Command<T>): Promise<void> {
+  /**
+   * Send a command to the module, on the exact sample at `at` when given,
+   * e.g. `{ Seconds: audioContext.currentTime + 0.1 }`.
+   * Parameters changed by the command ramp to their new value along `ramp` when given,
+   * e.g. `{ duration: 0.5, curve: 'Exponential' }`
+   */
+  async message(command: Command<T>, at?: CommandTime, ramp?: Ramp): Promise<void> {
     const address = await this.get_address()
 
     await this.get_context().client.request({
       method: 'message',
-      params: [address, this.to_module_dto(command)]
+      params: [address, this.to_module_dto(command), at ?? null, ramp ?? null]
     })
   }
 
+  /**
+   * Set a parameter by name, clamped to its range. Timed and ramped like `message`.
+   * Parameters of each module are listed by `list_modules`.
+   */
+  async set_param(name: string, value: number, at?: CommandTime, ramp?: Ramp): Promise<boolean> {
+    const address = await this.get_address()
+
+    return this.get_context().client.request({
+      method: 'set_param',
+      params: [address, name, value, at ?? null, ramp ?? null]
+    }) as Promise<boolean>
+  }
+
+  /**
+   * Current value of a parameter by name
+   */
+  async get_param(name: string): Promise<number> {
+    const address = await this.get_address()
+
+    return this.get_context().client.request({
+      method: 'get_param',
+      params: [address, name]
+    }) as Promise<number>
+  }
+
+  /**
+   * Save the current params of the module as a preset for its module type
+   */
+  async save_preset(name: string): Promise<Preset> {
+    const address = await this.get_address()
+
+    return this.get_context().client.request({
+      method: 'save_preset',
+      params: [address, name]
+    }) as Promise<Preset>
+  }
+
+  /**
+   * Set the module to a preset saved for its module type. Timed and ramped like `message`.
+   */
+  async apply_preset(name: string, at?: CommandTime, ramp?: Ramp): Promise<boolean> {
+    const address = await this.get_address()
+
+    return this.get_context().client.request({
+      method: 'apply_preset',
+      params: [address, name, at ?? null, ramp ?? null]
+    }) as Promise<boolean>
+  }
+
   private to_module_dto<T>(input: T): { node_type: string, data: T } {
     return { node_type: this.type, data: input }
   }
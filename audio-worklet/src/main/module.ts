@@ -62,8 +62,20 @@ export class Noise extends AbstractModule<'Noise'> {
 }
 
 export class Midi extends AbstractModule<'Midi'> {
-  constructor(context: SobakaContext) {
-    super(context, 'Midi', undefined)
+  constructor(context: SobakaContext, initial_state: Params<'Midi'> = null) {
+    super(context, 'Midi', initial_state)
+  }
+}
+
+export class MidiFile extends AbstractModule<'MidiFile'> {
+  constructor(context: SobakaContext, initial_state: Params<'MidiFile'>) {
+    super(context, 'MidiFile', initial_state)
+  }
+}
+
+export class MidiOut extends AbstractModule<'MidiOut'> {
+  constructor(context: SobakaContext, initial_state: Params<'MidiOut'>) {
+    super(context, 'MidiOut', initial_state)
   }
 }
 
@@ -113,4 +125,15 @@ export class SampleAndHold extends AbstractModule<'SampleAndHold'> {
   constructor(context: SobakaContext) {
     super(context, 'SampleAndHold', undefined as never)
   }
-}
\ No newline at end of file
+}
+export class Poly extends AbstractModule<'Poly'> {
+  constructor(context: SobakaContext, initial_state: Params<'Poly'>, parent?: Subpatch) {
+    super(context, 'Poly', initial_state, parent)
+  }
+}
+
+export class Subpatch extends AbstractModule<'Subpatch'> {
+  constructor(context: SobakaContext, initial_state: Params<'Subpatch'>, parent?: Subpatch) {
+    super(context, 'Subpatch', initial_state, parent)
+  }
+}
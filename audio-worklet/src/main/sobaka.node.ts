@@ -3,6 +3,12 @@ import { RequestManager, Client } from "@open-rpc/client-js";
 import { PostMessageTransport } from "./postMessageTransport";
 import { AbstractModule } from "./abstractModule";
 import { In, Out } from "./conversion";
+import { SumMode } from "../../bindings/SumMode";
+import { Patch } from "../../bindings/Patch";
+import { Operation } from "../../bindings/Operation";
+import { GraphDescription } from "../../bindings/GraphDescription";
+import { ModuleInfo } from "../../bindings/ModuleInfo";
+import { Preset } from "../../bindings/Preset";
 export class SobakaContext extends AudioWorkletNode {
   client: Client
   private subscriptions: Map<
@@ -62,7 +68,7 @@ export class SobakaContext extends AudioWorkletNode {
   public link<
     A extends AbstractModule<any>,
     B extends AbstractModule<any>
-  >(from: A, from_port: number, to: B, to_port: number): () => void {
+  >(from: A, from_port: number, to: B, to_port: number, mode?: SumMode): () => void {
     const pending_cleanup = Promise.all([
       from.get_address(),
       to.get_address()
@@ -72,14 +78,17 @@ export class SobakaContext extends AudioWorkletNode {
         params: [
           `${address_a}/${Out(from_port)}`,
           `${address_b}/${In(to_port)}`,
+          ...(mode ? [mode] : [])
         ]
       }) as Promise<number>
     })
 
     return async () => {
+      // Connections inside a subpatch are looked up through the subpatch address
+      const parent = (await from.get_address()).split('/').slice(0, -1).join('/')
       void this.client.request({
         method: 'disconnect',
-        params: [await pending_cleanup]
+        params: [await pending_cleanup, ...(parent === '/sobaka' ? [] : [parent])]
       })
     }
   }
@@ -112,6 +121,96 @@ export class SobakaContext extends AudioWorkletNode {
     }
   }
 
+  public async get_patch(): Promise<Patch> {
+    return this.client.request({
+      method: 'get_patch',
+      params: []
+    }) as Promise<Patch>
+  }
+
+  public async load_patch(patch: Patch): Promise<boolean> {
+    return this.client.request({
+      method: 'load_patch',
+      params: [patch]
+    }) as Promise<boolean>
+  }
+
+  public async describe(): Promise<GraphDescription> {
+    return this.client.request({
+      method: 'describe',
+      params: []
+    }) as Promise<GraphDescription>
+  }
+
+  public async list_modules(): Promise<ModuleInfo[]> {
+    return this.client.request({
+      method: 'list_modules',
+      params: []
+    }) as Promise<ModuleInfo[]>
+  }
+
+  /**
+   * Presets saved for a module type, e.g. 'Envelope'
+   */
+  public async list_presets(module_type: string): Promise<Preset[]> {
+    return this.client.request({
+      method: 'list_presets',
+      params: [module_type]
+    }) as Promise<Preset[]>
+  }
+
+  public async delete_preset(module_type: string, name: string): Promise<boolean> {
+    return this.client.request({
+      method: 'delete_preset',
+      params: [module_type, name]
+    }) as Promise<boolean>
+  }
+
+  /**
+   * Save presets listed in an earlier session
+   */
+  public async load_presets(presets: Preset[]): Promise<boolean> {
+    return this.client.request({
+      method: 'load_presets',
+      params: [presets]
+    }) as Promise<boolean>
+  }
+
+  public async set_seed(seed: number): Promise<boolean> {
+    return this.client.request({
+      method: 'set_seed',
+      params: [seed]
+    }) as Promise<boolean>
+  }
+
+  public async get_seed(): Promise<number> {
+    return this.client.request({
+      method: 'get_seed',
+      params: []
+    }) as Promise<number>
+  }
+
+  public async undo(): Promise<boolean> {
+    return this.client.request({
+      method: 'undo',
+      params: []
+    }) as Promise<boolean>
+  }
+
+  public async redo(): Promise<boolean> {
+    return this.client.request({
+      method: 'redo',
+      params: []
+    }) as Promise<boolean>
+  }
+
+  public async apply(operations: Operation[]): Promise<Array<string | number | boolean>> {
+    return this.client.request({
+      method: 'apply',
+      params: [operations]
+    }) as Promise<Array<string | number | boolean>>
+  }
+
   public async send_wasm_program(data: ArrayBuffer): Promise<void> {
     await this.client.request({
       method: 'send_wasm_program',
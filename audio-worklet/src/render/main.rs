@@ -0,0 +1,73 @@
+//! Render a patch saved with `get_patch` to a WAV file, without a browser.
+//!
+//! Usage: sobaka-render <patch.json> <output.wav> [--script <script.json>]
+//!        [--duration <seconds>] [--sample-rate <hz>]
+//!
+//! A script is a list of operations, each applied at its `time` in seconds, e.g.
+//! `[{ "time": 0.5, "op": "Message", "address": "/sobaka/2", "message": { ... } }]`
+
+use std::{env, error::Error, fs, path::Path, process};
+
+use sobaka_sample_audio_worklet::{
+    interface::patch::Patch,
+    render::{render, TimedOperation},
+};
+
+const USAGE: &str = "usage: sobaka-render <patch.json> <output.wav> [--script <script.json>] [--duration <seconds>] [--sample-rate <hz>]";
+
+struct Options {
+    patch: String,
+    output: String,
+    script: Option<String>,
+    duration: f64,
+    sample_rate: f64,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, Box<dyn Error>> {
+    let mut paths = vec![];
+    let mut script = None;
+    let mut duration = 10.0;
+    let mut sample_rate = 44100.0;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--script" => script = Some(value()?),
+            "--duration" => duration = value()?.parse()?,
+            "--sample-rate" => sample_rate = value()?.parse()?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg).into()),
+            _ => paths.push(arg),
+        }
+    }
+
+    match &paths[..] {
+        [patch, output] => Ok(Options {
+            patch: patch.clone(),
+            output: output.clone(),
+            script,
+            duration,
+            sample_rate,
+        }),
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    let patch: Patch = serde_json::from_str(&fs::read_to_string(&options.patch)?)?;
+    let script: Vec<TimedOperation> = match &options.script {
+        Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+        None => vec![],
+    };
+
+    let wave = render(patch, script, options.sample_rate, options.duration)?;
+    wave.save_wav16(Path::new(&options.output))?;
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = parse(env::args().skip(1)).and_then(run) {
+        eprintln!("sobaka-render: {}", error);
+        process::exit(1);
+    }
+}
@@ -1,8 +1,13 @@
-use std::convert::TryInto;
+use std::{convert::TryInto, sync::Arc};
 
+use fundsp::hacker::Tag;
 use futures::StreamExt;
 
 use crate::{
+    dsp::{
+        messaging::{Message, ParamMessage},
+        param::Ramp,
+    },
     module::{AudioModuleCommand, AudioModuleEvent, NoOp},
     utils::observer::{BoxedObservable, Observable, Observer, Producer, Subject},
 };
@@ -15,7 +20,9 @@ where
     Event: Into<AudioModuleEvent>,
 {
     /// Message transmitter. Incoming messages get sent into this transmitter.
-    tx: Option<Subject<Command>>,
+    tx: Option<Arc<Subject<Message<Command>>>>,
+    /// Parameter transmitter. Tagged parameter changes get sent into this transmitter.
+    params: Option<Arc<Subject<ParamMessage>>>,
     /// Message receiver. Outgoing messages get sent out via this receiver.
     rx: Option<BoxedObservable<Event>>,
 }
@@ -27,7 +34,11 @@ where
     Rx: Into<AudioModuleEvent> + Send + Clone,
 {
     fn default() -> Self {
-        Self { tx: None, rx: None }
+        Self {
+            tx: None,
+            params: None,
+            rx: None,
+        }
     }
 }
 
@@ -38,8 +49,13 @@ where
     Rx: Into<AudioModuleEvent> + Send + Clone + 'static,
 {
     /// Sets the command handler for the module
-    pub fn set_tx(&mut self, tx: Subject<Tx>) {
-        self.tx = Some(tx);
+    pub fn set_tx(&mut self, tx: Subject<Message<Tx>>) {
+        self.tx = Some(Arc::new(tx));
+    }
+
+    /// Sets the parameter handler for the module, see `AudioModuleType::params`
+    pub fn set_params(&mut self, params: Subject<ParamMessage>) {
+        self.params = Some(Arc::new(params));
     }
 
     /// Sets the event emitter for the module
@@ -52,10 +68,37 @@ where
     }
 }
 
+/// Reasons a module refuses a command or parameter change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+    /// No module with the id
+    UnknownNode,
+    /// The command is not one of the commands of the module, or it takes none
+    CommandMismatch,
+    /// The module has no parameter with the tag
+    UnknownParam(Tag),
+}
+
 // @todo fix error types here
 pub trait GeneralMessaging {
-    /// Try send command using the module specific command type
-    fn try_notify(&self, message: AudioModuleCommand) -> Result<(), ()>;
+    /// Try send command using the module specific command type,
+    /// parameters it changes follow `ramp` when given
+    fn try_notify(&self, message: AudioModuleCommand, ramp: Option<Ramp>) -> Result<(), ()>;
+
+    /// Try convert the command, returning a function that sends it later
+    fn try_prepare(
+        &self,
+        message: AudioModuleCommand,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError>;
+
+    /// Try set a tagged parameter, returning a function that sends it later
+    fn try_prepare_param(
+        &self,
+        tag: Tag,
+        value: f64,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError>;
 
     /// Try observe module events while converting module type to api type
     fn try_observe(&self) -> Result<Observer<AudioModuleEvent>, ()>;
@@ -63,6 +106,9 @@ pub trait GeneralMessaging {
 
 pub type GeneralContext = Box<dyn GeneralMessaging + Send>;
 
+/// Sends a prepared command when called.
+pub type Notify = Box<dyn FnOnce() + Send>;
+
 impl<Tx, Rx> GeneralMessaging for ModuleContext<Tx, Rx>
 where
     AudioModuleCommand: TryInto<Tx>,
@@ -70,15 +116,47 @@ where
     Rx: Into<AudioModuleEvent> + Send + Clone + 'static,
 {
     /// Try send command using the module specific command type
-    fn try_notify(&self, message: AudioModuleCommand) -> Result<(), ()> {
+    fn try_notify(&self, message: AudioModuleCommand, ramp: Option<Ramp>) -> Result<(), ()> {
         if let Some(tx) = &self.tx {
-            tx.notify(message.try_into().map_err(|_| ())?);
+            tx.notify((message.try_into().map_err(|_| ())?, ramp));
             Ok(())
         } else {
             Err(())
         }
     }
 
+    /// Try convert the command, returning a function that sends it later
+    fn try_prepare(
+        &self,
+        message: AudioModuleCommand,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError> {
+        if let Some(tx) = &self.tx {
+            let tx = tx.clone();
+            let message = message
+                .try_into()
+                .map_err(|_| MessageError::CommandMismatch)?;
+            Ok(Box::new(move || tx.notify((message, ramp))))
+        } else {
+            Err(MessageError::CommandMismatch)
+        }
+    }
+
+    /// Try set a tagged parameter, returning a function that sends it later
+    fn try_prepare_param(
+        &self,
+        tag: Tag,
+        value: f64,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError> {
+        if let Some(params) = &self.params {
+            let params = params.clone();
+            Ok(Box::new(move || params.notify(((tag, value), ramp))))
+        } else {
+            Err(MessageError::UnknownParam(tag))
+        }
+    }
+
     /// Try observe module events while converting module type to api type
     fn try_observe(&self) -> Result<Observer<AudioModuleEvent>, ()> {
         if let Some(rx) = &self.rx {
@@ -0,0 +1,287 @@
+use std::marker::PhantomData;
+
+use fundsp::prelude::*;
+
+use crate::{
+    interface::midi::MidiMessage,
+    utils::observer::{Observable, Observer, Producer, Subject},
+};
+
+/// Divisions of the beat played by the clock outputs.
+pub const DIVISIONS: [f64; 5] = [1.0, 2.0, 4.0, 8.0, 16.0];
+
+/// MIDI clock messages per beat.
+pub const PPQN: u64 = 24;
+
+/// MIDI clock messages per sixteenth note, the unit of song position.
+const TICKS_PER_SIXTEENTH: u64 = 6;
+
+/// Weight of the latest interval between MIDI clock messages in the estimated interval.
+const SMOOTHING: f64 = 0.25;
+
+#[inline]
+pub fn clock_sync<T, X>(clock: An<X>, follow: bool, send: bool) -> An<ClockSync<T, X>>
+where
+    T: Real,
+    X: AudioNode<Sample = T, Inputs = U1, Outputs = U5>,
+{
+    An(ClockSync::new(clock.0, follow, send))
+}
+
+/// Plays the internal clock, or follows MIDI clock and transport messages instead.
+/// - Input 0: Frequency of the internal beat in Hz, played by `clock`.
+/// - Output 0-4: Square waves at `DIVISIONS` of the beat, low while a followed transport is stopped.
+///
+/// When sending, MIDI clock messages are emitted 24 times per beat of the internal clock.
+/// A clock following MIDI passes on the clock and transport messages it follows instead.
+pub struct ClockSync<T, X> {
+    /// Internal clock, square waves at `DIVISIONS` of the beat
+    clock: X,
+    follow: bool,
+    send: bool,
+    running: bool,
+    /// Tick started by the next MIDI clock message
+    next: u64,
+    /// Tick started by the last MIDI clock message since the transport started
+    current: Option<u64>,
+    /// Samples since the last MIDI clock message
+    elapsed: Option<f64>,
+    /// Estimated samples between MIDI clock messages
+    interval: Option<f64>,
+    /// Ticks of the internal clock since it started
+    phase: f64,
+    /// Ticks of the internal clock sent
+    sent: u64,
+    sample_rate: f64,
+    subject: Subject<MidiMessage>,
+    _marker: PhantomData<T>,
+}
+
+impl<T, X> ClockSync<T, X>
+where
+    T: Real,
+    X: AudioNode<Sample = T, Inputs = U1, Outputs = U5>,
+{
+    pub fn new(clock: X, follow: bool, send: bool) -> Self {
+        Self {
+            clock,
+            follow,
+            send,
+            running: false,
+            next: 0,
+            current: None,
+            elapsed: None,
+            interval: None,
+            phase: 0.0,
+            sent: 0,
+            sample_rate: DEFAULT_SR,
+            subject: Subject::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Handle a clock or transport message, ignored unless following MIDI.
+    pub fn handle(&mut self, message: MidiMessage) {
+        if !self.follow {
+            return;
+        }
+
+        match message {
+            MidiMessage::Clock => {
+                // Clocks resuming after a long pause do not tell the tempo
+                if let Some(elapsed) = self.elapsed.filter(|elapsed| *elapsed < self.sample_rate) {
+                    self.interval = Some(self.interval.map_or(elapsed, |interval| {
+                        interval + (elapsed - interval) * SMOOTHING
+                    }));
+                }
+                self.elapsed = Some(0.0);
+
+                if self.running {
+                    self.current = Some(self.next);
+                    self.next += 1;
+                }
+            }
+            MidiMessage::Start => {
+                self.running = true;
+                self.next = 0;
+                self.current = None;
+            }
+            MidiMessage::Continue => {
+                self.running = true;
+                self.current = None;
+            }
+            MidiMessage::Stop => self.running = false,
+            MidiMessage::SongPosition(position) => {
+                self.next = position as u64 * TICKS_PER_SIXTEENTH;
+                self.current = None;
+            }
+            _ => return,
+        }
+
+        if self.send {
+            self.subject.notify(message);
+        }
+    }
+
+    /// Position in beats of the followed MIDI clock, while its transport runs.
+    fn beat(&self) -> Option<f64> {
+        let tick = self.current.filter(|_| self.running)?;
+        let fraction = match (self.elapsed, self.interval) {
+            (Some(elapsed), Some(interval)) => (elapsed / interval).min(1.0),
+            _ => 0.0,
+        };
+
+        Some((tick as f64 + fraction) / PPQN as f64)
+    }
+
+    /// Gates of one sample, given the beat in Hz and the internal clock.
+    fn gates(&mut self, frequency: T, clock: Frame<T, U5>) -> Frame<T, U5> {
+        let output = if self.follow {
+            let beat = self.beat();
+            Frame::generate(|i| match beat {
+                Some(beat) if (beat * DIVISIONS[i]).fract() < 0.5 => T::one(),
+                _ => -T::one(),
+            })
+        } else {
+            if self.send && self.phase >= self.sent as f64 {
+                self.sent += 1;
+                self.subject.notify(MidiMessage::Clock);
+            }
+            self.phase += frequency.to_f64() * PPQN as f64 / self.sample_rate;
+
+            clock
+        };
+
+        if let Some(elapsed) = self.elapsed.as_mut() {
+            *elapsed += 1.0;
+        }
+
+        output
+    }
+}
+
+impl<T, X> Observable for ClockSync<T, X> {
+    type Output = MidiMessage;
+
+    fn observe(&self) -> Observer<Self::Output> {
+        self.subject.observe()
+    }
+}
+
+impl<T, X> AudioNode for ClockSync<T, X>
+where
+    T: Real,
+    X: AudioNode<Sample = T, Inputs = U1, Outputs = U5>,
+{
+    const ID: u64 = 0;
+    type Sample = T;
+    type Inputs = U1;
+    type Outputs = U5;
+
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        if let Some(sample_rate) = sample_rate {
+            self.sample_rate = sample_rate;
+        }
+        self.clock.reset(sample_rate);
+        self.phase = 0.0;
+        self.sent = 0;
+    }
+
+    /// Hashes as the internal clock alone, which keeps its phases.
+    fn ping(&mut self, probe: bool, hash: AttoRand) -> AttoRand {
+        self.clock.ping(probe, hash)
+    }
+
+    fn tick(
+        &mut self,
+        input: &Frame<Self::Sample, Self::Inputs>,
+    ) -> Frame<Self::Sample, Self::Outputs> {
+        let clock = self.clock.tick(input);
+        self.gates(input[0], clock)
+    }
+
+    fn process(
+        &mut self,
+        size: usize,
+        input: &[&[Self::Sample]],
+        output: &mut [&mut [Self::Sample]],
+    ) {
+        // The internal clock is processed as a block, as when it was played on its own
+        self.clock.process(size, input, output);
+
+        for i in 0..size {
+            let clock = Frame::generate(|channel| output[channel][i]);
+            let gates = self.gates(input[0][i], clock);
+            for (channel, gate) in gates.into_iter().enumerate() {
+                output[channel][i] = gate;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::hacker32::*;
+    use futures::{FutureExt, StreamExt};
+
+    use super::ClockSync;
+    use crate::{interface::midi::MidiMessage, utils::observer::Observable};
+
+    /// Internal clock passing the beat to every division
+    type Sync = ClockSync<f32, Split<U5, f32>>;
+
+    fn gates(unit: &mut Sync) -> Vec<f32> {
+        unit.tick(&Frame::default()).to_vec()
+    }
+
+    #[test]
+    fn test_follow_midi_clock() {
+        let mut unit = Sync::new(Split::new(), true, false);
+
+        // Clock messages ten samples apart, before the transport starts
+        let clock = |unit: &mut Sync| {
+            unit.handle(MidiMessage::Clock);
+            (0..10).map(|_| gates(unit)).collect::<Vec<_>>()
+        };
+        clock(&mut unit);
+        assert!(clock(&mut unit).iter().all(|gates| gates[0] == -1.0));
+
+        // The beat starts on the first clock after starting and lasts 240 samples
+        unit.handle(MidiMessage::Start);
+        let samples = (0..12).flat_map(|_| clock(&mut unit)).collect::<Vec<_>>();
+        assert_eq!(samples[0], [1.0; 5]);
+        assert_eq!(samples[7][4], 1.0);
+        assert_eq!(samples[8][4], -1.0);
+        assert_eq!(samples[15][4], 1.0);
+        assert_eq!(samples[90][2], -1.0);
+
+        unit.handle(MidiMessage::Stop);
+        assert!(clock(&mut unit).iter().all(|gates| gates[0] == -1.0));
+
+        // Continue from the second beat
+        unit.handle(MidiMessage::SongPosition(4));
+        unit.handle(MidiMessage::Continue);
+        assert_eq!(clock(&mut unit)[0], [1.0; 5]);
+        assert_eq!(unit.beat(), Some(25.0 / 24.0));
+    }
+
+    #[test]
+    fn test_send_midi_clock() {
+        let mut unit = Sync::new(Split::new(), false, true);
+        let mut messages = unit.observe();
+        unit.reset(Some(240.0));
+
+        // 10 beats per second at 240 samples per second is one message per sample
+        let input = Frame::from([10.0]);
+        for _ in 0..5 {
+            assert_eq!(unit.tick(&input)[4], 10.0);
+        }
+
+        let mut sent = 0;
+        while let Some(Some(message)) = messages.next().now_or_never() {
+            assert_eq!(message, MidiMessage::Clock);
+            sent += 1;
+        }
+        assert_eq!(sent, 5);
+    }
+}
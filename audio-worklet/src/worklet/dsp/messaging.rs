@@ -1,37 +1,59 @@
-use async_std::{future::ready, task::spawn_local};
 use fundsp::hacker::*;
-use futures::StreamExt;
+use futures::{channel::oneshot, future, FutureExt, StreamExt};
 
 use crate::utils::observer::{Observable, Observer, Subject};
 
-use super::shared::Shared;
+use super::{param::Ramp, shared::Shared};
+
+/// A message for a node, with the ramp its parameter changes follow.
+pub type Message<M> = (M, Option<Ramp>);
+
+/// A change of a tagged parameter, e.g. `(0, 1.0)` sets the parameter tagged 0 to 1.0.
+pub type ParamMessage = Message<(Tag, f64)>;
+
 pub trait MessageHandler<X> {
     /// The message handler provides a means to receive messages incoming
     /// messages. The handler gets a mutable reference to the `AudioNode`
     /// And can mutate it to change the state of the node.
-    fn message_handler<F, M>(self, message_fn: F) -> Subject<M>
+    /// Messages are queued and handled by the audio thread before the node is processed.
+    /// Parameters set by the handler follow the ramp sent along with the message.
+    fn message_handler<F, M>(self, message_fn: F) -> Subject<Message<M>>
     where
         M: Clone + Send + 'static,
         X: AudioNode + 'static,
-        F: Fn(&mut X, M) + 'static;
+        F: Fn(&mut X, M) + Send + 'static;
+
+    /// Handler setting tagged parameters of the node.
+    fn param_handler(self) -> Subject<ParamMessage>
+    where
+        X: AudioNode + 'static,
+        Self: Sized,
+    {
+        self.message_handler(|unit: &mut X, (tag, value)| unit.set(tag, value))
+    }
 }
 
 impl<X> MessageHandler<X> for Shared<X>
 where
     X: AudioNode,
 {
-    fn message_handler<F, M>(self, message_fn: F) -> Subject<M>
+    fn message_handler<F, M>(self, message_fn: F) -> Subject<Message<M>>
     where
         M: Clone + Send + 'static,
         X: AudioNode + 'static,
-        F: Fn(&mut X, M) + 'static,
+        F: Fn(&mut X, M) + Send + 'static,
     {
-        let handler = Subject::new();
+        let handler: Subject<Message<M>> = Subject::new();
+        let mut messages = handler.observe();
 
-        spawn_local(handler.observe().for_each(move |message: M| {
-            let mut unit = self.lock();
-            message_fn(&mut unit, message);
-            ready(())
+        self.add_handler(Box::new(move |unit| {
+            while let Some(Some((message, ramp))) = messages.next().now_or_never() {
+                match ramp {
+                    Some(ramp) => ramp.shape(unit, |unit| message_fn(unit, message)),
+                    None => message_fn(unit, message),
+                }
+            }
+            true
         }));
 
         handler
@@ -40,11 +62,28 @@ where
 
 impl<X> Observable for Shared<X>
 where
-    X: AudioNode + Observable,
+    X: AudioNode + Observable + 'static,
+    X::Output: Send + 'static,
 {
     type Output = X::Output;
 
+    /// The node is observed by the audio thread before it is next processed,
+    /// so observing never waits for processing. Events end when the node is dropped.
     fn observe(&self) -> Observer<Self::Output> {
-        self.lock().observe()
+        let (sender, receiver) = oneshot::channel();
+        let mut sender = Some(sender);
+
+        self.add_handler(Box::new(move |unit| {
+            if let Some(sender) = sender.take() {
+                let _ = sender.send(unit.observe());
+            }
+            false
+        }));
+
+        receiver
+            .into_stream()
+            .filter_map(|observer| future::ready(observer.ok()))
+            .flatten()
+            .boxed()
     }
 }
@@ -1,79 +1,553 @@
-use fundsp::prelude::*;
+use fundsp::{buffer::Buffer, prelude::*};
 use numeric_array::typenum::Prod;
+use serde::{Deserialize, Serialize};
 use std::ops::Mul;
+use ts_rs::TS;
 
 use super::midi_volt;
+use crate::interface::midi::{MidiMessage, ALL_NOTES_OFF, BEND_CENTRE, MOD_WHEEL};
 
-/// Midi adapter.
+/// Number of controllers that can be assigned to outputs of the adapter.
+pub const CONTROLLERS: usize = 4;
+
+/// Number of outputs of the adapter, each carrying one channel per voice.
+pub type Ports = U10;
+
+/// How notes are given to the voices of a `MidiAdapter`.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[ts(export)]
+pub enum Allocation {
+    /// Cycle through the voices, skipping held ones until every voice is held
+    #[default]
+    RoundRobin,
+    /// Take a free voice, or else the voice held the longest
+    StealOldest,
+    /// Take a free voice, or else the held voice played at the lowest velocity
+    StealQuietest,
+    /// Play the lowest held note on the first voice
+    Lowest,
+    /// Play the highest held note on the first voice
+    Highest,
+    /// Play the last held note on the first voice
+    Last,
+}
+
+impl Allocation {
+    /// Monophonic allocations play a single note, picked among the held notes.
+    pub fn is_mono(&self) -> bool {
+        matches!(
+            self,
+            Allocation::Lowest | Allocation::Highest | Allocation::Last
+        )
+    }
+}
+
+/// Midi adapter, playing up to `N` voices.
 /// - Output 0: Gate output.
-/// - Output 1: Pitch output.
-#[derive(Default)]
-pub struct MidiAdapter<N: Size<T> + Size<usize>, T: Real> {
+/// - Output 1: Pitch output, bent by pitch bend.
+/// - Output 2: Velocity output (0-1).
+/// - Output 3: Aftertouch output (0-1), of the note or of the whole channel.
+/// - Output 4: Pitch bend output (-1-1).
+/// - Output 5: Mod wheel output (0-1).
+/// - Output 6-9: Assigned controller outputs (0-1).
+/// - Parameter 0: Range of pitch bend in semitones either way.
+///
+/// A voice changing note while its gate is open closes the gate for one sample,
+/// unless it plays legato.
+pub struct MidiAdapter<N: Size<T>, T: Real> {
+    /// Channel listened to, every channel when None
+    channel: Option<u8>,
+    bend_range: f64,
+    controllers: [u8; CONTROLLERS],
+    /// Voices in use, up to `N`
+    voices: usize,
+    allocation: Allocation,
+    legato: bool,
+    /// Voice tried first by the next note
     next: usize,
-    notes: Frame<T, N>,
-    gates: Frame<T, N>,
+    /// Number of notes played, telling how old each voice is
+    played: u64,
+    /// Held notes with their velocity, in the order they were played
+    held: Vec<(u8, u8)>,
+    /// Last note of each voice, which keeps its pitch after it ends
+    notes: Vec<Option<u8>>,
+    ages: Vec<u64>,
+    gates: Vec<bool>,
+    /// Voices keeping their gate closed for a sample, so notes played right after are heard apart
+    retriggers: Vec<bool>,
+    velocities: Vec<u8>,
+    pressures: Vec<u8>,
+    bend: T,
+    mod_wheel: T,
+    values: [T; CONTROLLERS],
+    _marker: std::marker::PhantomData<N>,
 }
 
-impl<N: Size<T> + Size<usize>, T: Real> MidiAdapter<N, T> {
-    pub fn new() -> Self {
+/// Level of a 7 bit value between 0 and 1.
+fn level<T: Real>(value: u8) -> T {
+    T::from_f64(value as f64 / 127.0)
+}
+
+impl<N: Size<T>, T: Real> MidiAdapter<N, T> {
+    pub fn new(
+        channel: Option<u8>,
+        bend_range: f64,
+        controllers: [u8; CONTROLLERS],
+        voices: usize,
+        allocation: Allocation,
+        legato: bool,
+    ) -> Self {
         Self {
+            channel,
+            bend_range,
+            controllers,
+            voices: voices.clamp(1, N::USIZE),
+            allocation,
+            legato,
             next: 0,
-            notes: Frame::splat(T::zero()),
-            gates: Frame::splat(T::zero()),
+            played: 0,
+            held: Vec::with_capacity(128),
+            notes: vec![None; N::USIZE],
+            ages: vec![0; N::USIZE],
+            gates: vec![false; N::USIZE],
+            retriggers: vec![false; N::USIZE],
+            velocities: vec![0; N::USIZE],
+            pressures: vec![0; N::USIZE],
+            bend: T::zero(),
+            mod_wheel: T::zero(),
+            values: [T::zero(); CONTROLLERS],
+            _marker: std::marker::PhantomData,
         }
     }
-    pub fn note_on(&mut self, note: u8) {
-        // @todo new notes should not effect already open ones
-        self.notes[self.next] = midi_volt(note);
-        self.gates[self.next] = T::one();
 
-        if self.next >= N::USIZE - 1 {
-            self.next = 0;
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        self.held.retain(|(held, _)| *held != note);
+        self.held.push((note, velocity));
+
+        if self.allocation.is_mono() {
+            self.play_held();
         } else {
-            self.next += 1;
+            let voice = self.allocate();
+            self.next = (voice + 1) % self.voices;
+            self.play(voice, note, velocity);
         }
     }
 
     pub fn note_off(&mut self, note: u8) {
-        if let Some(pos) = self.notes.iter().position(|n| *n == midi_volt(note)) {
-            self.gates[pos] = T::zero();
-            // self.notes[pos] = T::zero(); // @todo maybe note should hang on longer after gate
+        self.held.retain(|(held, _)| *held != note);
+
+        if self.allocation.is_mono() {
+            self.play_held();
+        } else {
+            for voice in 0..self.voices {
+                if self.notes[voice] == Some(note) {
+                    self.gates[voice] = false;
+                }
+            }
+        }
+    }
+
+    /// Close the gates of every voice, for at least a sample.
+    pub fn release(&mut self) {
+        self.held.clear();
+        for voice in 0..self.voices {
+            if self.gates[voice] {
+                self.gates[voice] = false;
+                self.retriggers[voice] = true;
+            }
+        }
+    }
+
+    /// Voice playing the next note.
+    fn allocate(&self) -> usize {
+        let free = (0..self.voices)
+            .map(|i| (self.next + i) % self.voices)
+            .find(|&voice| !self.gates[voice]);
+        let voices = 0..self.voices;
+
+        match (free, self.allocation) {
+            (Some(voice), _) => Some(voice),
+            (None, Allocation::StealOldest) => voices.min_by_key(|&voice| self.ages[voice]),
+            (None, Allocation::StealQuietest) => {
+                voices.min_by_key(|&voice| (self.velocities[voice], self.ages[voice]))
+            }
+            (None, _) => Some(self.next),
+        }
+        .unwrap_or(0)
+    }
+
+    /// Play the held note picked by a monophonic allocation on the first voice.
+    fn play_held(&mut self) {
+        let picked = match self.allocation {
+            Allocation::Lowest => self.held.iter().min_by_key(|(note, _)| *note),
+            Allocation::Highest => self.held.iter().max_by_key(|(note, _)| *note),
+            _ => self.held.last(),
+        };
+
+        match picked.copied() {
+            Some((note, _)) if self.gates[0] && self.notes[0] == Some(note) => {}
+            Some((note, velocity)) => self.play(0, note, velocity),
+            None => self.gates[0] = false,
+        }
+    }
+
+    fn play(&mut self, voice: usize, note: u8, velocity: u8) {
+        if self.gates[voice] && !self.legato {
+            self.retriggers[voice] = true;
+        }
+
+        self.played += 1;
+        self.notes[voice] = Some(note);
+        self.ages[voice] = self.played;
+        self.gates[voice] = true;
+        self.velocities[voice] = velocity;
+        self.pressures[voice] = 0;
+    }
+
+    /// Handle a message on the channel listened to.
+    pub fn handle(&mut self, message: MidiMessage) {
+        if let (Some(channel), Some(listened)) = (message.channel(), self.channel) {
+            if channel != listened {
+                return;
+            }
+        }
+
+        match message {
+            MidiMessage::NoteOn { note, velocity, .. } => self.note_on(note, velocity),
+            MidiMessage::NoteOff { note, .. } => self.note_off(note),
+            MidiMessage::PolyAftertouch { note, pressure, .. } => {
+                for voice in 0..self.voices {
+                    if self.notes[voice] == Some(note) {
+                        self.pressures[voice] = pressure;
+                    }
+                }
+            }
+            MidiMessage::ChannelAftertouch { pressure, .. } => self.pressures.fill(pressure),
+            MidiMessage::ControlChange {
+                controller: ALL_NOTES_OFF,
+                ..
+            } => self.release(),
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => {
+                if controller == MOD_WHEEL {
+                    self.mod_wheel = level(value);
+                }
+                for (assigned, output) in self.controllers.iter().zip(self.values.iter_mut()) {
+                    if *assigned == controller {
+                        *output = level(value);
+                    }
+                }
+            }
+            MidiMessage::PitchBend { value, .. } => {
+                // The upper half is one step shorter, so both ends bend all the way
+                let offset = value as f64 - BEND_CENTRE as f64;
+                let half = if offset > 0.0 {
+                    BEND_CENTRE - 1
+                } else {
+                    BEND_CENTRE
+                };
+                self.bend = T::from_f64((offset / half as f64).clamp(-1.0, 1.0));
+            }
+            _ => {}
         }
     }
+
+    fn pitch(&self, voice: usize) -> T {
+        let note = self.notes[voice].map_or(T::zero(), midi_volt);
+        note + self.bend * T::from_f64(self.bend_range / 12.0)
+    }
 }
 
-impl<N: Size<T> + Size<usize>, T: Real> AudioNode for MidiAdapter<N, T>
+impl<N: Size<T>, T: Real> AudioNode for MidiAdapter<N, T>
 where
-    N: Mul<U2>,
-    <N as Mul<U2>>::Output: Size<T>,
+    N: Mul<Ports>,
+    <N as Mul<Ports>>::Output: Size<T>,
 {
     const ID: u64 = 0;
     type Sample = T;
     type Inputs = U0;
-    type Outputs = Prod<N, U2>;
+    type Outputs = Prod<N, Ports>;
 
     fn tick(
         &mut self,
         _input: &Frame<Self::Sample, Self::Inputs>,
     ) -> Frame<Self::Sample, Self::Outputs> {
-        Frame::generate(|i| {
-            if i < N::USIZE {
-                self.gates[i]
-            } else {
-                self.notes[i - N::USIZE]
+        let output = Frame::generate(|i| {
+            let voice = i % N::USIZE;
+            match i / N::USIZE {
+                0 if self.gates[voice] && !self.retriggers[voice] => T::one(),
+                0 => T::zero(),
+                1 => self.pitch(voice),
+                2 => level(self.velocities[voice]),
+                3 => level(self.pressures[voice]),
+                4 => self.bend,
+                5 => self.mod_wheel,
+                port => self.values[port - 6],
             }
-        }) // @todo maybe there is a way to concatenate the two frames?
+        });
+
+        self.retriggers.fill(false);
+        output
+    }
+
+    fn set(&mut self, parameter: Tag, value: f64) {
+        if parameter == 0 {
+            self.bend_range = value;
+        }
+    }
+
+    fn get(&self, parameter: Tag) -> Option<f64> {
+        if parameter == 0 {
+            Some(self.bend_range)
+        } else {
+            None
+        }
     }
 }
 
-/// Midi adapter to gate and pitch (polyphonic)
+/// Midi adapter to gate, pitch and controls (polyphonic), see `MidiAdapter`
 /// - Output 0-N: Gate outputs
 /// - Output N-2N: Pitch outputs
+/// - Output 2N-10N: Velocity, aftertouch, pitch bend, mod wheel and controller outputs
 #[inline]
-pub fn midi_poly<N: Size<T> + Size<usize>, T: Real>() -> An<MidiAdapter<N, T>>
+pub fn midi_poly<N, T: Real>(
+    channel: Option<u8>,
+    bend_range: f64,
+    controllers: [u8; CONTROLLERS],
+    voices: usize,
+    allocation: Allocation,
+    legato: bool,
+) -> An<MidiAdapter<N, T>>
 where
-    N: Mul<U2>,
-    <N as Mul<U2>>::Output: Size<T>,
+    N: Size<T> + Mul<Ports>,
+    <N as Mul<Ports>>::Output: Size<T>,
 {
-    An(MidiAdapter::new())
+    An(MidiAdapter::new(
+        channel,
+        bend_range,
+        controllers,
+        voices,
+        allocation,
+        legato,
+    ))
+}
+
+/// Keeps the first `voices` channels of each port of a unit with `max` channels per port,
+/// so a unit sized for the most voices can play fewer.
+pub struct VoiceSelect<X: AudioUnit32> {
+    unit: X,
+    max: usize,
+    voices: usize,
+    buffer: Buffer<f32>,
+    frame: Vec<f32>,
+}
+
+impl<X: AudioUnit32> VoiceSelect<X> {
+    pub fn new(unit: X, max: usize, voices: usize) -> Self {
+        let outputs = unit.outputs();
+        Self {
+            unit,
+            max,
+            voices,
+            buffer: Buffer::with_size(outputs),
+            frame: vec![0.0; outputs],
+        }
+    }
+
+    fn ports(&self) -> usize {
+        self.unit.outputs() / self.max
+    }
+}
+
+impl<X: AudioUnit32> AudioUnit32 for VoiceSelect<X> {
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        self.unit.reset(sample_rate);
+    }
+
+    fn tick(&mut self, input: &[f32], output: &mut [f32]) {
+        self.unit.tick(input, &mut self.frame);
+
+        for port in 0..self.ports() {
+            for voice in 0..self.voices {
+                output[port * self.voices + voice] = self.frame[port * self.max + voice];
+            }
+        }
+    }
+
+    fn process(&mut self, size: usize, input: &[&[f32]], output: &mut [&mut [f32]]) {
+        let outputs = self.unit.outputs();
+        self.unit.process(size, input, self.buffer.get_mut(outputs));
+
+        for port in 0..self.ports() {
+            for voice in 0..self.voices {
+                output[port * self.voices + voice][..size]
+                    .copy_from_slice(&self.buffer.at(port * self.max + voice)[..size]);
+            }
+        }
+    }
+
+    fn inputs(&self) -> usize {
+        self.unit.inputs()
+    }
+
+    fn outputs(&self) -> usize {
+        self.ports() * self.voices
+    }
+
+    fn route(&self, input: &SignalFrame, frequency: f64) -> SignalFrame {
+        let routed = self.unit.route(input, frequency);
+        let mut output = new_signal_frame(self.outputs());
+
+        for port in 0..self.ports() {
+            for voice in 0..self.voices {
+                output[port * self.voices + voice] = routed[port * self.max + voice];
+            }
+        }
+
+        output
+    }
+
+    fn set(&mut self, parameter: Tag, value: f64) {
+        self.unit.set(parameter, value);
+    }
+
+    fn get(&self, parameter: Tag) -> Option<f64> {
+        self.unit.get(parameter)
+    }
+
+    fn get_id(&self) -> u64 {
+        self.unit.get_id()
+    }
+
+    fn ping(&mut self, probe: bool, hash: AttoRand) -> AttoRand {
+        self.unit.ping(probe, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::hacker32::*;
+
+    use super::{Allocation, MidiAdapter, VoiceSelect};
+    use crate::dsp::shared::Share;
+    use crate::interface::midi::MidiMessage;
+
+    #[test]
+    fn test_midi_adapter_outputs() {
+        let mut unit = MidiAdapter::<U1, f32>::new(
+            Some(1),
+            2.0,
+            [74, 71, 7, 11],
+            1,
+            Allocation::RoundRobin,
+            false,
+        );
+        let parse = |bytes: &[u8]| MidiMessage::parse(bytes).unwrap();
+
+        unit.handle(parse(&[0x91, 60, 127]));
+        unit.handle(parse(&[0xd1, 127]));
+        unit.handle(parse(&[0xb1, 1, 127]));
+        unit.handle(parse(&[0xb1, 7, 127]));
+        // Bend all the way up, a whole tone
+        unit.handle(parse(&[0xe1, 0x7f, 0x7f]));
+        let output = unit.tick(&Frame::default());
+        assert_eq!(output[0], 1.0);
+        assert!((output[1] - 62.0 / 12.0).abs() < 1e-3);
+        assert_eq!(&output[2..6], &[1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(&output[6..], &[0.0, 0.0, 1.0, 0.0]);
+
+        // Other channels are ignored
+        unit.handle(parse(&[0x80, 60, 0]));
+        assert_eq!(unit.tick(&Frame::default())[0], 1.0);
+        unit.handle(parse(&[0x81, 60, 0]));
+        assert_eq!(unit.tick(&Frame::default())[0], 0.0);
+    }
+
+    fn adapter(voices: usize, allocation: Allocation, legato: bool) -> MidiAdapter<U4, f32> {
+        MidiAdapter::new(None, 2.0, [74, 71, 7, 11], voices, allocation, legato)
+    }
+
+    /// Notes of the voices with an open gate, `None` for the others.
+    fn playing(unit: &mut MidiAdapter<U4, f32>) -> Vec<Option<u8>> {
+        let output = unit.tick(&Frame::default());
+        (0..4)
+            .map(|voice| (output[voice] > 0.0).then(|| (output[4 + voice] * 12.0).round() as u8))
+            .collect()
+    }
+
+    #[test]
+    fn test_polyphonic_allocation() {
+        // Round robin skips the held voice
+        let mut unit = adapter(3, Allocation::RoundRobin, false);
+        unit.note_on(60, 100);
+        unit.note_on(62, 100);
+        unit.note_off(60);
+        unit.note_on(64, 100);
+        unit.note_on(65, 100);
+        assert_eq!(playing(&mut unit), [Some(65), Some(62), Some(64), None]);
+
+        // Steal the oldest voice when every voice is held
+        let mut unit = adapter(2, Allocation::StealOldest, false);
+        unit.note_on(60, 100);
+        unit.note_on(62, 100);
+        unit.note_on(64, 100);
+        assert_eq!(playing(&mut unit), [None, Some(62), None, None]);
+        assert_eq!(playing(&mut unit), [Some(64), Some(62), None, None]);
+
+        // Steal the quietest voice, playing legato without closing its gate
+        let mut unit = adapter(2, Allocation::StealQuietest, true);
+        unit.note_on(60, 20);
+        unit.note_on(62, 100);
+        unit.note_on(64, 100);
+        assert_eq!(playing(&mut unit), [Some(64), Some(62), None, None]);
+    }
+
+    #[test]
+    fn test_monophonic_priority() {
+        for (allocation, played) in [
+            (Allocation::Lowest, [60, 60, 60]),
+            (Allocation::Highest, [60, 67, 67]),
+            (Allocation::Last, [60, 67, 64]),
+        ] {
+            let mut unit = adapter(4, allocation, true);
+            unit.note_on(60, 100);
+            assert_eq!(playing(&mut unit)[0], Some(played[0]));
+            unit.note_on(67, 100);
+            assert_eq!(playing(&mut unit)[0], Some(played[1]));
+            unit.note_on(64, 100);
+            assert_eq!(playing(&mut unit)[0], Some(played[2]));
+            assert_eq!(&playing(&mut unit)[1..], [None, None, None]);
+        }
+
+        // Releasing the playing note returns to the held one, retriggering its gate
+        let mut unit = adapter(1, Allocation::Last, false);
+        unit.note_on(60, 100);
+        unit.note_on(64, 100);
+        playing(&mut unit);
+        unit.note_off(64);
+        assert_eq!(playing(&mut unit)[0], None);
+        assert_eq!(playing(&mut unit)[0], Some(60));
+        unit.note_off(60);
+        assert_eq!(playing(&mut unit)[0], None);
+    }
+
+    #[test]
+    fn test_voice_select() {
+        let notes = An(adapter(2, Allocation::RoundRobin, false)).share();
+        let mut unit = VoiceSelect::new(An(notes.0.clone()), 4, 2);
+        assert_eq!(unit.outputs(), 20);
+
+        notes.0.lock().unit.note_on(60, 100);
+        notes.0.lock().unit.note_on(72, 100);
+        let mut output = vec![0.0; 20];
+        unit.tick(&[], &mut output);
+        assert_eq!(&output[0..4], &[1.0, 1.0, 5.0, 6.0]);
+
+        let mut buffers = vec![vec![0.0; 8]; 20];
+        let mut buffers = buffers.iter_mut().map(|b| &mut b[..]).collect::<Vec<_>>();
+        unit.process(8, &[], &mut buffers);
+        assert!(buffers[3].iter().all(|&x| x == 6.0));
+        assert!(buffers[19].iter().all(|&x| x == 0.0));
+    }
 }
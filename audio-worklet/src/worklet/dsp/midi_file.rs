@@ -0,0 +1,385 @@
+use std::ops::Mul;
+
+use fundsp::prelude::*;
+use numeric_array::typenum::Prod;
+
+use super::{
+    midi::{Allocation, MidiAdapter, Ports},
+    trigger::SchmittTrigger,
+};
+use crate::interface::smf::{MidiFile, TrackEvent, DEFAULT_TEMPO};
+
+/// Weight of the latest interval between clock pulses in the estimated interval.
+const SMOOTHING: f64 = 0.25;
+
+/// Outputs of the player, the gate, pitch and velocity outputs of `MidiAdapter`.
+pub type PlayerPorts = U3;
+
+/// Events of every track of a file, merged before they are sent to the audio thread.
+/// Playing other tracks only filters the events, so nothing is sorted while playing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Playlist {
+    /// Ticks per quarter note
+    division: u16,
+    /// Events with the index of their track, sorted by time
+    events: Vec<(u64, usize, TrackEvent)>,
+    /// Time of the end of the file in ticks
+    end: u64,
+}
+
+impl From<&MidiFile> for Playlist {
+    fn from(file: &MidiFile) -> Self {
+        let mut events = file
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(index, track)| {
+                track
+                    .events
+                    .iter()
+                    .map(move |&(time, event)| (time, index, event))
+            })
+            .collect::<Vec<_>>();
+        // Sorting is stable, so events at the same time keep the order of their tracks
+        events.sort_by_key(|(time, _, _)| *time);
+
+        Self {
+            division: file.division,
+            events,
+            end: file.tracks.iter().map(|track| track.end).max().unwrap_or(0),
+        }
+    }
+}
+
+#[inline]
+pub fn midi_file_player<N, T: Real>(
+    voices: usize,
+    allocation: Allocation,
+    clock_division: Option<u32>,
+    looping: bool,
+) -> An<MidiFilePlayer<N, T>>
+where
+    N: Size<T> + Mul<Ports> + Mul<PlayerPorts>,
+    <N as Mul<Ports>>::Output: Size<T>,
+    <N as Mul<PlayerPorts>>::Output: Size<T>,
+{
+    An(MidiFilePlayer::new(
+        voices,
+        allocation,
+        clock_division,
+        looping,
+    ))
+}
+
+/// Plays tracks of a Standard MIDI File on up to `N` voices.
+/// - Input 0: Clock input, followed when playing by pulses.
+/// - Input 1: Reset input, going back to the start of the file.
+/// - Output 0: Gate output.
+/// - Output 1: Pitch output.
+/// - Output 2: Velocity output (0-1).
+///
+/// Outputs carry one channel per voice, like `MidiAdapter`.
+pub struct MidiFilePlayer<N: Size<T>, T: Real> {
+    adapter: MidiAdapter<N, T>,
+    playlist: Option<Playlist>,
+    /// Tracks played, every track when empty. Tempo changes of every track are followed
+    tracks: Vec<usize>,
+    /// Index of the next event to play
+    next: usize,
+    /// Time played in ticks
+    position: f64,
+    /// Microseconds per quarter note
+    tempo: u32,
+    /// Clock pulses per quarter note, playing at the tempo of the file when None
+    clock_division: Option<u32>,
+    /// Time of the last clock pulse in ticks, None before the first pulse
+    pulse: Option<f64>,
+    /// Samples since the last clock pulse
+    elapsed: Option<f64>,
+    /// Estimated samples between clock pulses
+    interval: Option<f64>,
+    looping: bool,
+    clock: SchmittTrigger,
+    reset: SchmittTrigger,
+    sample_rate: f64,
+}
+
+impl<N: Size<T>, T: Real> MidiFilePlayer<N, T> {
+    pub fn new(
+        voices: usize,
+        allocation: Allocation,
+        clock_division: Option<u32>,
+        looping: bool,
+    ) -> Self {
+        Self {
+            adapter: MidiAdapter::new(None, 2.0, [0; 4], voices, allocation, false),
+            playlist: None,
+            tracks: vec![],
+            next: 0,
+            position: 0.0,
+            tempo: DEFAULT_TEMPO,
+            clock_division,
+            pulse: None,
+            elapsed: None,
+            interval: None,
+            looping,
+            clock: SchmittTrigger::default(),
+            reset: SchmittTrigger::default(),
+            sample_rate: DEFAULT_SR,
+        }
+    }
+
+    /// Play a file from the start.
+    pub fn load(&mut self, playlist: Playlist) {
+        self.playlist = Some(playlist);
+        self.restart();
+    }
+
+    /// Play other tracks of the file from the start.
+    pub fn select(&mut self, tracks: Vec<usize>) {
+        self.tracks = tracks;
+        self.restart();
+    }
+
+    /// Go back to the start of the file, waiting for the next pulse when following a clock.
+    pub fn restart(&mut self) {
+        self.next = 0;
+        self.position = 0.0;
+        self.tempo = DEFAULT_TEMPO;
+        self.pulse = None;
+        self.adapter.release();
+    }
+
+    /// Ticks played per sample at the tempo of the file.
+    fn ticks_per_sample(&self) -> f64 {
+        let division = self
+            .playlist
+            .as_ref()
+            .map_or(0, |playlist| playlist.division);
+        division as f64 * 1_000_000.0 / self.tempo as f64 / self.sample_rate
+    }
+
+    /// Time played when following clock pulses, between the last pulse and the next one.
+    fn pulse_position(&self, clock_division: u32) -> Option<f64> {
+        let division = self.playlist.as_ref()?.division as f64;
+        let fraction = match (self.elapsed, self.interval) {
+            (Some(elapsed), Some(interval)) => (elapsed / interval).min(1.0),
+            _ => 0.0,
+        };
+
+        Some(self.pulse? + fraction * division / clock_division as f64)
+    }
+
+    /// Play the events up to the time played, going back to the start at the end of the file.
+    fn play(&mut self) {
+        self.play_events();
+
+        let end = self.playlist.as_ref().map_or(0, |playlist| playlist.end);
+        if self.looping && end > 0 && self.position >= end as f64 {
+            let end = end as f64;
+            self.position -= end;
+            self.pulse = self.pulse.map(|pulse| pulse - end);
+            self.next = 0;
+            self.tempo = DEFAULT_TEMPO;
+            self.adapter.release();
+            self.play_events();
+        }
+    }
+
+    fn play_events(&mut self) {
+        let events = match &self.playlist {
+            Some(playlist) => &playlist.events,
+            None => return,
+        };
+
+        while let Some(&(time, track, event)) = events.get(self.next) {
+            if time as f64 > self.position {
+                break;
+            }
+
+            match event {
+                TrackEvent::Message(message) => {
+                    if self.tracks.is_empty() || self.tracks.contains(&track) {
+                        self.adapter.handle(message)
+                    }
+                }
+                TrackEvent::Tempo(tempo) => self.tempo = tempo,
+            }
+            self.next += 1;
+        }
+    }
+}
+
+impl<N: Size<T>, T: Real> AudioNode for MidiFilePlayer<N, T>
+where
+    N: Mul<Ports> + Mul<PlayerPorts>,
+    <N as Mul<Ports>>::Output: Size<T>,
+    <N as Mul<PlayerPorts>>::Output: Size<T>,
+{
+    const ID: u64 = 0;
+    type Sample = T;
+    type Inputs = U2;
+    type Outputs = Prod<N, PlayerPorts>;
+
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        if let Some(sample_rate) = sample_rate {
+            self.sample_rate = sample_rate;
+        }
+        self.restart();
+    }
+
+    fn tick(
+        &mut self,
+        input: &Frame<Self::Sample, Self::Inputs>,
+    ) -> Frame<Self::Sample, Self::Outputs> {
+        if self.reset.tick(input[1], 0.0, 0.001) == Some(true) {
+            self.restart();
+        }
+
+        match self.clock_division {
+            Some(clock_division) => {
+                if self.clock.tick(input[0], 0.0, 0.001) == Some(true) {
+                    // Pulses after a long pause do not tell the tempo
+                    if let Some(elapsed) =
+                        self.elapsed.filter(|elapsed| *elapsed < self.sample_rate)
+                    {
+                        self.interval = Some(self.interval.map_or(elapsed, |interval| {
+                            interval + (elapsed - interval) * SMOOTHING
+                        }));
+                    }
+                    self.elapsed = Some(0.0);
+
+                    let division = self
+                        .playlist
+                        .as_ref()
+                        .map_or(0, |playlist| playlist.division);
+                    self.pulse = Some(
+                        self.pulse
+                            .map_or(0.0, |pulse| pulse + division as f64 / clock_division as f64),
+                    );
+                }
+
+                if let Some(position) = self.pulse_position(clock_division) {
+                    self.position = self.position.max(position);
+                    self.play();
+                }
+
+                if let Some(elapsed) = self.elapsed.as_mut() {
+                    *elapsed += 1.0;
+                }
+            }
+            None => {
+                self.play();
+                self.position += self.ticks_per_sample();
+            }
+        }
+
+        let output = self.adapter.tick(&Frame::default());
+        Frame::generate(|i| output[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::hacker32::*;
+
+    use super::{MidiFilePlayer, Playlist};
+    use crate::{
+        dsp::midi::Allocation,
+        interface::{
+            midi::MidiMessage,
+            smf::{MidiFile, Track, TrackEvent},
+        },
+    };
+
+    fn note(time: u64, note: u8, on: bool) -> (u64, TrackEvent) {
+        let message = match on {
+            true => MidiMessage::NoteOn {
+                channel: 0,
+                note,
+                velocity: 127,
+            },
+            false => MidiMessage::NoteOff {
+                channel: 0,
+                note,
+                velocity: 0,
+            },
+        };
+        (time, TrackEvent::Message(message))
+    }
+
+    /// Two tracks of a beat each, the second at double tempo.
+    fn file() -> MidiFile {
+        MidiFile {
+            division: 4,
+            tracks: vec![
+                Track {
+                    events: vec![(4, TrackEvent::Tempo(250_000))],
+                    end: 8,
+                },
+                Track {
+                    events: vec![note(0, 60, true), note(4, 60, false), note(6, 72, true)],
+                    end: 8,
+                },
+            ],
+        }
+    }
+
+    fn gate_and_pitch(unit: &mut MidiFilePlayer<U2, f32>, input: [f32; 2]) -> (f32, f32) {
+        let output = unit.tick(&Frame::from(input));
+        (output[0], output[2])
+    }
+
+    #[test]
+    fn test_play_at_file_tempo() {
+        let mut unit = MidiFilePlayer::<U2, f32>::new(1, Allocation::RoundRobin, None, true);
+        unit.reset(Some(8.0));
+        unit.load(Playlist::from(&file()));
+
+        // A beat lasts 4 samples at 120 BPM, then 2 samples
+        let played = (0..12)
+            .map(|_| gate_and_pitch(&mut unit, [0.0, 0.0]))
+            .collect::<Vec<_>>();
+        assert_eq!(played[0], (1.0, 5.0));
+        assert_eq!(played[3], (1.0, 5.0));
+        assert_eq!(played[4], (0.0, 5.0));
+        assert_eq!(played[5], (1.0, 6.0));
+        // Back to the start, closing the gate for a sample between the notes
+        assert_eq!(played[6], (0.0, 5.0));
+        assert_eq!(played[7], (1.0, 5.0));
+        assert_eq!(played[9], (1.0, 5.0));
+        assert_eq!(played[10], (0.0, 5.0));
+
+        // Only the tempo track is left
+        unit.select(vec![0]);
+        assert!((0..32).all(|_| gate_and_pitch(&mut unit, [0.0, 0.0]).0 == 0.0));
+    }
+
+    #[test]
+    fn test_follow_clock_pulses() {
+        let mut unit = MidiFilePlayer::<U2, f32>::new(1, Allocation::RoundRobin, Some(2), false);
+        unit.load(Playlist::from(&file()));
+
+        // Nothing plays before the first pulse
+        assert_eq!(gate_and_pitch(&mut unit, [0.0, 0.0]), (0.0, 0.0));
+
+        // Two pulses per beat, four samples apart
+        let mut played = vec![];
+        for _ in 0..5 {
+            played.push(gate_and_pitch(&mut unit, [1.0, 0.0]));
+            for _ in 0..3 {
+                played.push(gate_and_pitch(&mut unit, [0.0, 0.0]));
+            }
+        }
+        assert_eq!(played[0], (1.0, 5.0));
+        assert_eq!(played[7], (1.0, 5.0));
+        assert_eq!(played[8], (0.0, 5.0));
+        assert_eq!(played[11], (0.0, 5.0));
+        assert_eq!(played[12], (1.0, 6.0));
+        assert_eq!(played[19], (1.0, 6.0));
+
+        // Resetting waits for the next pulse to play from the start
+        assert_eq!(gate_and_pitch(&mut unit, [0.0, 1.0]), (0.0, 6.0));
+        assert_eq!(gate_and_pitch(&mut unit, [1.0, 0.0]), (1.0, 5.0));
+    }
+}
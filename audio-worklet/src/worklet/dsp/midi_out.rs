@@ -0,0 +1,216 @@
+use std::marker::PhantomData;
+
+use fundsp::prelude::*;
+
+use super::{midi::CONTROLLERS, midi_volt, trigger::SchmittTrigger, volt_midi};
+use crate::{
+    interface::midi::MidiMessage,
+    utils::observer::{Observable, Observer, Producer, Subject},
+};
+
+/// Semitones the pitch moves past the middle of two notes before a held note changes,
+/// so pitches close to the middle do not flip between them.
+const HYSTERESIS: f64 = 0.1;
+
+#[inline]
+pub fn midi_out<T: Real>(channel: u8, controllers: [Option<u8>; CONTROLLERS]) -> An<MidiOut<T>> {
+    An(MidiOut::new(channel, controllers))
+}
+
+/// 7 bit value of a level between 0 and 1.
+fn value<T: Real>(level: T) -> u8 {
+    (level.to_f64().clamp(0.0, 1.0) * 127.0).round() as u8
+}
+
+/// Turns gate, pitch and controller CV into MIDI messages, sent to its observers.
+/// - Input 0: Gate input, playing a note while open.
+/// - Input 1: Pitch input (1v per octave), quantised to the nearest note.
+/// - Input 2: Velocity input (0-1), read when the gate opens.
+/// - Input 3-6: Controller inputs (0-1).
+/// - Output 0: Pitch of the last note played.
+///
+/// A held note changes when the pitch moves to another note, without waiting for the gate.
+pub struct MidiOut<T> {
+    channel: u8,
+    controllers: [Option<u8>; CONTROLLERS],
+    gate: SchmittTrigger,
+    /// Note held, which is turned off when the gate closes
+    playing: Option<u8>,
+    /// Last note played
+    note: u8,
+    /// Last value sent by each controller
+    values: [Option<u8>; CONTROLLERS],
+    subject: Subject<MidiMessage>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Real> MidiOut<T> {
+    pub fn new(channel: u8, controllers: [Option<u8>; CONTROLLERS]) -> Self {
+        Self {
+            channel: Ord::min(channel, 15),
+            controllers,
+            gate: SchmittTrigger::default(),
+            playing: None,
+            note: 0,
+            values: [None; CONTROLLERS],
+            subject: Subject::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn note_on(&mut self, note: u8, velocity: T) {
+        self.playing = Some(note);
+        self.note = note;
+        self.subject.notify(MidiMessage::NoteOn {
+            channel: self.channel,
+            note,
+            // Notes turned on with no velocity are turned off
+            velocity: Ord::max(value(velocity), 1),
+        });
+    }
+
+    fn note_off(&mut self) {
+        if let Some(note) = self.playing.take() {
+            self.subject.notify(MidiMessage::NoteOff {
+                channel: self.channel,
+                note,
+                velocity: 0,
+            });
+        }
+    }
+}
+
+impl<T: Real> Observable for MidiOut<T> {
+    type Output = MidiMessage;
+
+    fn observe(&self) -> Observer<Self::Output> {
+        self.subject.observe()
+    }
+}
+
+impl<T: Real> AudioNode for MidiOut<T> {
+    const ID: u64 = 0;
+    type Sample = T;
+    type Inputs = U7;
+    type Outputs = U1;
+
+    fn reset(&mut self, _sample_rate: Option<f64>) {
+        self.note_off();
+        self.gate = SchmittTrigger::default();
+        self.values = [None; CONTROLLERS];
+    }
+
+    fn tick(
+        &mut self,
+        input: &Frame<Self::Sample, Self::Inputs>,
+    ) -> Frame<Self::Sample, Self::Outputs> {
+        match self.gate.tick(input[0], 0.0, 0.001) {
+            Some(true) => self.note_on(volt_midi(input[1]), input[2]),
+            Some(false) => self.note_off(),
+            None => {
+                if let Some(note) = self.playing {
+                    let distance = (input[1].to_f64() * 12.0 - note as f64).abs();
+                    if distance > 0.5 + HYSTERESIS {
+                        self.note_off();
+                        self.note_on(volt_midi(input[1]), input[2]);
+                    }
+                }
+            }
+        }
+
+        for (i, &controller) in self.controllers.iter().enumerate() {
+            let Some(controller) = controller else {
+                continue;
+            };
+
+            let level = value(input[3 + i]);
+            if self.values[i] != Some(level) {
+                self.values[i] = Some(level);
+                self.subject.notify(MidiMessage::ControlChange {
+                    channel: self.channel,
+                    controller,
+                    value: level,
+                });
+            }
+        }
+
+        [midi_volt(self.note)].into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::hacker32::*;
+    use futures::{FutureExt, StreamExt};
+
+    use super::MidiOut;
+    use crate::{
+        interface::midi::MidiMessage,
+        utils::observer::{Observable, Observer},
+    };
+
+    fn sent(messages: &mut Observer<MidiMessage>) -> Vec<MidiMessage> {
+        let mut sent = vec![];
+        while let Some(Some(message)) = messages.next().now_or_never() {
+            sent.push(message);
+        }
+        sent
+    }
+
+    fn input(gate: f32, pitch: f32, velocity: f32, cc: f32) -> Frame<f32, U7> {
+        Frame::from([gate, pitch, velocity, cc, 0.0, 0.0, 0.0])
+    }
+
+    #[test]
+    fn test_send_notes() {
+        let mut unit = MidiOut::<f32>::new(2, [None; 4]);
+        let mut messages = unit.observe();
+        let note_on = |note, velocity| MidiMessage::NoteOn {
+            channel: 2,
+            note,
+            velocity,
+        };
+        let note_off = |note| MidiMessage::NoteOff {
+            channel: 2,
+            note,
+            velocity: 0,
+        };
+
+        // Pitch is quantised to the nearest note when the gate opens
+        assert_eq!(unit.tick(&input(0.0, 5.0, 0.5, 0.0))[0], 0.0);
+        assert_eq!(unit.tick(&input(1.0, 5.02, 0.5, 0.0))[0], 5.0);
+        assert_eq!(sent(&mut messages), [note_on(60, 64)]);
+
+        // Held notes change past the middle of two notes
+        unit.tick(&input(1.0, 5.0 + 0.55 / 12.0, 1.0, 0.0));
+        assert_eq!(sent(&mut messages), []);
+        unit.tick(&input(1.0, 5.0 + 0.65 / 12.0, 1.0, 0.0));
+        assert_eq!(sent(&mut messages), [note_off(60), note_on(61, 127)]);
+
+        // Silent velocity still plays
+        unit.tick(&input(0.0, 5.0, 0.0, 0.0));
+        unit.tick(&input(1.0, 4.0, 0.0, 0.0));
+        assert_eq!(sent(&mut messages), [note_off(61), note_on(48, 1)]);
+
+        // Held notes are turned off on reset
+        unit.reset(None);
+        assert_eq!(sent(&mut messages), [note_off(48)]);
+    }
+
+    #[test]
+    fn test_send_controllers() {
+        let mut unit = MidiOut::<f32>::new(0, [Some(74), None, None, None]);
+        let mut messages = unit.observe();
+        let control = |value| MidiMessage::ControlChange {
+            channel: 0,
+            controller: 74,
+            value,
+        };
+
+        // Only changes are sent, unset controllers are not
+        unit.tick(&input(0.0, 0.0, 0.0, 0.5));
+        unit.tick(&input(0.0, 0.0, 0.0, 0.501));
+        unit.tick(&input(0.0, 0.0, 0.0, 2.0));
+        assert_eq!(sent(&mut messages), [control(64), control(127)]);
+    }
+}
@@ -1,15 +1,19 @@
 use fundsp::Float;
 
+pub mod clock;
 pub mod envelope;
 pub mod hold;
 pub mod join;
 pub mod messaging;
 pub mod midi;
+pub mod midi_file;
+pub mod midi_out;
 pub mod onset;
 pub mod oscillator;
 pub mod param;
 pub mod player;
 pub mod pluck;
+pub mod poly;
 pub mod quantiser;
 pub mod scope;
 pub mod shared;
@@ -25,3 +29,8 @@ pub fn volt_hz<T: Float>(voltage: T) -> T {
 pub fn midi_volt<T: Float>(pitch: u8) -> T {
     T::from_f64(pitch as f64 / 12.0)
 }
+
+// CV to the nearest midi note
+pub fn volt_midi<T: Float>(voltage: T) -> u8 {
+    (voltage.to_f64() * 12.0).round().clamp(0.0, 127.0) as u8
+}
@@ -1,17 +1,355 @@
-use fundsp::{
-    hacker::AFollow,
-    hacker32::{An, Pipe, Tag, Tagged},
-    prelude::{follow, tag},
-    Float,
-};
-
-#[allow(clippy::type_complexity)]
-pub fn param<T: Float>(id: Tag, value: T) -> An<Pipe<T, Tagged<T>, AFollow<T, f32, f32>>> {
-    tag(id, value) >> follow(0.1) // Smooth out param over 0.1s
-}
-
-// @todo this is weird
-#[allow(clippy::type_complexity)]
-pub fn param32(id: Tag, value: f32) -> An<Pipe<f32, Tagged<f32>, AFollow<f32, f32, f32>>> {
-    param(id, value)
+use fundsp::{hacker::*, Float};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Shape of a parameter ramp.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub enum Curve {
+    Linear,
+    /// Equal ratios in equal times, linear when the ramp crosses or touches zero
+    Exponential,
+    /// Eases in and out of the ramp
+    SCurve,
+}
+
+impl Curve {
+    /// Value at `position` in 0...1 along a ramp from `from` to `to`.
+    pub fn at(&self, from: f64, to: f64, position: f64) -> f64 {
+        match self {
+            Curve::Exponential if from * to > 0.0 => from * (to / from).powf(position),
+            Curve::SCurve => lerp(from, to, smooth3(position)),
+            _ => lerp(from, to, position),
+        }
+    }
+
+    fn from_f64(value: f64) -> Self {
+        match value as i64 {
+            1 => Curve::Exponential,
+            2 => Curve::SCurve,
+            _ => Curve::Linear,
+        }
+    }
+
+    fn to_f64(self) -> f64 {
+        match self {
+            Curve::Linear => 0.0,
+            Curve::Exponential => 1.0,
+            Curve::SCurve => 2.0,
+        }
+    }
+}
+
+/// Parameters changed by a command ramp to their new value over `duration` seconds,
+/// instead of following the smoothing of each parameter.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq)]
+#[ts(export)]
+pub struct Ramp {
+    pub duration: f64,
+    pub curve: Curve,
+}
+
+/// Tags shaping the next change of every `Param` in a unit, see `Ramp::shape`.
+const RAMP_DURATION: Tag = -1;
+const RAMP_CURVE: Tag = -2;
+
+impl Ramp {
+    /// Parameters set on `unit` within `set` follow the ramp.
+    pub fn shape<X: AudioNode>(&self, unit: &mut X, set: impl FnOnce(&mut X)) {
+        unit.set(RAMP_DURATION, self.duration);
+        unit.set(RAMP_CURVE, self.curve.to_f64());
+        set(unit);
+        unit.set(RAMP_DURATION, f64::NAN);
+    }
+}
+
+/// Tagged parameter ramping to the values it is set to.
+pub struct Param<T: Float> {
+    id: Tag,
+    /// Duration of ramps, unless shaped otherwise
+    smoothing: f64,
+    sample_rate: f64,
+    value: f64,
+    from: f64,
+    target: f64,
+    curve: Curve,
+    position: usize,
+    length: usize,
+    /// Shape of the next change, see `Ramp::shape`
+    next: Option<Ramp>,
+    /// Start-up fade from zero, see `fade_in`
+    fade: Option<AFollow<T, f32, f32>>,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Float> Param<T> {
+    pub fn new(id: Tag, value: f64, smoothing: f64) -> Self {
+        Self {
+            id,
+            smoothing,
+            sample_rate: DEFAULT_SR,
+            value,
+            from: value,
+            target: value,
+            curve: Curve::Linear,
+            position: 0,
+            length: 0,
+            next: None,
+            fade: None,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Fades in from zero when the unit starts, following the value with a `follow` filter
+    /// that reaches halfway in `time` seconds, until it settles or the value is changed.
+    pub fn fade_in(mut self, time: f32) -> Self {
+        self.fade = Some(AFollow::new(self.sample_rate, time));
+        self
+    }
+
+    fn ramp_to(&mut self, target: f64) {
+        // A change ends the fade, ramping on from where it got to
+        if let Some(fade) = self.fade.take() {
+            self.value = fade.value() as f64;
+        }
+
+        let ramp = self.next.unwrap_or(Ramp {
+            duration: self.smoothing,
+            curve: Curve::Linear,
+        });
+
+        self.from = self.value;
+        self.target = target;
+        self.curve = ramp.curve;
+        self.position = 0;
+        self.length = (ramp.duration.max(0.0) * self.sample_rate).round() as usize;
+        if self.length == 0 {
+            self.value = target;
+        }
+    }
+
+    #[inline]
+    fn advance(&mut self) -> f64 {
+        if self.position < self.length {
+            self.position += 1;
+            self.value = if self.position == self.length {
+                self.target
+            } else {
+                let position = self.position as f64 / self.length as f64;
+                self.curve.at(self.from, self.target, position)
+            };
+        }
+        self.value
+    }
+
+    #[inline]
+    fn fade(&mut self, value: T) -> T {
+        match self.fade.as_mut() {
+            Some(fade) => {
+                let previous = fade.value();
+                let faded = fade.tick(&[value].into())[0];
+                // The filter stops moving once it is as close as it gets
+                if fade.value() == previous {
+                    self.fade = None;
+                }
+                faded
+            }
+            None => value,
+        }
+    }
+
+    #[inline]
+    fn next_sample(&mut self) -> T {
+        let value = T::from_f64(self.advance());
+        self.fade(value)
+    }
+}
+
+impl<T: Float> AudioNode for Param<T> {
+    // Same as `Tagged`, which it stands in for, so hashes of units do not change
+    const ID: u64 = 54;
+
+    type Sample = T;
+
+    type Inputs = U0;
+
+    type Outputs = U1;
+
+    /// Finishes the ramp in progress, a fade starts again from zero
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        if let Some(sample_rate) = sample_rate {
+            self.sample_rate = sample_rate;
+        }
+        self.value = self.target;
+        self.length = 0;
+        if let Some(fade) = self.fade.as_mut() {
+            fade.reset(sample_rate);
+        }
+    }
+
+    #[inline]
+    fn tick(
+        &mut self,
+        _input: &Frame<Self::Sample, Self::Inputs>,
+    ) -> Frame<Self::Sample, Self::Outputs> {
+        [self.next_sample()].into()
+    }
+
+    fn process(
+        &mut self,
+        size: usize,
+        _input: &[&[Self::Sample]],
+        output: &mut [&mut [Self::Sample]],
+    ) {
+        if self.position < self.length || self.fade.is_some() {
+            for sample in output[0][..size].iter_mut() {
+                *sample = self.next_sample();
+            }
+        } else {
+            output[0][..size].fill(T::from_f64(self.value));
+        }
+    }
+
+    fn route(&self, _input: &SignalFrame, _frequency: f64) -> SignalFrame {
+        let mut output = new_signal_frame(self.outputs());
+        output[0] = Signal::Value(self.value);
+        output
+    }
+
+    fn set(&mut self, parameter: Tag, value: f64) {
+        match parameter {
+            RAMP_DURATION if value.is_nan() => self.next = None,
+            RAMP_DURATION => {
+                self.next = Some(Ramp {
+                    duration: value,
+                    curve: Curve::Linear,
+                })
+            }
+            RAMP_CURVE => {
+                if let Some(next) = self.next.as_mut() {
+                    next.curve = Curve::from_f64(value);
+                }
+            }
+            id if id == self.id => self.ramp_to(value),
+            _ => {}
+        }
+    }
+
+    /// Value the parameter is ramping to
+    fn get(&self, parameter: Tag) -> Option<f64> {
+        if parameter == self.id {
+            Some(self.target)
+        } else {
+            None
+        }
+    }
+}
+
+/// Tagged parameter, ramping to new values over `smoothing` seconds.
+/// - Output 0: Parameter value.
+pub fn param<T: Float>(id: Tag, value: T, smoothing: f64) -> An<Param<T>> {
+    An(Param::new(id, value.to_f64(), smoothing))
+}
+
+/// Tagged `f32` parameter, see `param`.
+pub fn param32(id: Tag, value: f32, smoothing: f64) -> An<Param<f32>> {
+    param(id, value, smoothing)
+}
+
+/// Tagged parameter like `param`, fading in from zero when the unit starts,
+/// reaching halfway in `smoothing` seconds, see `Param::fade_in`.
+pub fn fade_in_param<T: Float>(id: Tag, value: T, smoothing: f64) -> An<Param<T>> {
+    An(Param::new(id, value.to_f64(), smoothing).fade_in(smoothing as f32))
+}
+
+/// Tagged `f32` parameter fading in from zero, see `fade_in_param`.
+pub fn fade_in_param32(id: Tag, value: f32, smoothing: f64) -> An<Param<f32>> {
+    fade_in_param(id, value, smoothing)
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::hacker32::*;
+
+    use super::{Curve, Param, Ramp};
+
+    fn render(unit: &mut impl AudioNode<Sample = f32>, length: usize) -> Vec<f32> {
+        (0..length)
+            .map(|_| unit.tick(&Frame::default())[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_param_follows_its_smoothing() {
+        let mut unit = Param::<f32>::new(0, 1.0, 0.001);
+        unit.reset(Some(1000.0));
+        assert_eq!(render(&mut unit, 2), vec![1.0, 1.0]);
+
+        unit.set(0, 2.0);
+        assert_eq!(render(&mut unit, 2), vec![2.0, 2.0]);
+        assert_eq!(unit.get(0), Some(2.0));
+
+        // Other tags are ignored
+        unit.set(1, 3.0);
+        assert_eq!(render(&mut unit, 1), vec![2.0]);
+    }
+
+    #[test]
+    fn test_fade_in_until_changed() {
+        let mut unit = Param::<f32>::new(0, 1.0, 0.0).fade_in(0.001);
+        unit.reset(Some(1000.0));
+        let faded = render(&mut unit, 3);
+        assert!(faded[0] > 0.0);
+        assert!(faded
+            .windows(2)
+            .all(|pair| pair[0] < pair[1] && pair[1] < 1.0));
+
+        // A change ramps on from the faded value
+        let ramp = Ramp {
+            duration: 0.002,
+            curve: Curve::Linear,
+        };
+        ramp.shape(&mut unit, |unit| unit.set(0, 2.0));
+        let ramped = render(&mut unit, 2);
+        assert!(faded[2] < ramped[0] && ramped[0] < 2.0);
+        assert_eq!(ramped[1], 2.0);
+
+        // Otherwise the fade ends on the value once it settles
+        let mut unit = Param::<f32>::new(0, 1.0, 0.0).fade_in(0.001);
+        unit.reset(Some(1000.0));
+        assert_eq!(render(&mut unit, 1000).last(), Some(&1.0));
+    }
+
+    #[test]
+    fn test_ramp_shapes_the_next_change() {
+        let mut unit = Param::<f32>::new(0, 1.0, 0.0);
+        unit.reset(Some(1000.0));
+
+        let ramp = Ramp {
+            duration: 0.004,
+            curve: Curve::Linear,
+        };
+        ramp.shape(&mut unit, |unit| unit.set(0, 3.0));
+        assert_eq!(render(&mut unit, 5), vec![1.5, 2.0, 2.5, 3.0, 3.0]);
+
+        // The ramp only applies to changes made within it
+        unit.set(0, 0.0);
+        assert_eq!(render(&mut unit, 1), vec![0.0]);
+
+        // Resetting lands on the value being ramped to
+        ramp.shape(&mut unit, |unit| unit.set(0, 1.0));
+        unit.reset(None);
+        assert_eq!(render(&mut unit, 1), vec![1.0]);
+    }
+
+    #[test]
+    fn test_curves() {
+        assert_eq!(Curve::Linear.at(1.0, 4.0, 0.5), 2.5);
+        assert_eq!(Curve::Exponential.at(1.0, 4.0, 0.5), 2.0);
+        assert_eq!(Curve::SCurve.at(1.0, 4.0, 0.25), 1.46875);
+
+        // Exponential ramps through zero fall back to linear
+        assert_eq!(Curve::Exponential.at(-1.0, 1.0, 0.5), 0.0);
+        assert_eq!(Curve::Exponential.at(0.0, 1.0, 0.5), 0.5);
+    }
 }
@@ -2,7 +2,6 @@ use std::{marker::PhantomData, sync::Arc};
 
 use crate::utils::observer::{Observable, Observer, Producer, Subject};
 use fundsp::prelude::*;
-use rand::Rng;
 
 use super::onset::{onset, superflux_diff_spec, Spectrogram};
 
@@ -17,6 +16,8 @@ pub struct Wave32Player<T: Float> {
     subject: Subject<PlayerEvent>,
     loop_point: Option<usize>,
     detections: Vec<usize>,
+    /// Picks the segment played on each trigger, seeded with `set_hash`
+    rnd: AttoRand,
     _marker: PhantomData<T>,
 }
 
@@ -37,6 +38,7 @@ impl<T: Float> Wave32Player<T> {
             subject: Default::default(),
             loop_point,
             detections: Default::default(),
+            rnd: AttoRand::new(0),
             _marker: PhantomData::default(),
         }
     }
@@ -124,14 +126,18 @@ impl<T: Float> AudioNode for Wave32Player<T> {
 
     fn reset(&mut self, _sample_rate: Option<f64>) {
         if !self.detections.is_empty() {
-            let mut rng = rand::thread_rng();
-            self.sample = rng.gen_range(0..self.detections.len() - 1);
+            let segments = Ord::max(self.detections.len() - 1, 1);
+            self.sample = (self.rnd.get() % segments as u64) as usize;
 
             self.subject.notify(PlayerEvent::OnTrigger(self.sample))
         }
         self.index = 0;
     }
 
+    fn set_hash(&mut self, hash: u64) {
+        self.rnd = AttoRand::new(hash);
+    }
+
     #[inline]
     fn tick(
         &mut self,
@@ -0,0 +1,178 @@
+use fundsp::{buffer::Buffer, hacker::*};
+
+use crate::module::ModuleUnit;
+
+/// A voice of a polyphonic unit, with its own buffers.
+struct Voice {
+    unit: ModuleUnit,
+    input: Buffer<f32>,
+    output: Buffer<f32>,
+    tick_input: Vec<f32>,
+    tick_output: Vec<f32>,
+}
+
+/// Runs one unit per channel of polyphonic ports.
+/// Channels of a port are consecutive: channel `c` of port `p` is at `p * voices + c`
+/// and is processed by voice `c`.
+pub struct PolyUnit {
+    voices: Vec<Voice>,
+    inputs: usize,
+    outputs: usize,
+}
+
+impl PolyUnit {
+    /// Create a polyphonic unit from identical voices.
+    pub fn new(units: Vec<ModuleUnit>) -> Self {
+        let inputs = units.first().map_or(0, |unit| unit.inputs());
+        let outputs = units.first().map_or(0, |unit| unit.outputs());
+
+        let voices = units
+            .into_iter()
+            .map(|unit| Voice {
+                unit,
+                input: Buffer::with_size(inputs),
+                output: Buffer::with_size(outputs),
+                tick_input: vec![0.0; inputs],
+                tick_output: vec![0.0; outputs],
+            })
+            .collect();
+
+        Self {
+            voices,
+            inputs,
+            outputs,
+        }
+    }
+}
+
+impl AudioUnit32 for PolyUnit {
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        for voice in self.voices.iter_mut() {
+            voice.unit.reset(sample_rate);
+        }
+    }
+
+    fn tick(&mut self, input: &[f32], output: &mut [f32]) {
+        let channels = self.voices.len();
+
+        for (channel, voice) in self.voices.iter_mut().enumerate() {
+            for port in 0..self.inputs {
+                voice.tick_input[port] = input[port * channels + channel];
+            }
+
+            voice.unit.tick(&voice.tick_input, &mut voice.tick_output);
+
+            for port in 0..self.outputs {
+                output[port * channels + channel] = voice.tick_output[port];
+            }
+        }
+    }
+
+    fn process(&mut self, size: usize, input: &[&[f32]], output: &mut [&mut [f32]]) {
+        let channels = self.voices.len();
+
+        for (channel, voice) in self.voices.iter_mut().enumerate() {
+            for port in 0..self.inputs {
+                voice.input.mut_at(port)[..size]
+                    .copy_from_slice(&input[port * channels + channel][..size]);
+            }
+
+            voice.unit.process(
+                size,
+                voice.input.get_ref(self.inputs),
+                voice.output.get_mut(self.outputs),
+            );
+
+            for port in 0..self.outputs {
+                output[port * channels + channel][..size]
+                    .copy_from_slice(&voice.output.at(port)[..size]);
+            }
+        }
+    }
+
+    fn inputs(&self) -> usize {
+        self.inputs * self.voices.len()
+    }
+
+    fn outputs(&self) -> usize {
+        self.outputs * self.voices.len()
+    }
+
+    fn route(&self, input: &SignalFrame, frequency: f64) -> SignalFrame {
+        let channels = self.voices.len();
+        let mut output = new_signal_frame(self.outputs());
+
+        for (channel, voice) in self.voices.iter().enumerate() {
+            let mut voice_input = new_signal_frame(self.inputs);
+            for port in 0..self.inputs {
+                voice_input[port] = input[port * channels + channel];
+            }
+
+            let voice_output = voice.unit.route(&voice_input, frequency);
+            for port in 0..self.outputs {
+                output[port * channels + channel] = voice_output[port];
+            }
+        }
+
+        output
+    }
+
+    fn set(&mut self, parameter: Tag, value: f64) {
+        for voice in self.voices.iter_mut() {
+            voice.unit.set(parameter, value);
+        }
+    }
+
+    fn get(&self, parameter: Tag) -> Option<f64> {
+        self.voices
+            .first()
+            .and_then(|voice| voice.unit.get(parameter))
+    }
+
+    fn get_id(&self) -> u64 {
+        self.voices.first().map_or(0, |voice| voice.unit.get_id())
+    }
+
+    /// Voices are seeded one after the other, so each gets its own randomness.
+    fn ping(&mut self, probe: bool, hash: AttoRand) -> AttoRand {
+        let hash = hash.hash(self.get_id());
+        self.voices
+            .iter_mut()
+            .fold(hash, |hash, voice| voice.unit.ping(probe, hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::hacker32::*;
+
+    use super::PolyUnit;
+    use crate::module::ModuleUnit;
+
+    #[test]
+    fn test_poly_channel_layout() {
+        // Each voice adds its own offset to the first input and passes the second
+        let voices: Vec<ModuleUnit> = (0..3)
+            .map(|voice| Box::new((pass() + dc(voice as f32)) | pass()) as ModuleUnit)
+            .collect();
+        let mut poly = PolyUnit::new(voices);
+
+        assert_eq!(poly.inputs(), 6);
+        assert_eq!(poly.outputs(), 6);
+
+        let input = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut output = [0.0; 6];
+        poly.tick(&input, &mut output);
+        assert_eq!(output, [1.0, 3.0, 5.0, 4.0, 5.0, 6.0]);
+
+        let input: Vec<Vec<f32>> = input.iter().map(|&x| vec![x; 4]).collect();
+        let input: Vec<&[f32]> = input.iter().map(|x| &x[..]).collect();
+        let mut output = vec![vec![0.0; 4]; 6];
+        let mut output: Vec<&mut [f32]> = output.iter_mut().map(|x| &mut x[..]).collect();
+        poly.process(4, &input, &mut output);
+        assert_eq!(
+            output.iter().map(|x| x[3]).collect::<Vec<_>>(),
+            vec![1.0, 3.0, 5.0, 4.0, 5.0, 6.0]
+        );
+    }
+}
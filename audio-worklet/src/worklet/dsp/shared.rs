@@ -1,12 +1,32 @@
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use fundsp::{hacker::Complex64, hacker32::*};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Deferred mutation of a shared `AudioNode`, run by the audio thread.
+/// Handlers return false once they are done, and are then dropped.
+pub type Handler<X> = Box<dyn FnMut(&mut X) -> bool + Send>;
+
+/// Node along with its handlers, only locked by the clone that is processed.
+pub struct Handled<X> {
+    pub unit: X,
+    /// Handlers added since the node was last processed
+    added: UnboundedReceiver<Handler<X>>,
+    handlers: Vec<Handler<X>>,
+}
 
 /// AudioNodes in `fundsp` are owned by audio processing. In some cases,
 /// it is necessary to be able to mutate the state of `AudioNodes` asyncronously.
 /// By wrapping an `AudioNode` with `Shared` it can be cloned, which clones
 /// the reference to the `AudioNode` and allows interior mutability of the `AudioNode`.
-pub struct Shared<X: AudioNode>(Arc<Mutex<X>>);
+///
+/// Other threads never lock the node. They hand handlers over through a queue with
+/// `add_handler`, which the audio thread drains and runs before each `tick` or `process`,
+/// so processing never waits on them.
+pub struct Shared<X: AudioNode> {
+    node: Arc<Mutex<Handled<X>>>,
+    handlers: UnboundedSender<Handler<X>>,
+}
 
 pub trait Share<X: AudioNode> {
     /// Converts a `AudioNode` into a `Shared` `AudioNode`.
@@ -16,12 +36,39 @@ pub trait Share<X: AudioNode> {
 
 impl<X: AudioNode> Clone for Shared<X> {
     fn clone(&self) -> Self {
-        Shared(self.0.clone())
+        Shared {
+            node: self.node.clone(),
+            handlers: self.handlers.clone(),
+        }
     }
 }
 impl<X: AudioNode> Shared<X> {
-    pub fn lock(&self) -> MutexGuard<X> {
-        self.0.lock().unwrap()
+    /// Lock the node. Only the audio thread should do so once the node is processed.
+    pub fn lock(&self) -> MutexGuard<'_, Handled<X>> {
+        self.node.lock().unwrap()
+    }
+
+    /// Queues a handler that is run by the audio thread before processing.
+    pub fn add_handler(&self, handler: Handler<X>) {
+        // Handlers are dropped along with the node
+        let _ = self.handlers.unbounded_send(handler);
+    }
+
+    /// Locks the node and runs the pending handlers on it.
+    fn lock_handled(&self) -> MutexGuard<'_, Handled<X>> {
+        let mut node = self.lock();
+        let Handled {
+            unit,
+            added,
+            handlers,
+        } = &mut *node;
+
+        while let Ok(Some(handler)) = added.try_next() {
+            handlers.push(handler);
+        }
+        handlers.retain_mut(|handler| handler(unit));
+
+        node
     }
 }
 
@@ -35,14 +82,14 @@ impl<X: AudioNode> AudioNode for Shared<X> {
     type Outputs = X::Outputs;
 
     fn reset(&mut self, sample_rate: Option<f64>) {
-        self.0.lock().unwrap().reset(sample_rate);
+        self.lock().unit.reset(sample_rate);
     }
 
     fn tick(
         &mut self,
         input: &Frame<Self::Sample, Self::Inputs>,
     ) -> Frame<Self::Sample, Self::Outputs> {
-        self.0.lock().unwrap().tick(input)
+        self.lock_handled().unit.tick(input)
     }
 
     fn process(
@@ -51,39 +98,39 @@ impl<X: AudioNode> AudioNode for Shared<X> {
         input: &[&[Self::Sample]],
         output: &mut [&mut [Self::Sample]],
     ) {
-        self.0.lock().unwrap().process(size, input, output)
+        self.lock_handled().unit.process(size, input, output)
     }
 
     fn set_hash(&mut self, hash: u64) {
-        self.0.lock().unwrap().set_hash(hash)
+        self.lock().unit.set_hash(hash)
     }
 
     fn ping(&mut self, probe: bool, hash: AttoRand) -> AttoRand {
-        self.0.lock().unwrap().ping(probe, hash)
+        self.lock().unit.ping(probe, hash)
     }
 
     fn route(&self, input: &SignalFrame, frequency: f64) -> SignalFrame {
-        self.0.lock().unwrap().route(input, frequency)
+        self.lock().unit.route(input, frequency)
     }
 
     fn set(&mut self, parameter: Tag, value: f64) {
-        self.0.lock().unwrap().set(parameter, value);
+        self.lock().unit.set(parameter, value);
     }
 
     fn get(&self, parameter: Tag) -> Option<f64> {
-        self.0.lock().unwrap().get(parameter)
+        self.lock().unit.get(parameter)
     }
 
     fn response(&self, output: usize, frequency: f64) -> Option<Complex64> {
-        self.0.lock().unwrap().response(output, frequency)
+        self.lock().unit.response(output, frequency)
     }
 
     fn response_db(&self, output: usize, frequency: f64) -> Option<f64> {
-        self.0.lock().unwrap().response_db(output, frequency)
+        self.lock().unit.response_db(output, frequency)
     }
 
     fn latency(&self) -> Option<f64> {
-        self.0.lock().unwrap().latency()
+        self.lock().unit.latency()
     }
 }
 
@@ -92,6 +139,15 @@ where
     X: AudioNode,
 {
     fn share(self) -> An<Shared<X>> {
-        An(Shared(Arc::new(Mutex::new(self.0))))
+        let (handlers, added) = mpsc::unbounded();
+
+        An(Shared {
+            node: Arc::new(Mutex::new(Handled {
+                unit: self.0,
+                added,
+                handlers: vec![],
+            })),
+            handlers,
+        })
     }
 }
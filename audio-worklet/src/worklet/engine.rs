@@ -0,0 +1,703 @@
+//! Audio thread side of the `AudioProcessor`.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use fundsp::{
+    buffer::Buffer,
+    hacker::{AttoRand, AudioUnit32, SignalFrame, Tag},
+    DEFAULT_SR,
+};
+use futures::channel::{mpsc::UnboundedReceiver, oneshot};
+use petgraph::stable_graph::EdgeIndex;
+
+use crate::{
+    context::Notify,
+    graph::{Graph32, NodeIndex, PortIndex, SumMode},
+    interface::time::CommandTime,
+    module::ModuleUnit,
+};
+
+/// Edits to the graph, queued by the control thread.
+/// Indices are assigned by the `Topology` mirror, nodes and edges are added at them.
+pub enum GraphEdit {
+    Add(NodeIndex, ModuleUnit),
+    Remove(NodeIndex),
+    /// Connect a cable between (first channel, channel count) ranges of two nodes.
+    Connect(
+        EdgeIndex,
+        (NodeIndex, (PortIndex, usize)),
+        (NodeIndex, (PortIndex, usize)),
+    ),
+    Disconnect(EdgeIndex),
+    SetSumMode((NodeIndex, PortIndex), SumMode),
+    /// Seed the random sources of every module.
+    Seed(u64),
+    /// Edits applied together, before the next block is processed.
+    Batch(Vec<GraphEdit>),
+    /// Run on the audio thread while applying edits,
+    /// used to deliver commands and subpatch edits along with a batch.
+    Notify(Notify),
+    /// Run on the audio thread right before the frame at the given time is processed,
+    /// used to deliver commands on an exact sample.
+    Schedule(CommandTime, Notify),
+    /// Batch of a subpatch, delivered along with the batch of its parent graph.
+    /// Later edits wait for it, see `AudioEngine::apply_edits`.
+    Pending(oneshot::Receiver<GraphEdit>),
+}
+
+impl GraphEdit {
+    /// Apply the edit, returning false when the graph does not match the topology
+    /// that sent it, e.g. the id of an added node is already in use.
+    pub fn apply(self, graph: &mut Graph32, schedule: &mut Schedule) -> bool {
+        match self {
+            GraphEdit::Add(id, unit) => graph.add_at(id, unit),
+            GraphEdit::Remove(id) => graph.remove(id),
+            GraphEdit::Connect(id, (source, source_channels), (target, target_channels)) => {
+                graph.connect_at(id, source, source_channels, target, target_channels)
+            }
+            GraphEdit::Disconnect(id) => graph.disconnect(id),
+            GraphEdit::SetSumMode((node, port), mode) => {
+                // Sum modes of ports past the inputs of a module are kept by the topology only
+                graph.set_sum_mode(node, port, mode);
+                true
+            }
+            GraphEdit::Seed(seed) => {
+                graph.set_hash(seed);
+                true
+            }
+            GraphEdit::Batch(edits) => graph.batch(|graph| {
+                // Every edit is applied, even after one does not match
+                let mut synced = true;
+                for edit in edits {
+                    synced &= edit.apply(graph, schedule);
+                }
+                synced
+            }),
+            GraphEdit::Notify(notify) => {
+                notify();
+                true
+            }
+            GraphEdit::Schedule(time, notify) => {
+                schedule.insert(time, notify);
+                true
+            }
+            // Only queued by the topology, waited for by the engine
+            GraphEdit::Pending(_) => false,
+        }
+    }
+}
+
+/// Number of commands waiting in the schedule before more are run straight away.
+/// Room for them is allocated up front, so scheduling does not allocate on the audio thread.
+pub const MAX_SCHEDULED: usize = 1024;
+
+/// Commands waiting for the frame they are timed at.
+pub struct Schedule {
+    sample_rate: f64,
+    /// Sorted by frame, commands timed at the same frame keep the order they were sent in.
+    commands: Vec<(u64, Notify)>,
+}
+
+impl Schedule {
+    fn insert(&mut self, time: CommandTime, notify: Notify) {
+        let frame = match time {
+            CommandTime::Frame(frame) => frame,
+            CommandTime::Seconds(seconds) => (seconds.max(0.0) * self.sample_rate).round() as u64,
+        };
+
+        // A full schedule runs the command early rather than dropping it
+        if self.commands.len() == self.commands.capacity() {
+            notify();
+            return;
+        }
+
+        let index = self.commands.partition_point(|(at, _)| *at <= frame);
+        self.commands.insert(index, (frame, notify));
+    }
+
+    /// Run the commands timed at `frame` or earlier.
+    fn run(&mut self, frame: u64) {
+        let due = self.commands.partition_point(|(at, _)| *at <= frame);
+        for (_, notify) in self.commands.drain(..due) {
+            notify();
+        }
+    }
+
+    /// Frame of the next command.
+    fn next(&self) -> Option<u64> {
+        self.commands.first().map(|(frame, _)| *frame)
+    }
+}
+
+/// Owns the `Graph32` on the audio thread.
+/// Queued edits are applied at block boundaries, so processing never waits on the control thread.
+/// Scheduled commands are run on their exact frame, splitting the block around them.
+/// The engine is itself an audio unit, which lets subpatches nest a graph inside a module.
+pub struct AudioEngine {
+    graph: Graph32,
+    edits: UnboundedReceiver<GraphEdit>,
+    /// Batch the queued edits wait for, until the parent graph delivers it
+    pending: Option<oneshot::Receiver<GraphEdit>>,
+    /// Cleared once an edit does not match the graph, see `Topology::is_synced`
+    synced: Arc<AtomicBool>,
+    schedule: Schedule,
+    /// Frame about to be processed
+    frame: u64,
+    /// Input and output of a block split by scheduled commands
+    input: Buffer<f32>,
+    output: Buffer<f32>,
+}
+
+impl AudioEngine {
+    pub fn new(
+        graph: Graph32,
+        edits: UnboundedReceiver<GraphEdit>,
+        synced: Arc<AtomicBool>,
+    ) -> Self {
+        let input = Buffer::with_size(graph.inputs());
+        let output = Buffer::with_size(graph.outputs());
+
+        Self {
+            graph,
+            edits,
+            pending: None,
+            synced,
+            schedule: Schedule {
+                sample_rate: DEFAULT_SR,
+                commands: Vec::with_capacity(MAX_SCHEDULED),
+            },
+            frame: 0,
+            input,
+            output,
+        }
+    }
+
+    /// Apply all queued edits to the graph.
+    /// Edits queued after a pending batch wait until the parent graph delivers it,
+    /// which happens on the audio thread without allocating.
+    pub fn apply_edits(&mut self) {
+        loop {
+            if let Some(pending) = self.pending.as_mut() {
+                match pending.try_recv() {
+                    Ok(Some(edit)) => self.apply(edit),
+                    Ok(None) => return,
+                    // The batch of the parent graph was dropped along with it
+                    Err(oneshot::Canceled) => {}
+                }
+                self.pending = None;
+            }
+
+            match self.edits.try_next() {
+                Ok(Some(GraphEdit::Pending(receiver))) => self.pending = Some(receiver),
+                Ok(Some(edit)) => self.apply(edit),
+                _ => return,
+            }
+        }
+    }
+
+    fn apply(&mut self, edit: GraphEdit) {
+        if !edit.apply(&mut self.graph, &mut self.schedule) {
+            self.synced.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Follow the clock of the audio context, so commands are timed against its frames.
+    /// Frames are counted from zero otherwise.
+    pub fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// Process `length` samples from `offset` into the block, through the split buffers.
+    fn process_range(
+        &mut self,
+        offset: usize,
+        length: usize,
+        input: &[&[f32]],
+        output: &mut [&mut [f32]],
+    ) {
+        let range = offset..offset + length;
+        for (channel, input) in input.iter().enumerate() {
+            self.input.mut_at(channel)[..length].copy_from_slice(&input[range.clone()]);
+        }
+
+        self.graph.process(
+            length,
+            self.input.get_ref(input.len()),
+            self.output.get_mut(output.len()),
+        );
+
+        for (channel, output) in output.iter_mut().enumerate() {
+            output[range.clone()].copy_from_slice(&self.output.at(channel)[..length]);
+        }
+    }
+}
+
+impl AudioUnit32 for AudioEngine {
+    fn reset(&mut self, sample_rate: Option<f64>) {
+        if let Some(sample_rate) = sample_rate {
+            self.schedule.sample_rate = sample_rate;
+        }
+        self.graph.reset(sample_rate);
+    }
+
+    fn tick(&mut self, input: &[f32], output: &mut [f32]) {
+        self.apply_edits();
+        self.schedule.run(self.frame);
+        self.graph.tick(input, output);
+        self.frame += 1;
+    }
+
+    /// Process one block, applying queued edits first.
+    /// The block is split wherever a scheduled command is due.
+    fn process(&mut self, size: usize, input: &[&[f32]], output: &mut [&mut [f32]]) {
+        self.apply_edits();
+
+        let mut offset = 0;
+        while offset < size {
+            // Commands are run before the frame they are timed at is processed
+            self.schedule.run(self.frame);
+
+            let remaining = size - offset;
+            let length = self.schedule.next().map_or(remaining, |next| {
+                (next - self.frame).min(remaining as u64) as usize
+            });
+
+            if length == size {
+                self.graph.process(size, input, output);
+            } else {
+                self.process_range(offset, length, input, output);
+            }
+
+            offset += length;
+            self.frame += length as u64;
+        }
+    }
+
+    fn inputs(&self) -> usize {
+        self.graph.inputs()
+    }
+
+    fn outputs(&self) -> usize {
+        self.graph.outputs()
+    }
+
+    fn route(&self, input: &SignalFrame, frequency: f64) -> SignalFrame {
+        self.graph.route(input, frequency)
+    }
+
+    fn set(&mut self, parameter: Tag, value: f64) {
+        self.graph.set(parameter, value);
+    }
+
+    fn get(&self, parameter: Tag) -> Option<f64> {
+        self.graph.get(parameter)
+    }
+
+    fn get_id(&self) -> u64 {
+        self.graph.get_id()
+    }
+
+    fn set_hash(&mut self, hash: u64) {
+        self.graph.set_hash(hash);
+    }
+
+    fn ping(&mut self, probe: bool, hash: AttoRand) -> AttoRand {
+        self.graph.ping(probe, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::{hacker::AudioUnit32, hacker32::zero, MAX_BUFFER_SIZE};
+
+    use crate::{
+        dsp::param::{Curve, Ramp},
+        interface::{
+            address::{Address, Port},
+            error::SobakaError,
+            time::CommandTime,
+        },
+        module::{
+            parameter::{ParameterCommand, ParameterParams},
+            subpatch::SubpatchParams,
+            AudioModuleCommand, AudioModuleType,
+        },
+        AudioProcessor,
+    };
+
+    #[test]
+    fn test_edits_apply_at_block_boundaries() {
+        let (processor, mut engine) = AudioProcessor::new();
+
+        let mut left = [0.0; MAX_BUFFER_SIZE];
+        let mut right = [0.0; MAX_BUFFER_SIZE];
+        let mut process = || {
+            let mut output = [0.0; MAX_BUFFER_SIZE];
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+            left[MAX_BUFFER_SIZE - 1]
+        };
+
+        let parameter = processor
+            .create(
+                AudioModuleType::Parameter(ParameterParams {
+                    min: 0.0,
+                    max: 1.0,
+                    default: 1.0,
+                }),
+                None,
+            )
+            .unwrap();
+        let output = processor.create(AudioModuleType::Output, None).unwrap();
+
+        processor
+            .connect(
+                Address {
+                    port: Some(Port::Output(0)),
+                    ..parameter.clone()
+                },
+                Address {
+                    port: Some(Port::Input(0)),
+                    ..output
+                },
+                None,
+            )
+            .unwrap();
+
+        // Edits are queued until the next block
+        let mut value = process();
+        assert!(value > 0.0);
+
+        for _ in 0..1000 {
+            value = process();
+        }
+        assert!((value - 1.0).abs() < 1.0e-3);
+
+        // Commands are handled by the audio thread while processing
+        processor
+            .message(
+                parameter,
+                AudioModuleCommand::Parameter(ParameterCommand::SetParameter(0.0)),
+                None,
+                None,
+            )
+            .unwrap();
+
+        for _ in 0..1000 {
+            value = process();
+        }
+        assert!(value.abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn test_out_of_sync_edits_are_refused() {
+        let (processor, mut engine) = AudioProcessor::new();
+
+        // A node added behind the back of the topology takes the id of the next module
+        engine.graph.add(Box::new(zero()));
+        processor.create(AudioModuleType::Noise, None).unwrap();
+
+        let mut outputs = [[0.0; MAX_BUFFER_SIZE]; 3];
+        let [left, right, output] = &mut outputs;
+        engine.process(MAX_BUFFER_SIZE, &[], &mut [left, right, output]);
+
+        assert_eq!(
+            processor.create(AudioModuleType::Noise, None).err(),
+            Some(SobakaError::OutOfSync)
+        );
+        // The patch can still be saved, to load it again after a reload
+        assert_eq!(processor.get_patch().unwrap().modules.len(), 1);
+    }
+
+    #[test]
+    fn test_subpatch_nested_addresses() {
+        let (processor, mut engine) = AudioProcessor::new();
+
+        let subpatch = processor
+            .create(
+                AudioModuleType::Subpatch(SubpatchParams {
+                    inputs: 0,
+                    outputs: 2,
+                }),
+                None,
+            )
+            .unwrap();
+        let parameter = processor
+            .create(
+                AudioModuleType::Parameter(ParameterParams {
+                    min: 0.0,
+                    max: 1.0,
+                    default: 1.0,
+                }),
+                Some(subpatch.clone()),
+            )
+            .unwrap();
+        assert_eq!(parameter.to_string(), "/sobaka/2/2");
+
+        // Ports are allocated up front, so their number is bounded
+        assert!(processor
+            .create(
+                AudioModuleType::Subpatch(SubpatchParams {
+                    inputs: usize::MAX,
+                    outputs: 2,
+                }),
+                None,
+            )
+            .is_err());
+
+        // Module 1 inside the subpatch takes the subpatch outputs
+        for port in 0..2 {
+            processor
+                .connect(
+                    Address {
+                        port: Some(Port::Output(0)),
+                        ..parameter.clone()
+                    },
+                    Address {
+                        port: Some(Port::Input(port)),
+                        ..subpatch.child(1)
+                    },
+                    None,
+                )
+                .unwrap();
+        }
+
+        let output = processor.create(AudioModuleType::Output, None).unwrap();
+        for port in 0..2 {
+            processor
+                .connect(
+                    Address {
+                        port: Some(Port::Output(port)),
+                        ..subpatch.clone()
+                    },
+                    Address {
+                        port: Some(Port::Input(port)),
+                        ..output.clone()
+                    },
+                    None,
+                )
+                .unwrap();
+        }
+
+        // Connections cannot cross subpatch boundaries
+        assert!(processor
+            .connect(
+                Address {
+                    port: Some(Port::Output(0)),
+                    ..parameter.clone()
+                },
+                Address {
+                    port: Some(Port::Input(0)),
+                    ..output
+                },
+                None,
+            )
+            .is_err());
+
+        let mut left = [0.0; MAX_BUFFER_SIZE];
+        let mut right = [0.0; MAX_BUFFER_SIZE];
+        let mut output = [0.0; MAX_BUFFER_SIZE];
+        for _ in 0..1000 {
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+        }
+        assert!((left[MAX_BUFFER_SIZE - 1] - 1.0).abs() < 1.0e-3);
+        assert!((right[MAX_BUFFER_SIZE - 1] - 1.0).abs() < 1.0e-3);
+
+        // Commands reach modules inside the subpatch
+        processor
+            .message(
+                parameter,
+                AudioModuleCommand::Parameter(ParameterCommand::SetParameter(0.0)),
+                None,
+                None,
+            )
+            .unwrap();
+
+        for _ in 0..1000 {
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+        }
+        assert!(left[MAX_BUFFER_SIZE - 1].abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn test_commands_land_on_their_frame() {
+        let (processor, mut engine) = AudioProcessor::new();
+        let silent = AudioModuleType::Parameter(ParameterParams {
+            min: 0.0,
+            max: 1.0,
+            default: 0.0,
+        });
+        let port = |address: &Address, port| Address {
+            port: Some(port),
+            ..address.clone()
+        };
+
+        // One parameter plays on the left, the other one inside a subpatch on the right
+        let output = processor.create(AudioModuleType::Output, None).unwrap();
+        let left = processor.create(silent.clone(), None).unwrap();
+        processor
+            .connect(
+                port(&left, Port::Output(0)),
+                port(&output, Port::Input(0)),
+                None,
+            )
+            .unwrap();
+
+        let subpatch = processor
+            .create(
+                AudioModuleType::Subpatch(SubpatchParams {
+                    inputs: 0,
+                    outputs: 1,
+                }),
+                None,
+            )
+            .unwrap();
+        let right = processor.create(silent, Some(subpatch.clone())).unwrap();
+        processor
+            .connect(
+                port(&right, Port::Output(0)),
+                port(&subpatch.child(1), Port::Input(0)),
+                None,
+            )
+            .unwrap();
+        processor
+            .connect(
+                port(&subpatch, Port::Output(0)),
+                port(&output, Port::Input(1)),
+                None,
+            )
+            .unwrap();
+
+        let set = |address: Address, at| {
+            processor
+                .message(
+                    address,
+                    AudioModuleCommand::Parameter(ParameterCommand::SetParameter(1.0)),
+                    Some(at),
+                    None,
+                )
+                .unwrap();
+        };
+        set(left, CommandTime::Frame(100));
+        set(right, CommandTime::Seconds(150.0 / 44100.0));
+
+        let mut process = || {
+            let mut left = [0.0; MAX_BUFFER_SIZE];
+            let mut right = [0.0; MAX_BUFFER_SIZE];
+            let mut output = [0.0; MAX_BUFFER_SIZE];
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+            (left, right)
+        };
+
+        // Frames 0 to 63
+        let (left, right) = process();
+        assert!(left.iter().chain(&right).all(|&sample| sample == 0.0));
+
+        // Frames 64 to 127, the left parameter is set on frame 100
+        let (left, right) = process();
+        assert!(left[..36].iter().all(|&sample| sample == 0.0));
+        assert!(left[36] > 0.0);
+        assert!(right.iter().all(|&sample| sample == 0.0));
+
+        // Frames 128 to 191, the right parameter is set on frame 150
+        let (_, right) = process();
+        assert!(right[..22].iter().all(|&sample| sample == 0.0));
+        assert!(right[22] > 0.0);
+    }
+
+    #[test]
+    fn test_ramps_follow_their_curve() {
+        let (processor, mut engine) = AudioProcessor::new();
+        let output = processor.create(AudioModuleType::Output, None).unwrap();
+        let parameter = processor
+            .create(
+                AudioModuleType::Parameter(ParameterParams {
+                    min: 0.0,
+                    max: 4.0,
+                    default: 1.0,
+                }),
+                None,
+            )
+            .unwrap();
+        processor
+            .connect(
+                Address {
+                    port: Some(Port::Output(0)),
+                    ..parameter.clone()
+                },
+                Address {
+                    port: Some(Port::Input(0)),
+                    ..output
+                },
+                None,
+            )
+            .unwrap();
+
+        // Ramp over one block, starting with the second block
+        let ramp = |value, curve| {
+            processor
+                .message(
+                    parameter.clone(),
+                    AudioModuleCommand::Parameter(ParameterCommand::SetParameter(value)),
+                    Some(CommandTime::Frame(64)),
+                    Some(Ramp {
+                        duration: 64.0 / 44100.0,
+                        curve,
+                    }),
+                )
+                .unwrap();
+        };
+
+        let mut process = || {
+            let mut left = [0.0; MAX_BUFFER_SIZE];
+            let mut right = [0.0; MAX_BUFFER_SIZE];
+            let mut output = [0.0; MAX_BUFFER_SIZE];
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+            left
+        };
+
+        // A change with an instant ramp ends the fade in of the parameter
+        processor
+            .message(
+                parameter.clone(),
+                AudioModuleCommand::Parameter(ParameterCommand::SetParameter(1.0)),
+                None,
+                Some(Ramp {
+                    duration: 0.0,
+                    curve: Curve::Linear,
+                }),
+            )
+            .unwrap();
+        ramp(4.0, Curve::Exponential);
+        assert!(process().iter().all(|&sample| sample == 1.0));
+
+        // Doubles every half of the ramp
+        let left = process();
+        assert!((left[31] - 2.0).abs() < 1.0e-5);
+        assert_eq!(left[63], 4.0);
+        assert!(left.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(process().iter().all(|&sample| sample == 4.0));
+    }
+}
@@ -2,14 +2,16 @@
 
 use fundsp::buffer::Buffer;
 use fundsp::prelude::*;
+use petgraph::algo::has_path_connecting;
 use petgraph::stable_graph::{EdgeIndex, StableGraph};
 use petgraph::visit::EdgeRef;
 use petgraph::visit::Reversed;
-use petgraph::visit::{DfsPostOrder, Visitable};
-use petgraph::EdgeDirection::Incoming;
+use petgraph::visit::{DfsPostOrder, EdgeFiltered, IntoEdgeReferences, NodeIndexable, Visitable};
+use petgraph::EdgeDirection::{Incoming, Outgoing};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
-use crate::context::{GeneralContext, ModuleContext};
-use crate::module::{ModuleUnit, NoOp};
+use crate::module::ModuleUnit;
 
 pub type PortIndex = usize;
 pub type NodeIndex = petgraph::stable_graph::NodeIndex;
@@ -18,15 +20,61 @@ const ID: u64 = 62;
 
 #[derive(Clone, Copy)]
 pub struct Edge {
-    /// Output port.
+    /// First output channel.
     pub source: PortIndex,
-    /// Input port.
+    /// First input channel.
     pub target: PortIndex,
+    /// Number of output channels carried by the edge.
+    pub source_channels: usize,
+    /// Number of input channels fed by the edge.
+    /// A mono source is copied to every input channel, while a polyphonic source
+    /// feeding fewer input channels wraps around and is combined by the sum mode.
+    pub target_channels: usize,
+    /// Feedback edges close a cycle in the graph. They read the output of their
+    /// source from the previous tick (or block), acting as a unit delay.
+    pub feedback: bool,
+}
+
+/// How signals are combined when several edges are connected to the same input.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[ts(export)]
+pub enum SumMode {
+    /// Add the signals together, like a passive mult into a summing input.
+    #[default]
+    Sum,
+    /// Average the signals.
+    Average,
+    /// Take the highest signal.
+    Max,
+    /// Only use the most recently connected edge.
+    Last,
+}
+
+impl Edge {
+    /// Pairs of (output channel, input channel) carried by the edge.
+    pub fn channel_pairs(&self) -> impl Iterator<Item = (PortIndex, PortIndex)> {
+        let Edge {
+            source,
+            target,
+            source_channels,
+            target_channels,
+            ..
+        } = *self;
+
+        (0..std::cmp::max(source_channels, target_channels))
+            .map(move |i| (source + i % source_channels, target + i % target_channels))
+    }
 }
 
 /// Create an edge from source to target.
 pub fn edge(source: PortIndex, target: PortIndex) -> Edge {
-    Edge { source, target }
+    Edge {
+        source,
+        target,
+        source_channels: 1,
+        target_channels: 1,
+        feedback: false,
+    }
 }
 
 /// Individual AudioUnits are vertices in the graph.
@@ -41,12 +89,12 @@ pub struct Node32 {
     pub tick_input: Vec<f32>,
     /// Output for tick iteration. The length indicates the number of outputs.
     pub tick_output: Vec<f32>,
-    /// Context for the audo module
-    pub context: GeneralContext,
+    /// How multiple edges are combined on each input.
+    pub sum_modes: Vec<SumMode>,
 }
 
 impl Node32 {
-    pub fn new(unit: ModuleUnit, context: GeneralContext) -> Self {
+    pub fn new(unit: ModuleUnit) -> Self {
         let inputs = unit.inputs();
         let outputs = unit.outputs();
 
@@ -56,7 +104,7 @@ impl Node32 {
             output: Buffer::with_size(outputs),
             tick_input: vec![0.0; inputs],
             tick_output: vec![0.0; outputs],
-            context,
+            sum_modes: vec![SumMode::default(); inputs],
         }
     }
     pub fn inputs(&self) -> usize {
@@ -68,6 +116,82 @@ impl Node32 {
     }
 }
 
+/// Passes any number of channels through unchanged.
+/// Used for the global input and output of the network.
+pub(crate) struct Passthrough(pub usize);
+
+impl AudioUnit32 for Passthrough {
+    fn reset(&mut self, _sample_rate: Option<f64>) {}
+
+    fn tick(&mut self, input: &[f32], output: &mut [f32]) {
+        output.copy_from_slice(input);
+    }
+
+    fn process(&mut self, size: usize, input: &[&[f32]], output: &mut [&mut [f32]]) {
+        for channel in 0..self.0 {
+            output[channel][..size].copy_from_slice(&input[channel][..size]);
+        }
+    }
+
+    fn inputs(&self) -> usize {
+        self.0
+    }
+
+    fn outputs(&self) -> usize {
+        self.0
+    }
+
+    fn route(&self, input: &SignalFrame, _frequency: f64) -> SignalFrame {
+        input.clone()
+    }
+
+    fn get_id(&self) -> u64 {
+        0
+    }
+}
+
+/// Precompiled processing step for one node of the graph.
+struct Step {
+    /// Node to process.
+    node: NodeIndex,
+    /// Incoming edges as (source node, output channel, input channel).
+    edges: Vec<(NodeIndex, PortIndex, PortIndex)>,
+    /// Number of edges attached to each input.
+    totals: Vec<usize>,
+    /// How the edges are combined on each input.
+    modes: Vec<SumMode>,
+}
+
+impl Step {
+    /// Value an input starts from before its edges are combined.
+    #[inline]
+    fn initial(&self, input: PortIndex) -> f32 {
+        if self.modes[input] == SumMode::Max && self.totals[input] > 0 {
+            f32::NEG_INFINITY
+        } else {
+            0.0
+        }
+    }
+
+    /// Combine a sample from an edge into the input.
+    #[inline]
+    fn combine(&self, input: PortIndex, sample: &mut f32, new: f32) {
+        match self.modes[input] {
+            SumMode::Max => *sample = sample.max(new),
+            _ => *sample += new,
+        }
+    }
+
+    /// Scale applied to an input after its edges are combined.
+    #[inline]
+    fn scale(&self, input: PortIndex) -> Option<f32> {
+        match (self.modes[input], self.totals[input]) {
+            (SumMode::Average, total) if total > 1 => Some(1.0 / total as f32),
+            _ => None,
+        }
+    }
+}
+
 /// Network unit. It can contain other units and maintain connections between them.
 /// Outputs of the network are sourced from user specified unit outputs or global inputs.
 pub struct Graph32 {
@@ -77,36 +201,46 @@ pub struct Graph32 {
     global_input: NodeIndex,
     /// Node representing global input
     global_output: NodeIndex,
-    visitor: DfsPostOrder<NodeIndex, <StableGraph<Node32, Edge> as Visitable>::Map>,
+    /// Processing schedule, recompiled whenever the topology changes.
+    schedule: Vec<Step>,
     sample_rate: f64,
+    /// Seed of the random sources of every node, set with `set_hash`.
+    seed: u64,
+    /// Set while a batch of edits is applied, so the schedule is compiled once at its end.
+    deferred: bool,
+    /// Whether an edit of the batch changed the graph, batches of commands only are not compiled.
+    stale: bool,
 }
 
 impl Graph32 {
     /// Create a new network with the given number of inputs and outputs.
     /// The number of inputs and outputs is fixed after construction.
     pub fn new<I: Size<f32> + Send, O: Size<f32> + Send>() -> Self {
-        let mut graph: StableGraph<Node32, Edge> = Default::default();
+        Self::with_ports(I::USIZE, O::USIZE)
+    }
 
-        let input: An<MultiPass<I, f32>> = An(MultiPass::default());
-        let output: An<MultiPass<O, f32>> = An(MultiPass::default());
+    /// Create a new network with a number of inputs and outputs chosen at runtime.
+    /// The global input and output are always nodes 0 and 1.
+    pub fn with_ports(inputs: usize, outputs: usize) -> Self {
+        let mut graph: StableGraph<Node32, Edge> = Default::default();
 
-        let global_input = graph.add_node(Node32::new(
-            Box::new(input),
-            ModuleContext::<NoOp, NoOp>::default().boxed(),
-        ));
+        let global_input = graph.add_node(Node32::new(Box::new(Passthrough(inputs))));
 
-        let global_output = graph.add_node(Node32::new(
-            Box::new(output),
-            ModuleContext::<NoOp, NoOp>::default().boxed(),
-        ));
+        let global_output = graph.add_node(Node32::new(Box::new(Passthrough(outputs))));
 
-        Self {
+        let mut network = Self {
             graph,
-            visitor: Default::default(),
             global_input,
             global_output,
+            schedule: vec![],
             sample_rate: DEFAULT_SR,
-        }
+            seed: 0,
+            deferred: false,
+            stale: false,
+        };
+
+        network.compile();
+        network
     }
 
     pub fn get_mod_mut(&mut self, id: NodeIndex) -> Option<&mut Node32> {
@@ -119,15 +253,109 @@ impl Graph32 {
 
     /// Add a new unit to the network. Return its ID handle.
     /// ID handles are always consecutive numbers starting from zero.
-    /// The unit is reset with the sample rate of the network.
-    pub fn add(&mut self, unit: ModuleUnit, context: GeneralContext) -> NodeIndex {
-        let node = Node32::new(unit, context);
+    /// The unit is seeded and reset with the sample rate of the network.
+    pub fn add(&mut self, unit: ModuleUnit) -> NodeIndex {
+        let node = Node32::new(unit);
+
+        let id = self.graph.add_node(node);
+        let node = &mut self.graph[id];
+        seed_unit(&mut node.unit, self.seed, id);
+        node.unit.reset(Some(self.sample_rate));
 
-        self.graph.add_node(node)
+        self.compile();
+        id
+    }
+
+    /// Add a unit with the given id, which must not be in use. Returns false when it is.
+    /// Placeholders are added until the id is handed out, then removed in reverse order
+    /// so that the ids handed out next are unchanged, as done by `Topology::add_at`.
+    pub fn add_at(&mut self, id: NodeIndex, unit: ModuleUnit) -> bool {
+        if self.graph.contains_node(id) {
+            return false;
+        }
+
+        let mut placeholders = vec![];
+        loop {
+            let added = self.graph.add_node(Node32::new(Box::new(Passthrough(0))));
+            if added == id {
+                break;
+            }
+            placeholders.push(added);
+        }
+
+        let mut node = Node32::new(unit);
+        seed_unit(&mut node.unit, self.seed, id);
+        node.unit.reset(Some(self.sample_rate));
+        self.graph[id] = node;
+
+        for placeholder in placeholders.into_iter().rev() {
+            self.graph.remove_node(placeholder);
+        }
+
+        self.compile();
+        true
+    }
+
+    /// Connect a polyphonic cable with the given id, which must not be in use.
+    /// Works like `add_at`, with placeholder edges carrying no channels.
+    /// Returns false when the id is in use or either node is missing.
+    pub fn connect_at(
+        &mut self,
+        id: EdgeIndex,
+        source: NodeIndex,
+        source_channels: (PortIndex, usize),
+        target: NodeIndex,
+        target_channels: (PortIndex, usize),
+    ) -> bool {
+        if self.graph.edge_weight(id).is_some()
+            || !self.graph.contains_node(source)
+            || !self.graph.contains_node(target)
+        {
+            return false;
+        }
+
+        let placeholder = Edge {
+            source_channels: 0,
+            target_channels: 0,
+            feedback: true,
+            ..edge(0, 0)
+        };
+        let mut placeholders = vec![];
+        loop {
+            let added = self
+                .graph
+                .add_edge(self.global_input, self.global_input, placeholder);
+            if added == id {
+                break;
+            }
+            placeholders.push(added);
+        }
+
+        // Removed ids are handed out again last in first out
+        self.graph.remove_edge(id);
+        let added = self.add_edge(source, source_channels, target, target_channels);
+
+        for placeholder in placeholders.into_iter().rev() {
+            self.graph.remove_edge(placeholder);
+        }
+
+        added == id
+    }
+
+    /// Apply several edits, compiling the schedule once they are all applied.
+    pub fn batch<R>(&mut self, edits: impl FnOnce(&mut Self) -> R) -> R {
+        let deferred = std::mem::replace(&mut self.deferred, true);
+        let result = edits(self);
+        self.deferred = deferred;
+        if std::mem::take(&mut self.stale) {
+            self.compile();
+        }
+        result
     }
 
     /// Connect the given unit output (`source`, `source_port`)
     /// to the given unit input (`target`, `target_port`).
+    /// If the connection closes a cycle it becomes a feedback edge, see `is_feedback`.
     pub fn connect(
         &mut self,
         source: NodeIndex,
@@ -135,20 +363,173 @@ impl Graph32 {
         target: NodeIndex,
         target_port: PortIndex,
     ) -> EdgeIndex {
+        self.add_edge(source, (source_port, 1), target, (target_port, 1))
+    }
+
+    /// Connect a polyphonic cable from the output channels (`source`, `source_channels`)
+    /// to the input channels (`target`, `target_channels`), each given as (first channel, count).
+    pub fn connect_channels(
+        &mut self,
+        source: NodeIndex,
+        source_channels: (PortIndex, usize),
+        target: NodeIndex,
+        target_channels: (PortIndex, usize),
+    ) -> EdgeIndex {
+        self.add_edge(source, source_channels, target, target_channels)
+    }
+
+    pub fn remove(&mut self, node: NodeIndex) -> bool {
+        let removed = self.graph.remove_node(node).is_some();
+        self.reclassify();
+        self.compile();
+        removed
+    }
+
+    /// disconnect the given unit output (`source`, `source_port`)
+    pub fn disconnect(&mut self, edge: EdgeIndex) -> bool {
+        let removed = self.graph.remove_edge(edge).is_some();
+        self.reclassify();
+        self.compile();
+        removed
+    }
+
+    /// Set how multiple edges connected to the input (`node`, `port`) are combined.
+    pub fn set_sum_mode(&mut self, node: NodeIndex, port: PortIndex, mode: SumMode) -> bool {
+        match self.graph.node_weight_mut(node) {
+            Some(data) if port < data.inputs() => {
+                data.sum_modes[port] = mode;
+                self.compile();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the edge closes a cycle and is delayed by one tick (or one block).
+    pub fn is_feedback(&self, edge: EdgeIndex) -> Option<bool> {
+        self.graph.edge_weight(edge).map(|edge| edge.feedback)
+    }
+
+    /// Add an edge, marking it as feedback when `target` already reaches `source`.
+    /// Feedback edges stay delayed while other edges are added, so node order and latency
+    /// stay fixed until their cycle is removed, see `reclassify`.
+    fn add_edge(
+        &mut self,
+        source: NodeIndex,
+        (source_port, source_channels): (PortIndex, usize),
+        target: NodeIndex,
+        (target_port, target_channels): (PortIndex, usize),
+    ) -> EdgeIndex {
+        let forward = EdgeFiltered::from_fn(&self.graph, |edge| !edge.weight().feedback);
+        let feedback = source == target || has_path_connecting(&forward, target, source, None);
+
         let edge = Edge {
             source: source_port,
             target: target_port,
+            source_channels,
+            target_channels,
+            feedback,
         };
-        self.graph.add_edge(source, target, edge)
+        let id = self.graph.add_edge(source, target, edge);
+
+        self.compile();
+        id
     }
 
-    pub fn remove(&mut self, node: NodeIndex) -> bool {
-        self.graph.remove_node(node).is_some()
+    /// Turn feedback edges that no longer close a cycle back into forward edges,
+    /// once an edge or node of their cycle is removed. Self edges stay feedback edges.
+    fn reclassify(&mut self) {
+        let feedback = self
+            .graph
+            .edge_indices()
+            .filter(|edge| self.graph[*edge].feedback)
+            .collect::<Vec<_>>();
+
+        for edge in feedback {
+            let (source, target) = match self.graph.edge_endpoints(edge) {
+                Some((source, target)) if source != target => (source, target),
+                _ => continue,
+            };
+
+            let forward = EdgeFiltered::from_fn(&self.graph, |edge| !edge.weight().feedback);
+            if !has_path_connecting(&forward, target, source, None) {
+                self.graph[edge].feedback = false;
+            }
+        }
     }
 
-    /// disconnect the given unit output (`source`, `source_port`)
-    pub fn disconnect(&mut self, edge: EdgeIndex) -> bool {
-        self.graph.remove_edge(edge).is_some()
+    /// Compile the processing schedule.
+    /// Only nodes that reach the global output are processed. They are ordered
+    /// so that every node runs after the sources of its non-feedback inputs.
+    /// Processing walks the schedule without allocating on the audio thread.
+    fn compile(&mut self) {
+        if self.deferred {
+            self.stale = true;
+            return;
+        }
+
+        let mut visitor: DfsPostOrder<NodeIndex, <StableGraph<Node32, Edge> as Visitable>::Map> =
+            DfsPostOrder::new(Reversed(&self.graph), self.global_output);
+
+        let mut reachable = vec![false; self.graph.node_bound()];
+        while let Some(node) = visitor.next(Reversed(&self.graph)) {
+            reachable[node.index()] = true;
+        }
+
+        // Kahn's algorithm over the forward edges, visiting nodes by index so the
+        // order only depends on the topology.
+        let mut pending = vec![0_usize; self.graph.node_bound()];
+        for edge in self.graph.edge_references() {
+            if !edge.weight().feedback && reachable[edge.source().index()] {
+                pending[edge.target().index()] += 1;
+            }
+        }
+
+        let mut ready: Vec<NodeIndex> = self
+            .graph
+            .node_indices()
+            .filter(|node| reachable[node.index()] && pending[node.index()] == 0)
+            .collect();
+        ready.reverse();
+
+        self.schedule.clear();
+        while let Some(node) = ready.pop() {
+            let modes = self.graph[node].sum_modes.clone();
+            let mut totals = vec![0; modes.len()];
+
+            // Incoming edges are listed from the most recently connected.
+            // Polyphonic edges are expanded into one entry per channel.
+            let mut edges = vec![];
+            for edge in self.graph.edges_directed(node, Incoming) {
+                for (output, input) in edge.weight().channel_pairs() {
+                    if modes[input] == SumMode::Last && totals[input] > 0 {
+                        continue;
+                    }
+                    totals[input] += 1;
+                    edges.push((edge.source(), output, input));
+                }
+            }
+
+            self.schedule.push(Step {
+                node,
+                edges,
+                totals,
+                modes,
+            });
+
+            let mut next: Vec<NodeIndex> = vec![];
+            for edge in self.graph.edges_directed(node, Outgoing) {
+                if edge.weight().feedback || !reachable[edge.target().index()] {
+                    continue;
+                }
+                pending[edge.target().index()] -= 1;
+                if pending[edge.target().index()] == 0 {
+                    next.push(edge.target());
+                }
+            }
+            next.sort();
+            ready.extend(next.into_iter().rev());
+        }
     }
 
     /// Connect the node input (`target`, `target_port`)
@@ -159,11 +540,12 @@ impl Graph32 {
         target: NodeIndex,
         target_port: PortIndex,
     ) -> EdgeIndex {
-        let edge = Edge {
-            source: global_input,
-            target: target_port,
-        };
-        self.graph.add_edge(self.global_input, target, edge)
+        self.add_edge(
+            self.global_input,
+            (global_input, 1),
+            target,
+            (target_port, 1),
+        )
     }
 
     /// Pipe global input to node `target`.
@@ -185,11 +567,12 @@ impl Graph32 {
         source_port: PortIndex,
         global_output: PortIndex,
     ) {
-        let edge = Edge {
-            source: source_port,
-            target: global_output,
-        };
-        self.graph.add_edge(source, self.global_output, edge);
+        self.add_edge(
+            source,
+            (source_port, 1),
+            self.global_output,
+            (global_output, 1),
+        );
     }
 
     /// Pipe node outputs to global outputs.
@@ -250,8 +633,6 @@ impl AudioUnit32 for Graph32 {
     }
 
     fn tick(&mut self, input: &[f32], output: &mut [f32]) {
-        const NO_NODE: &str = "no node exists for the given index";
-
         // Write inputs to input graph node
         let input_node = self
             .graph
@@ -262,59 +643,34 @@ impl AudioUnit32 for Graph32 {
             input_node.tick_input[channel] = input[channel];
         });
 
-        self.visitor.reset(Reversed(&self.graph));
-        self.visitor.move_to(self.global_output);
-
         // Walk the graph
-        while let Some(node) = self.visitor.next(Reversed(&self.graph)) {
-            let visit_node_data = self.graph.node_weight_mut(node).expect(NO_NODE);
-
-            let visit_node_inputs = visit_node_data.inputs();
-
-            let raw_node_data: *mut _ = &mut *visit_node_data;
-
-            // Collects input buffers
-            let mut in_buffers: Vec<f32> = vec![0.0; visit_node_inputs];
-            let mut in_totals: Vec<usize> = vec![0; visit_node_inputs];
-
-            for incoming_edge_ref in self.graph.edges_directed(node, Incoming) {
-                // Skip edges that connect the node to itself to avoid aliasing `node`.
-                if node == incoming_edge_ref.source() {
-                    continue;
+        for step in self.schedule.iter() {
+            if step.node != self.global_input {
+                let node = &mut self.graph[step.node];
+                for (input, sample) in node.tick_input.iter_mut().enumerate() {
+                    *sample = step.initial(input);
                 }
-                let incoming_node_data = self
-                    .graph
-                    .node_weight(incoming_edge_ref.source())
-                    .expect("No node found");
-
-                let incoming_edge_data = self
-                    .graph
-                    .edge_weight(incoming_edge_ref.id())
-                    .expect("No edge found");
+            }
 
-                in_totals[incoming_edge_data.target] += 1;
-                in_buffers[incoming_edge_data.target] +=
-                    incoming_node_data.tick_output[incoming_edge_data.source];
+            // Collect inputs. Feedback edges read the output of the previous tick,
+            // as their source is ordered after this node or is this node.
+            for &(source, source_port, target_port) in step.edges.iter() {
+                let new = self.graph[source].tick_output[source_port];
+                let sample = &mut self.graph[step.node].tick_input[target_port];
+                step.combine(target_port, sample, new);
             }
 
+            let node = &mut self.graph[step.node];
+
             // Average signals when multiple edges are attached to the same input
-            for (sample, total) in in_buffers.iter_mut().zip(in_totals) {
-                if total > 0 {
-                    *sample /= total as f32
+            for (input, sample) in node.tick_input.iter_mut().enumerate() {
+                if let Some(scale) = step.scale(input) {
+                    *sample *= scale
                 }
             }
 
-            // Here we deference our raw pointer to the `Node32`. The only references to the graph at
-            // this point in time are the input references and the node itself. We know that the input
-            // references do not alias our node's mutable reference as we explicitly check for it while
-            // looping through the inputs above.
-            let visit_node_data = unsafe { &mut *raw_node_data };
-
             // Process each node
-            visit_node_data.unit.tick(
-                &in_buffers[..visit_node_data.unit.inputs()],
-                visit_node_data.tick_output.as_mut(),
-            );
+            node.unit.tick(&node.tick_input, &mut node.tick_output);
         }
 
         // Collect outputs from output graph node
@@ -329,8 +685,6 @@ impl AudioUnit32 for Graph32 {
     }
 
     fn process(&mut self, size: usize, input: &[&[f32]], output: &mut [&mut [f32]]) {
-        const NO_NODE: &str = "no node exists for the given index";
-
         // Write inputs to input graph node
         let input_node = self
             .graph
@@ -338,67 +692,58 @@ impl AudioUnit32 for Graph32 {
             .expect("No global input node");
 
         (0..input.len()).for_each(|channel| {
-            input_node
-                .input
-                .mut_at(channel)
-                .copy_from_slice(&input[channel][..size]);
+            input_node.input.mut_at(channel)[..size].copy_from_slice(&input[channel][..size]);
         });
 
-        self.visitor.reset(Reversed(&self.graph));
-        self.visitor.move_to(self.global_output);
-
         // Walk the graph
-        while let Some(node) = self.visitor.next(Reversed(&self.graph)) {
-            let visit_node_data = self.graph.node_weight_mut(node).expect(NO_NODE);
-            let visit_node_inputs = visit_node_data.inputs();
-
-            let raw_node_data: *mut _ = &mut *visit_node_data;
-
-            // Collects input buffers
-            let mut in_buffers = Buffer::with_size(visit_node_inputs);
-            let mut in_totals: Vec<usize> = vec![0; visit_node_inputs];
-            for incoming_edge_ref in self.graph.edges_directed(node, Incoming) {
-                // Skip edges that connect the node to itself to avoid aliasing `node`.
-                if node == incoming_edge_ref.source() {
-                    continue;
+        for step in self.schedule.iter() {
+            if step.node != self.global_input {
+                let node = &mut self.graph[step.node];
+                for channel in 0..node.inputs() {
+                    node.input.mut_at(channel)[..size].fill(step.initial(channel));
                 }
-                let incoming_node = self
-                    .graph
-                    .node_weight(incoming_edge_ref.source())
-                    .expect("No node found");
-
-                let incoming_edge = self
-                    .graph
-                    .edge_weight(incoming_edge_ref.id())
-                    .expect("No edge found");
+            }
 
-                in_totals[incoming_edge.target] += 1;
-                in_buffers
-                    .mut_at(incoming_edge.target)
+            // Collect input buffers. Feedback edges read the output of the previous block,
+            // as their source is ordered after this node or is this node.
+            for &(source, source_port, target_port) in step.edges.iter() {
+                let (source_output, target_input) = if source == step.node {
+                    let node = &mut self.graph[step.node];
+                    (node.output.at(source_port), node.input.mut_at(target_port))
+                } else {
+                    let (source_node, target_node) = self.graph.index_twice_mut(source, step.node);
+                    (
+                        source_node.output.at(source_port),
+                        target_node.input.mut_at(target_port),
+                    )
+                };
+
+                target_input[..size]
                     .iter_mut()
-                    .zip(incoming_node.output.at(incoming_edge.source))
-                    .for_each(|(sample, new)| *sample += new);
+                    .zip(&source_output[..size])
+                    .for_each(|(sample, new)| step.combine(target_port, sample, *new));
             }
 
+            let Node32 {
+                unit,
+                input: in_buffers,
+                output: out_buffers,
+                ..
+            } = &mut self.graph[step.node];
+
             // Average signals when multiple edges are attached to the same input
-            for (buffer, total) in in_buffers.self_mut().iter_mut().zip(in_totals) {
-                if total > 0 {
-                    buffer.iter_mut().for_each(|sample| *sample /= total as f32);
+            for channel in 0..step.totals.len() {
+                if let Some(scale) = step.scale(channel) {
+                    in_buffers.mut_at(channel)[..size]
+                        .iter_mut()
+                        .for_each(|sample| *sample *= scale);
                 }
             }
 
-            // Here we deference our raw pointer to the `Node32`. The only references to the graph at
-            // this point in time are the input references and the node itself. We know that the input
-            // references do not alias our node's mutable reference as we explicitly check for it while
-            // looping through the inputs above.
-            let visit_node_data = unsafe { &mut *raw_node_data };
-
-            visit_node_data.unit.process(
+            unit.process(
                 size,
-                in_buffers.get_ref(visit_node_data.unit.inputs()),
-                visit_node_data
-                    .output
-                    .get_mut(visit_node_data.unit.outputs()),
+                in_buffers.get_ref(unit.inputs()),
+                out_buffers.get_mut(unit.outputs()),
             );
         }
 
@@ -417,23 +762,24 @@ impl AudioUnit32 for Graph32 {
         ID
     }
 
+    /// Seed the random sources of every node. Nodes draw from the new seed
+    /// once they are reset, or straight away for those seeding a generator on `set_hash`.
     fn set_hash(&mut self, hash: u64) {
-        let mut hash = AttoRand::new(hash);
+        self.seed = hash;
 
-        for node in self.graph.node_weights_mut() {
-            node.unit.set_hash(hash.get())
+        for index in 0..self.graph.node_bound() {
+            let id = NodeIndex::new(index);
+            if let Some(node) = self.graph.node_weight_mut(id) {
+                seed_unit(&mut node.unit, hash, id);
+            }
         }
     }
+
     fn ping(&mut self, probe: bool, hash: AttoRand) -> AttoRand {
         if !probe {
             self.set_hash(hash.value())
         }
-
-        let mut hash = hash.hash(ID);
-        for node in self.graph.node_weights_mut() {
-            hash = node.unit.ping(probe, hash);
-        }
-        hash
+        hash.hash(ID)
     }
 
     /// Route constants, latencies and frequency responses at `frequency` Hz
@@ -442,12 +788,6 @@ impl AudioUnit32 for Graph32 {
     fn route(&self, input: &SignalFrame, frequency: f64) -> SignalFrame {
         const NO_NODE: &str = "no node exists for the given index";
 
-        let mut visitor: DfsPostOrder<NodeIndex, <StableGraph<Node32, Edge> as Visitable>::Map> =
-            Default::default();
-
-        visitor.reset(Reversed(&self.graph));
-        visitor.move_to(self.global_output);
-
         let mut inner_signal: Vec<SignalFrame> = vec![];
         for node in self.graph.node_weights() {
             inner_signal.push(new_signal_frame(node.unit.outputs()));
@@ -456,25 +796,27 @@ impl AudioUnit32 for Graph32 {
         inner_signal[self.global_input.index()] = input.clone();
 
         // Walk the graph
-        while let Some(node) = visitor.next(Reversed(&self.graph)) {
+        for step in self.schedule.iter() {
+            let node = step.node;
             let data = self.graph.node_weight(node).expect(NO_NODE);
             let inputs = data.inputs();
 
             let mut input_signal = new_signal_frame(inputs);
 
             for edge_ref in self.graph.edges_directed(node, Incoming) {
-                // Skip edges that connect the node to itself to avoid aliasing `node`.
-                if node == edge_ref.source() {
-                    continue;
-                }
-
                 let edge_data = self
                     .graph
                     .edge_weight(edge_ref.id())
                     .expect("No edge found");
 
-                input_signal[node.index()] =
-                    inner_signal[edge_ref.source().index()][edge_data.source];
+                // Feedback edges have not been routed yet at this point.
+                if edge_data.feedback {
+                    continue;
+                }
+
+                for (output, input) in edge_data.channel_pairs() {
+                    input_signal[input] = inner_signal[edge_ref.source().index()][output];
+                }
             }
 
             inner_signal[node.index()] = data.unit.route(&input_signal, frequency);
@@ -505,6 +847,12 @@ impl AudioUnit32 for Graph32 {
     }
 }
 
+/// Seed a unit from the seed of its graph and its id, so a node draws the same
+/// randomness whichever order the nodes of the graph are added in.
+fn seed_unit(unit: &mut ModuleUnit, seed: u64, id: NodeIndex) {
+    unit.ping(false, AttoRand::new(seed).hash(id.index() as u64));
+}
+
 #[test]
 fn test_basic() {
     /// Check that the stereo generator given is rendered identically
@@ -527,13 +875,303 @@ fn test_basic() {
     }
 
     let mut graph = Graph32::new::<U0, U2>();
-    let id = graph.add(
-        Box::new(noise() >> moog_hz(1500.0, 0.8) | noise() >> moog_hz(500.0, 0.4)),
-        ModuleContext::<NoOp, NoOp>::default().boxed(),
-    );
+    let id = graph.add(Box::new(
+        noise() >> moog_hz(1500.0, 0.8) | noise() >> moog_hz(500.0, 0.4),
+    ));
 
     graph.connect_output(id, 0, 1);
     graph.connect_output(id, 1, 1);
 
     check_wave(graph)
 }
+
+#[test]
+fn test_feedback() {
+    // Node 1 adds a constant to the output of node 2, which passes it straight back.
+    fn feedback_graph() -> (Graph32, EdgeIndex) {
+        let mut graph = Graph32::new::<U0, U1>();
+        let constant = graph.add(Box::new(dc(1.0)));
+        let sum = graph.add(Box::new(pass() + pass()));
+        let loopback = graph.add(Box::new(pass()));
+
+        graph.connect(constant, 0, sum, 0);
+        graph.connect(sum, 0, loopback, 0);
+        let back_edge = graph.connect(loopback, 0, sum, 1);
+        graph.connect_output(sum, 0, 0);
+
+        (graph, back_edge)
+    }
+
+    // Single sample processing delays the feedback by one sample
+    let (mut graph, back_edge) = feedback_graph();
+    assert_eq!(graph.is_feedback(back_edge), Some(true));
+    for expected in 1..=8 {
+        assert_eq!(graph.get_mono(), expected as f32);
+    }
+
+    // Block processing delays the feedback by one block
+    let (mut graph, _) = feedback_graph();
+    let mut output = [0.0; 4];
+    for expected in 1..=8 {
+        graph.process(4, &[], &mut [&mut output]);
+        assert_eq!(output, [expected as f32; 4]);
+    }
+
+    // Self edges are delayed in the same way
+    let mut graph = Graph32::new::<U0, U1>();
+    let sum = graph.add(Box::new(pass() + dc(1.0)));
+    let self_edge = graph.connect(sum, 0, sum, 0);
+    graph.connect_output(sum, 0, 0);
+
+    assert_eq!(graph.is_feedback(self_edge), Some(true));
+    assert_eq!(graph.get_mono(), 1.0);
+    assert_eq!(graph.get_mono(), 2.0);
+}
+
+#[test]
+fn test_feedback_removed_with_cycle() {
+    let mut graph = Graph32::new::<U0, U1>();
+    let constant = graph.add(Box::new(dc(1.0)));
+    let sum = graph.add(Box::new(pass() + pass()));
+    let loopback = graph.add(Box::new(pass()));
+
+    graph.connect(constant, 0, loopback, 0);
+    let forward_edge = graph.connect(sum, 0, loopback, 0);
+    let back_edge = graph.connect(loopback, 0, sum, 1);
+    graph.connect_output(sum, 0, 0);
+    assert_eq!(graph.is_feedback(back_edge), Some(true));
+
+    // Once the cycle is broken the edge is no longer delayed by a block
+    assert!(graph.disconnect(forward_edge));
+    assert_eq!(graph.is_feedback(back_edge), Some(false));
+
+    let mut output = [0.0; 4];
+    graph.process(4, &[], &mut [&mut output]);
+    assert_eq!(output, [1.0; 4]);
+}
+
+#[test]
+fn test_sum_modes() {
+    let mut graph = Graph32::new::<U0, U1>();
+    let mix = graph.add(Box::new(pass()));
+    for value in [1.0, 3.0, 2.0] {
+        let constant = graph.add(Box::new(dc(value)));
+        graph.connect(constant, 0, mix, 0);
+    }
+    graph.connect_output(mix, 0, 0);
+
+    for (mode, expected) in [
+        (SumMode::Sum, 6.0),
+        (SumMode::Average, 2.0),
+        (SumMode::Max, 3.0),
+        (SumMode::Last, 2.0),
+    ] {
+        assert!(graph.set_sum_mode(mix, 0, mode));
+
+        assert_eq!(graph.get_mono(), expected);
+
+        let mut output = [0.0; 4];
+        graph.process(4, &[], &mut [&mut output]);
+        assert_eq!(output, [expected; 4]);
+    }
+
+    // Only existing inputs have a mode
+    assert!(!graph.set_sum_mode(mix, 1, SumMode::Sum));
+}
+
+#[test]
+fn test_poly_cables() {
+    let mut graph = Graph32::new::<U0, U4>();
+
+    // Mono source is copied to every channel of a poly input
+    let mono = graph.add(Box::new(dc(1.0)));
+    let poly = graph.add(Box::new(multipass::<U3, f32>()));
+    graph.connect_channels(mono, (0, 1), poly, (0, 3));
+
+    // Poly source feeds matching channels
+    let offsets = graph.add(Box::new(dc((1.0, 2.0, 3.0))));
+    graph.connect_channels(offsets, (0, 3), poly, (0, 3));
+    for channel in 0..3 {
+        graph.connect_output(poly, channel, channel);
+    }
+
+    // Poly source into a mono input is combined by its sum mode
+    let mix = graph.add(Box::new(pass()));
+    graph.connect_channels(poly, (0, 3), mix, (0, 1));
+    graph.connect_output(mix, 0, 3);
+
+    let mut output = [0.0; 4];
+    graph.tick(&[], &mut output);
+    assert_eq!(output, [2.0, 3.0, 4.0, 9.0]);
+
+    graph.set_sum_mode(mix, 0, SumMode::Max);
+    let mut output = [[0.0; 4]; 4];
+    let [a, b, c, d] = &mut output;
+    graph.process(4, &[], &mut [a, b, c, d]);
+    assert_eq!(output, [[2.0; 4], [3.0; 4], [4.0; 4], [4.0; 4]]);
+}
+
+#[cfg(test)]
+mod allocation {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use fundsp::prelude::*;
+
+    use super::Graph32;
+    use crate::context::GeneralContext;
+    use crate::module::{
+        envelope::EnvelopeParams, filter::FilterParams, lfo::LfoParams,
+        oscillator::OscillatorParams, subpatch::SubpatchParams, vca::VcaParams, AudioModuleType,
+        ModuleUnit,
+    };
+    use crate::{interface::time::CommandTime, AudioProcessor};
+
+    /// Counts allocations made by threads that opt in with `counting`.
+    struct CountingAllocator;
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if COUNTING.with(|counting| counting.get()) {
+                ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+            }
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Count the allocations made by `f` on the current thread.
+    fn counting<F: FnOnce()>(f: F) -> usize {
+        ALLOCATIONS.with(|allocations| allocations.set(0));
+        COUNTING.with(|counting| counting.set(true));
+        f();
+        COUNTING.with(|counting| counting.set(false));
+        ALLOCATIONS.with(|allocations| allocations.get())
+    }
+
+    #[test]
+    fn test_process_does_not_allocate() {
+        let mut graph = Graph32::new::<U1, U2>();
+        let mut add = |module: AudioModuleType| {
+            let (unit, _context): (ModuleUnit, GeneralContext) = (&module).into();
+            graph.add(unit)
+        };
+
+        // 25 voices of lfo -> oscillator -> filter -> vca, with an envelope on each vca
+        let mut voices = vec![];
+        for _ in 0..25 {
+            let lfo = add(AudioModuleType::Lfo(LfoParams { bpm: 60.0 }));
+            let oscillator = add(AudioModuleType::Oscillator(OscillatorParams {
+                pitch: 2.0,
+                saw: 0.5,
+                ..Default::default()
+            }));
+            let filter = add(AudioModuleType::Filter(FilterParams {
+                frequency: 5.0,
+                q: 1.0,
+            }));
+            let envelope = add(AudioModuleType::Envelope(EnvelopeParams {
+                attack: 0.1,
+                decay: 0.1,
+                sustain: 0.5,
+                release: 0.1,
+            }));
+            let vca = add(AudioModuleType::Vca(VcaParams { value: 0.5 }));
+            voices.push((lfo, oscillator, filter, envelope, vca));
+        }
+        let output = add(AudioModuleType::Output);
+
+        for &(lfo, oscillator, filter, envelope, vca) in voices.iter() {
+            graph.connect(lfo, 0, oscillator, 1);
+            graph.connect(oscillator, 0, filter, 0);
+            graph.connect(filter, 0, vca, 0);
+            graph.connect(lfo, 0, envelope, 0);
+            graph.connect(envelope, 0, vca, 1);
+            graph.connect(vca, 0, output, 0);
+            graph.connect(vca, 0, output, 1);
+            // Feedback from the vca back into the filter cutoff
+            graph.connect(vca, 0, filter, 1);
+        }
+        graph.pipe_output(output);
+
+        let input = [0.0; MAX_BUFFER_SIZE];
+        let mut left = [0.0; MAX_BUFFER_SIZE];
+        let mut right = [0.0; MAX_BUFFER_SIZE];
+
+        // The first block may set up scratch space that is reused afterwards
+        graph.process(MAX_BUFFER_SIZE, &[&input], &mut [&mut left, &mut right]);
+
+        let allocations = counting(|| {
+            for _ in 0..10 {
+                graph.process(MAX_BUFFER_SIZE, &[&input], &mut [&mut left, &mut right]);
+            }
+        });
+
+        assert_eq!(allocations, 0);
+
+        // Sanity check that allocations are counted
+        assert_eq!(counting(|| drop(std::hint::black_box(vec![0.0_f32; 8]))), 1);
+    }
+
+    #[test]
+    fn test_nested_batches_do_not_allocate() {
+        let (processor, mut engine) = AudioProcessor::new();
+        processor
+            .create(
+                AudioModuleType::Subpatch(SubpatchParams {
+                    inputs: 1,
+                    outputs: 1,
+                }),
+                None,
+            )
+            .unwrap();
+
+        let mut outputs = [[0.0; MAX_BUFFER_SIZE]; 3];
+        let [left, right, scope] = &mut outputs;
+        engine.process(MAX_BUFFER_SIZE, &[], &mut [left, right, scope]);
+
+        // Every batch delivers a batch to the subpatch, empty here
+        processor.apply(vec![]).unwrap();
+        let allocations = counting(|| {
+            engine.process(MAX_BUFFER_SIZE, &[], &mut [left, right, scope]);
+        });
+
+        assert_eq!(allocations, 0);
+    }
+
+    #[test]
+    fn test_scheduling_does_not_allocate() {
+        let (processor, mut engine) = AudioProcessor::new();
+        let address = processor
+            .create(AudioModuleType::Vca(VcaParams { value: 0.5 }), None)
+            .unwrap();
+
+        let mut outputs = [[0.0; MAX_BUFFER_SIZE]; 3];
+        let [left, right, scope] = &mut outputs;
+        engine.process(MAX_BUFFER_SIZE, &[], &mut [left, right, scope]);
+
+        // Commands are sorted into the schedule by the audio thread
+        for frame in (0..100).rev() {
+            let at = Some(CommandTime::Frame(1_000_000 + frame));
+            processor
+                .set_param(address.clone(), "level", 1.0, at, None)
+                .unwrap();
+        }
+        let allocations = counting(|| {
+            engine.process(MAX_BUFFER_SIZE, &[], &mut [left, right, scope]);
+        });
+
+        assert_eq!(allocations, 0);
+    }
+}
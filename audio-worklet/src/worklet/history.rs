@@ -0,0 +1,123 @@
+//! Undo and redo of the edits made through `AudioProcessor`.
+
+use fundsp::hacker::Tag;
+
+use crate::{
+    graph::{PortIndex, SumMode},
+    interface::patch::{PatchConnection, PatchModule},
+    module::AudioModuleCommand,
+};
+
+/// Number of actions that can be undone.
+pub const HISTORY_LIMIT: usize = 100;
+
+/// A change to the topology of a subpatch, with modules and connections at exact ids.
+/// Using exact ids means undoing and redoing hands back the same addresses.
+#[derive(Clone)]
+pub enum Step {
+    /// Add a module with its params, sum modes and the inner patch of a subpatch
+    Add(PatchModule),
+    /// Remove a module by id
+    Remove(usize),
+    /// Add a connection
+    Connect(PatchConnection),
+    /// Remove a connection by id
+    Disconnect(usize),
+    /// Set how the connections to an input of a module are combined
+    SetSumMode(usize, PortIndex, SumMode),
+    /// Send a command to a module
+    Message(usize, AudioModuleCommand),
+    /// Set a tagged parameter of a module
+    SetParam(usize, Tag, f64),
+}
+
+/// Steps of one action, each along with the step that reverts it.
+/// Steps are applied to the subpatch at their path, or to the root topology when it is empty.
+#[derive(Default)]
+pub struct Entry {
+    steps: Vec<(Vec<usize>, Step, Step)>,
+}
+
+impl Entry {
+    pub fn push(&mut self, path: &[usize], step: Step, inverse: Step) {
+        self.steps.push((path.to_vec(), step, inverse));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Steps redoing the action, in order.
+    pub fn redo(&self) -> impl Iterator<Item = (&[usize], &Step)> {
+        self.steps
+            .iter()
+            .map(|(path, step, _)| (path.as_slice(), step))
+    }
+
+    /// Steps undoing the action, in order.
+    pub fn undo(&self) -> impl Iterator<Item = (&[usize], &Step)> {
+        self.steps
+            .iter()
+            .rev()
+            .map(|(path, _, inverse)| (path.as_slice(), inverse))
+    }
+}
+
+/// Actions that can be undone, and undone actions that can be redone.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+}
+
+impl History {
+    /// Record an action. Undone actions can no longer be redone.
+    pub fn record(&mut self, entry: Entry) {
+        if entry.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.undo.push(entry);
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Forget every action, e.g. when the whole patch is replaced.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Undo the last action with `apply`, which is given its steps.
+    /// Returns false when there is nothing to undo. The action stays put when `apply` fails.
+    pub fn undo<E>(&mut self, apply: impl FnOnce(&Entry) -> Result<(), E>) -> Result<bool, E> {
+        Self::replay(&mut self.undo, &mut self.redo, apply)
+    }
+
+    /// Redo the last undone action with `apply`, like `undo`.
+    pub fn redo<E>(&mut self, apply: impl FnOnce(&Entry) -> Result<(), E>) -> Result<bool, E> {
+        Self::replay(&mut self.redo, &mut self.undo, apply)
+    }
+
+    fn replay<E>(
+        from: &mut Vec<Entry>,
+        to: &mut Vec<Entry>,
+        apply: impl FnOnce(&Entry) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        match from.pop() {
+            Some(entry) => match apply(&entry) {
+                Ok(()) => {
+                    to.push(entry);
+                    Ok(true)
+                }
+                Err(error) => {
+                    from.push(entry);
+                    Err(error)
+                }
+            },
+            None => Ok(false),
+        }
+    }
+}
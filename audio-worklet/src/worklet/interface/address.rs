@@ -56,10 +56,38 @@ impl fmt::Display for InvalidAddressError {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Address {
+    /// Ids of the subpatches containing the module, outermost first.
+    pub parents: Vec<usize>,
     pub id: usize,
     pub port: Option<Port>,
 }
 
+impl Address {
+    /// Ids of the subpatch at this address and its parents, outermost first.
+    pub fn path(&self) -> Vec<usize> {
+        let mut path = self.parents.clone();
+        path.push(self.id);
+        path
+    }
+
+    /// Address of the module, without the port.
+    pub fn module(&self) -> Address {
+        Address {
+            port: None,
+            ..self.clone()
+        }
+    }
+
+    /// Address of the module `id` inside the subpatch at this address.
+    pub fn child(&self, id: usize) -> Address {
+        Address {
+            parents: self.path(),
+            id,
+            port: None,
+        }
+    }
+}
+
 impl FromStr for Address {
     type Err = InvalidAddressError;
     fn from_str(str: &str) -> Result<Self, Self::Err> {
@@ -69,14 +97,25 @@ impl FromStr for Address {
             .collect::<Vec<_>>();
 
         match parts[..] {
-            ["sobaka", id, target] => Ok(Address {
-                id: id.parse::<usize>().map_err(|_| InvalidAddressError)?,
-                port: Some(Port::from_str(target).map_err(|_| InvalidAddressError)?),
-            }),
-            ["sobaka", id] => Ok(Address {
-                id: id.parse::<usize>().map_err(|_| InvalidAddressError)?,
-                port: None,
-            }),
+            ["sobaka", ref path @ .., last] => {
+                let (path, port) = match Port::from_str(last) {
+                    Ok(port) => (path, Some(port)),
+                    Err(_) => (&parts[1..], None),
+                };
+
+                let mut ids = path
+                    .iter()
+                    .map(|id| id.parse::<usize>().map_err(|_| InvalidAddressError))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let id = ids.pop().ok_or(InvalidAddressError)?;
+
+                Ok(Address {
+                    parents: ids,
+                    id,
+                    port,
+                })
+            }
             _ => Err(InvalidAddressError),
         }
     }
@@ -84,14 +123,15 @@ impl FromStr for Address {
 
 impl Display for Address {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        match &self.port {
-            Some(port) => formatter.write_fmt(format_args!(
-                "/sobaka/{id}/{port}",
-                id = self.id,
-                port = port
-            )),
-            None => formatter.write_fmt(format_args!("/sobaka/{id}", id = self.id)),
+        formatter.write_str("/sobaka")?;
+        for parent in &self.parents {
+            formatter.write_fmt(format_args!("/{}", parent))?;
         }
+        formatter.write_fmt(format_args!("/{}", self.id))?;
+        if let Some(port) = &self.port {
+            formatter.write_fmt(format_args!("/{}", port))?;
+        }
+        Ok(())
     }
 }
 
@@ -118,6 +158,7 @@ impl Serialize for Address {
 impl From<NodeIndex> for Address {
     fn from(id: NodeIndex) -> Self {
         Address {
+            parents: vec![],
             id: id.index(),
             port: None,
         }
@@ -144,6 +185,7 @@ mod tests {
         assert_eq!(
             Address::from_str("/sobaka/54/out-0/").unwrap(),
             Address {
+                parents: vec![],
                 id: 54,
                 port: Some(Port::Output(0))
             }
@@ -153,6 +195,7 @@ mod tests {
         assert_eq!(
             Address::from_str("/sobaka/100/in-99").unwrap(),
             Address {
+                parents: vec![],
                 id: 100,
                 port: Some(Port::Input(99))
             }
@@ -161,7 +204,37 @@ mod tests {
         // No target specified
         assert_eq!(
             Address::from_str("/sobaka/54").unwrap(),
-            Address { id: 54, port: None }
+            Address {
+                parents: vec![],
+                id: 54,
+                port: None
+            }
+        );
+
+        // Nested in a subpatch
+        assert_eq!(
+            Address::from_str("/sobaka/12/7/in-0").unwrap(),
+            Address {
+                parents: vec![12],
+                id: 7,
+                port: Some(Port::Input(0))
+            }
+        );
+
+        // Nested in a subpatch, no target specified
+        assert_eq!(
+            Address::from_str("/sobaka/100/10").unwrap(),
+            Address {
+                parents: vec![100],
+                id: 10,
+                port: None
+            }
+        );
+
+        // Missing module id
+        assert_eq!(
+            Address::from_str("/sobaka/out-0").unwrap_err(),
+            InvalidAddressError
         );
 
         // Invalid module id
@@ -172,7 +245,7 @@ mod tests {
 
         // Invalid target
         assert_eq!(
-            Address::from_str("/sobaka/100/10").unwrap_err(),
+            Address::from_str("/sobaka/100/in-x").unwrap_err(),
             InvalidAddressError
         );
     }
@@ -184,6 +257,7 @@ mod tests {
             format!(
                 "{}",
                 Address {
+                    parents: vec![],
                     id: 44,
                     port: Some(Port::Output(0))
                 }
@@ -196,6 +270,7 @@ mod tests {
             format!(
                 "{}",
                 Address {
+                    parents: vec![],
                     id: 44,
                     port: Some(Port::Input(0))
                 }
@@ -204,6 +279,29 @@ mod tests {
         );
 
         // With missing target
-        assert_eq!(format!("{}", Address { id: 44, port: None }), "/sobaka/44");
+        assert_eq!(
+            format!(
+                "{}",
+                Address {
+                    parents: vec![],
+                    id: 44,
+                    port: None
+                }
+            ),
+            "/sobaka/44"
+        );
+
+        // Nested in subpatches
+        assert_eq!(
+            format!(
+                "{}",
+                Address {
+                    parents: vec![12, 3],
+                    id: 7,
+                    port: Some(Port::Input(0))
+                }
+            ),
+            "/sobaka/12/3/7/in-0"
+        );
     }
 }
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{
+    graph::SumMode,
+    interface::patch::PatchConnection,
+    module::{param::ParamInfo, port::PortInfo, AudioModuleType},
+};
+
+/// A module type with its ports and parameters, as listed by `list_modules`.
+#[derive(Serialize, TS, Clone)]
+#[ts(export)]
+pub struct ModuleInfo {
+    /// Module with default params
+    pub module: AudioModuleType,
+    /// Number of channels carried by each port
+    pub channels: usize,
+    pub inputs: Vec<PortInfo>,
+    pub outputs: Vec<PortInfo>,
+    pub params: Vec<ParamInfo>,
+}
+
+impl From<AudioModuleType> for ModuleInfo {
+    fn from(module: AudioModuleType) -> Self {
+        let (inputs, outputs) = module.ports();
+        Self {
+            channels: module.channels(),
+            params: module.params(),
+            module,
+            inputs,
+            outputs,
+        }
+    }
+}
+
+/// A module in the graph with its ports and parameters.
+#[derive(Serialize, TS, Clone)]
+#[ts(export)]
+pub struct ModuleDescription {
+    /// Module id
+    pub id: usize,
+    /// Module params, updated by the commands sent to the module
+    pub module: AudioModuleType,
+    /// Number of channels carried by each port
+    pub channels: usize,
+    pub inputs: Vec<PortInfo>,
+    pub outputs: Vec<PortInfo>,
+    pub params: Vec<ParamInfo>,
+    /// How the connections to each input are combined
+    pub sum_modes: Vec<SumMode>,
+    /// Inner graph of a subpatch
+    pub graph: Option<GraphDescription>,
+}
+
+/// The whole graph with the ports of every module, as returned by `describe`.
+#[derive(Serialize, TS, Clone)]
+#[ts(export)]
+pub struct GraphDescription {
+    /// Id of the module whose outputs carry the graph inputs
+    pub input: usize,
+    /// Graph inputs
+    pub inputs: Vec<PortInfo>,
+    /// Id of the module whose inputs are the graph outputs
+    pub output: usize,
+    /// Graph outputs
+    pub outputs: Vec<PortInfo>,
+    /// Modules in the graph, except the global input and output
+    pub modules: Vec<ModuleDescription>,
+    /// Connections between module ports
+    pub connections: Vec<PatchConnection>,
+}
@@ -1,14 +1,151 @@
+use serde::Serialize;
 use std::fmt;
+use ts_rs::TS;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Direction of a module port.
+#[derive(Serialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+pub enum PortDirection {
+    Input,
+    Output,
+}
+
+impl fmt::Display for PortDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortDirection::Input => write!(f, "input"),
+            PortDirection::Output => write!(f, "output"),
+        }
+    }
+}
+
+/// Reasons an edit to the graph is refused. Addresses are written as in the requests,
+/// e.g. `/sobaka/2/in-0`.
+#[derive(Serialize, TS, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", content = "data")]
+#[ts(export)]
 pub enum SobakaError {
-    Something,
+    /// No module at the address
+    UnknownNode(String),
+    /// The port at the address is past the ports of its module
+    PortOutOfRange { address: String, ports: usize },
+    /// The address targets a port of the wrong direction, or no port at all
+    WrongPortDirection {
+        address: String,
+        expected: PortDirection,
+    },
+    /// The module at the address does not send events
+    NotSubscribable(String),
+    /// The command is not one of the commands of the module at the address
+    CommandMismatch(String),
+    /// The data of the command cannot be used, with the reason
+    InvalidCommand { address: String, reason: String },
+    /// The module at the address has no parameter with the name
+    UnknownParam { address: String, name: String },
+    /// No preset with the name was saved for the module type
+    UnknownPreset { module_type: String, name: String },
+    /// The graph cannot be locked, after a panic while it was being changed
+    GraphLocked,
+    /// The graph processed by the audio thread no longer matches the patch,
+    /// the patch can still be saved to load it again after a reload
+    OutOfSync,
+    /// The address targets a module where none is expected, or cannot be parsed
+    InvalidAddress(String),
+    /// Connections cannot cross subpatch boundaries
+    CrossSubpatch { from: String, to: String },
+    /// No module was created with the placeholder earlier in the batch
+    UnknownPlaceholder(String),
+    /// The placeholder was already used earlier in the batch
+    DuplicatePlaceholder(String),
+    /// The module params are not supported, e.g. too many channels
+    InvalidModule,
+    /// The patch cannot be loaded, with the reason
+    InvalidPatch(String),
+    /// The graph does not match the undo history
+    HistoryMismatch,
+    /// An OSC packet cannot be decoded or does not map to an operation, with the reason
+    InvalidOsc(String),
+    /// An operation of a batch failed, with its index
+    Operation {
+        index: usize,
+        error: Box<SobakaError>,
+    },
 }
 
 impl fmt::Display for SobakaError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SobakaError::Something => write!(f, "invalid target"),
+            SobakaError::UnknownNode(address) => write!(f, "no module at {}", address),
+            SobakaError::PortOutOfRange { address, ports } => {
+                write!(f, "port {} is out of range, module has {}", address, ports)
+            }
+            SobakaError::WrongPortDirection { address, expected } => {
+                write!(f, "expected {} to be an {} port", address, expected)
+            }
+            SobakaError::NotSubscribable(address) => {
+                write!(f, "module at {} does not send events", address)
+            }
+            SobakaError::CommandMismatch(address) => {
+                write!(f, "command does not match module at {}", address)
+            }
+            SobakaError::InvalidCommand { address, reason } => {
+                write!(f, "invalid command for module at {}: {}", address, reason)
+            }
+            SobakaError::UnknownParam { address, name } => {
+                write!(f, "module at {} has no parameter {}", address, name)
+            }
+            SobakaError::UnknownPreset { module_type, name } => {
+                write!(f, "no preset {} for {} modules", name, module_type)
+            }
+            SobakaError::GraphLocked => write!(f, "graph is locked"),
+            SobakaError::OutOfSync => write!(f, "audio graph is out of sync with the patch"),
+            SobakaError::InvalidAddress(address) => write!(f, "invalid address {}", address),
+            SobakaError::CrossSubpatch { from, to } => write!(
+                f,
+                "cannot connect {} to {} across subpatch boundaries",
+                from, to
+            ),
+            SobakaError::UnknownPlaceholder(name) => write!(f, "unknown placeholder ${}", name),
+            SobakaError::DuplicatePlaceholder(name) => {
+                write!(f, "placeholder ${} is already in use", name)
+            }
+            SobakaError::InvalidModule => write!(f, "unsupported module params"),
+            SobakaError::InvalidPatch(reason) => write!(f, "invalid patch: {}", reason),
+            SobakaError::HistoryMismatch => write!(f, "graph does not match the history"),
+            SobakaError::InvalidOsc(reason) => write!(f, "invalid OSC: {}", reason),
+            SobakaError::Operation { index, error } => {
+                write!(f, "operation {} failed: {}", index, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SobakaError {}
+
+impl SobakaError {
+    /// JSON-RPC error code, in the range reserved for server errors.
+    /// Failed operations of a batch keep the code of their error.
+    pub fn code(&self) -> i64 {
+        match self {
+            SobakaError::UnknownNode(_) => -32001,
+            SobakaError::PortOutOfRange { .. } => -32002,
+            SobakaError::WrongPortDirection { .. } => -32003,
+            SobakaError::NotSubscribable(_) => -32004,
+            SobakaError::CommandMismatch(_) => -32005,
+            SobakaError::GraphLocked => -32006,
+            SobakaError::InvalidAddress(_) => -32007,
+            SobakaError::CrossSubpatch { .. } => -32008,
+            SobakaError::UnknownPlaceholder(_) => -32009,
+            SobakaError::DuplicatePlaceholder(_) => -32010,
+            SobakaError::InvalidModule => -32011,
+            SobakaError::InvalidPatch(_) => -32012,
+            SobakaError::HistoryMismatch => -32013,
+            SobakaError::UnknownParam { .. } => -32014,
+            SobakaError::UnknownPreset { .. } => -32015,
+            SobakaError::InvalidCommand { .. } => -32016,
+            SobakaError::InvalidOsc(_) => -32017,
+            SobakaError::OutOfSync => -32018,
+            SobakaError::Operation { error, .. } => error.code(),
         }
     }
 }
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// A MIDI 1.0 channel voice message, or a system message used to sync to other devices.
+/// Channels are numbered 0-15.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub enum MidiMessage {
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    /// Pressure on a held note
+    PolyAftertouch {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    /// Pressure on every held note of the channel
+    ChannelAftertouch {
+        channel: u8,
+        pressure: u8,
+    },
+    /// Bend between 0 and 16383, centred on 8192
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    /// Position in sixteenth notes from the start of the song
+    SongPosition(u16),
+    /// Timing clock, sent 24 times per quarter note
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+/// Controller turning off every held note of a channel.
+pub const ALL_NOTES_OFF: u8 = 123;
+
+/// Controller of the modulation wheel.
+pub const MOD_WHEEL: u8 = 1;
+
+/// Centre of pitch bend, where the pitch is not bent.
+pub const BEND_CENTRE: u16 = 8192;
+
+impl MidiMessage {
+    /// Parse a single message, e.g. as delivered by Web MIDI.
+    /// Running status, system exclusive and other system messages are not supported.
+    /// Notes turned on with no velocity are turned off, as most devices send them.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        if status < 0x80 || data.iter().any(|&byte| byte >= 0x80) {
+            return None;
+        }
+
+        let channel = status & 0x0f;
+        let data14 = |lsb: u8, msb: u8| (msb as u16) << 7 | lsb as u16;
+
+        Some(match (status & 0xf0, data) {
+            (0x80, &[note, velocity]) => MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            },
+            (0x90, &[note, 0]) => MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity: 0,
+            },
+            (0x90, &[note, velocity]) => MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            },
+            (0xa0, &[note, pressure]) => MidiMessage::PolyAftertouch {
+                channel,
+                note,
+                pressure,
+            },
+            (0xb0, &[controller, value]) => MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            },
+            (0xc0, &[program]) => MidiMessage::ProgramChange { channel, program },
+            (0xd0, &[pressure]) => MidiMessage::ChannelAftertouch { channel, pressure },
+            (0xe0, &[lsb, msb]) => MidiMessage::PitchBend {
+                channel,
+                value: data14(lsb, msb),
+            },
+            (0xf0, data) => match (status, data) {
+                (0xf2, &[lsb, msb]) => MidiMessage::SongPosition(data14(lsb, msb)),
+                (0xf8, &[]) => MidiMessage::Clock,
+                (0xfa, &[]) => MidiMessage::Start,
+                (0xfb, &[]) => MidiMessage::Continue,
+                (0xfc, &[]) => MidiMessage::Stop,
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Channel of channel voice messages.
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::PolyAftertouch { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::ChannelAftertouch { channel, .. }
+            | MidiMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MidiMessage;
+
+    #[test]
+    fn test_parse_messages() {
+        assert_eq!(
+            MidiMessage::parse(&[0x91, 60, 100]),
+            Some(MidiMessage::NoteOn {
+                channel: 1,
+                note: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0x90, 60, 0]),
+            Some(MidiMessage::NoteOff {
+                channel: 0,
+                note: 60,
+                velocity: 0
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xbf, 1, 127]),
+            Some(MidiMessage::ControlChange {
+                channel: 15,
+                controller: 1,
+                value: 127
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xd0, 64]),
+            Some(MidiMessage::ChannelAftertouch {
+                channel: 0,
+                pressure: 64
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xe0, 0x7f, 0x7f]),
+            Some(MidiMessage::PitchBend {
+                channel: 0,
+                value: 16383
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xf2, 0x00, 0x01]),
+            Some(MidiMessage::SongPosition(128))
+        );
+        assert_eq!(MidiMessage::parse(&[0xf8]), Some(MidiMessage::Clock));
+
+        // Wrong lengths, missing status and data bytes with the high bit set are refused
+        assert_eq!(MidiMessage::parse(&[0x90, 60]), None);
+        assert_eq!(MidiMessage::parse(&[0xf8, 0]), None);
+        assert_eq!(MidiMessage::parse(&[60, 100]), None);
+        assert_eq!(MidiMessage::parse(&[0x90, 60, 0x80]), None);
+        assert_eq!(MidiMessage::parse(&[]), None);
+    }
+}
@@ -1,2 +1,10 @@
 pub mod address;
+pub mod describe;
 pub mod error;
+pub mod midi;
+pub mod operation;
+pub mod osc;
+pub mod patch;
+pub mod preset;
+pub mod smf;
+pub mod time;
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    dsp::param::Ramp,
+    graph::SumMode,
+    interface::time::CommandTime,
+    module::{AudioModuleCommand, AudioModuleType},
+};
+
+/// An edit to the patch, applied with others in a batch.
+/// Addresses may start with a `$name` placeholder for a module created
+/// earlier in the same batch, e.g. `$osc/out-0` or `$voice/1/in-0` inside a subpatch.
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[serde(tag = "op")]
+#[ts(export)]
+pub enum Operation {
+    /// Create a module, optionally naming it with a placeholder
+    Create {
+        node: AudioModuleType,
+        parent: Option<String>,
+        placeholder: Option<String>,
+    },
+    /// Dispose of a module
+    Dispose { address: String },
+    /// Connect an output to an input
+    Connect {
+        from: String,
+        to: String,
+        mode: Option<SumMode>,
+    },
+    /// Remove a connection by id
+    Disconnect { id: usize, parent: Option<String> },
+    /// Send a command to a module, on the sample at `at` when given,
+    /// with the parameters it changes following `ramp`
+    Message {
+        address: String,
+        message: AudioModuleCommand,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    },
+    /// Set a parameter of a module by name, timed and ramped like `Message`
+    SetParam {
+        address: String,
+        name: String,
+        value: f64,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    },
+    /// Set a module to a preset saved for its type, timed and ramped like `Message`
+    ApplyPreset {
+        address: String,
+        name: String,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    },
+}
+
+/// Result of an operation in a batch.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum OperationResult {
+    /// Address of a created module
+    Address(String),
+    /// Id of a new connection
+    Connection(usize),
+    /// Whether the operation changed anything
+    Done(bool),
+}
@@ -0,0 +1,451 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Seconds from the start of NTP time in 1900 to the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Start of every bundle.
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// Time of a bundle, in seconds and fractions of a second since 1900 as in NTP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeTag {
+    pub seconds: u32,
+    /// Fraction of a second, in units of 2^-32 seconds
+    pub fraction: u32,
+}
+
+impl TimeTag {
+    /// Bundles with this time are applied as soon as they are received.
+    pub const IMMEDIATELY: TimeTag = TimeTag {
+        seconds: 0,
+        fraction: 1,
+    };
+
+    fn to_f64(self) -> f64 {
+        self.seconds as f64 + self.fraction as f64 / (1u64 << 32) as f64
+    }
+
+    /// Seconds from `epoch` to this time, negative when this time is earlier.
+    pub fn seconds_since(self, epoch: TimeTag) -> f64 {
+        self.to_f64() - epoch.to_f64()
+    }
+}
+
+impl From<SystemTime> for TimeTag {
+    fn from(time: SystemTime) -> Self {
+        // Times before the Unix epoch are not told apart
+        let since_unix = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let since_ntp = since_unix + Duration::from_secs(NTP_UNIX_OFFSET);
+
+        TimeTag {
+            seconds: since_ntp.as_secs() as u32,
+            fraction: (((since_ntp.subsec_nanos() as u64) << 32) / 1_000_000_000) as u32,
+        }
+    }
+}
+
+/// Argument of a message, by its type tag.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArgument {
+    /// `i`, 32 bit integer
+    Int(i32),
+    /// `f`, 32 bit float
+    Float(f32),
+    /// `s`, ASCII string
+    String(String),
+    /// `b`, bytes
+    Blob(Vec<u8>),
+    /// `h`, 64 bit integer
+    Long(i64),
+    /// `d`, 64 bit float
+    Double(f64),
+    /// `t`, time
+    Time(TimeTag),
+    /// `T` or `F`, with no data
+    Bool(bool),
+    /// `N`, with no data
+    Nil,
+}
+
+/// A message sent to an address, e.g. `/sobaka/2/frequency` with a float.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArgument>,
+}
+
+/// Messages and bundles applied together, at `time`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscBundle {
+    pub time: TimeTag,
+    pub content: Vec<OscPacket>,
+}
+
+/// Contents of a datagram, a single message or a bundle.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscPacket {
+    Message(OscMessage),
+    Bundle(OscBundle),
+}
+
+/// Reasons a packet cannot be decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OscError {
+    /// The packet ends in the middle of a field
+    Truncated,
+    /// The packet is neither a message nor a bundle
+    NotOscPacket,
+    /// A string is not terminated, or not ASCII
+    InvalidString,
+    /// The type tag of an argument is not supported
+    UnsupportedType(char),
+}
+
+impl fmt::Display for OscError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OscError::Truncated => write!(f, "OSC packet is truncated"),
+            OscError::NotOscPacket => write!(f, "not an OSC message or bundle"),
+            OscError::InvalidString => write!(f, "invalid OSC string"),
+            OscError::UnsupportedType(tag) => write!(f, "unsupported OSC type tag '{}'", tag),
+        }
+    }
+}
+
+impl std::error::Error for OscError {}
+
+/// Length of a field padded to a multiple of four bytes.
+fn padded(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+/// Reads the big endian, four byte aligned fields of a packet.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], OscError> {
+        if length > self.bytes.len() {
+            return Err(OscError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], OscError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u32(&mut self) -> Result<u32, OscError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, OscError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    fn time(&mut self) -> Result<TimeTag, OscError> {
+        Ok(TimeTag {
+            seconds: self.u32()?,
+            fraction: self.u32()?,
+        })
+    }
+
+    /// String terminated by at least one null byte, padded to four bytes.
+    fn string(&mut self) -> Result<String, OscError> {
+        let length = self
+            .bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(OscError::InvalidString)?;
+        let bytes = self.take(padded(length + 1))?;
+
+        match bytes[..length].is_ascii() {
+            true => Ok(String::from_utf8_lossy(&bytes[..length]).into_owned()),
+            false => Err(OscError::InvalidString),
+        }
+    }
+
+    fn blob(&mut self) -> Result<Vec<u8>, OscError> {
+        let length = self.u32()? as usize;
+        let bytes = self.take(padded(length))?;
+        Ok(bytes[..length].to_vec())
+    }
+}
+
+/// Writes the fields of a packet.
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn string(&mut self, string: &str) {
+        self.bytes.extend_from_slice(string.as_bytes());
+        let length = padded(string.len() + 1);
+        self.bytes
+            .resize(self.bytes.len() + length - string.len(), 0);
+    }
+
+    fn blob(&mut self, blob: &[u8]) {
+        self.bytes
+            .extend_from_slice(&(blob.len() as u32).to_be_bytes());
+        self.bytes.extend_from_slice(blob);
+        self.bytes
+            .resize(self.bytes.len() + padded(blob.len()) - blob.len(), 0);
+    }
+
+    fn time(&mut self, time: TimeTag) {
+        self.bytes.extend_from_slice(&time.seconds.to_be_bytes());
+        self.bytes.extend_from_slice(&time.fraction.to_be_bytes());
+    }
+}
+
+impl OscArgument {
+    fn tag(&self) -> char {
+        match self {
+            OscArgument::Int(_) => 'i',
+            OscArgument::Float(_) => 'f',
+            OscArgument::String(_) => 's',
+            OscArgument::Blob(_) => 'b',
+            OscArgument::Long(_) => 'h',
+            OscArgument::Double(_) => 'd',
+            OscArgument::Time(_) => 't',
+            OscArgument::Bool(true) => 'T',
+            OscArgument::Bool(false) => 'F',
+            OscArgument::Nil => 'N',
+        }
+    }
+
+    fn read(tag: char, reader: &mut Reader) -> Result<Self, OscError> {
+        Ok(match tag {
+            'i' => OscArgument::Int(reader.u32()? as i32),
+            'f' => OscArgument::Float(f32::from_bits(reader.u32()?)),
+            's' => OscArgument::String(reader.string()?),
+            'b' => OscArgument::Blob(reader.blob()?),
+            'h' => OscArgument::Long(reader.u64()? as i64),
+            'd' => OscArgument::Double(f64::from_bits(reader.u64()?)),
+            't' => OscArgument::Time(reader.time()?),
+            'T' => OscArgument::Bool(true),
+            'F' => OscArgument::Bool(false),
+            'N' => OscArgument::Nil,
+            tag => return Err(OscError::UnsupportedType(tag)),
+        })
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        match self {
+            OscArgument::Int(value) => writer.bytes.extend_from_slice(&value.to_be_bytes()),
+            OscArgument::Float(value) => writer.bytes.extend_from_slice(&value.to_be_bytes()),
+            OscArgument::String(value) => writer.string(value),
+            OscArgument::Blob(value) => writer.blob(value),
+            OscArgument::Long(value) => writer.bytes.extend_from_slice(&value.to_be_bytes()),
+            OscArgument::Double(value) => writer.bytes.extend_from_slice(&value.to_be_bytes()),
+            OscArgument::Time(value) => writer.time(*value),
+            OscArgument::Bool(_) | OscArgument::Nil => {}
+        }
+    }
+
+    /// Value of a numeric or boolean argument.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            OscArgument::Int(value) => Some(value as f64),
+            OscArgument::Float(value) => Some(value as f64),
+            OscArgument::Long(value) => Some(value as f64),
+            OscArgument::Double(value) => Some(value),
+            OscArgument::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            OscArgument::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl OscMessage {
+    pub fn new(address: impl Into<String>, args: Vec<OscArgument>) -> Self {
+        Self {
+            address: address.into(),
+            args,
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, OscError> {
+        let address = reader.string()?;
+
+        // Messages of early implementations may leave out the type tags when they have no arguments
+        let tags = match reader.bytes.is_empty() {
+            true => String::from(","),
+            false => reader.string()?,
+        };
+        let tags = tags.strip_prefix(',').ok_or(OscError::NotOscPacket)?;
+
+        let args = tags
+            .chars()
+            .map(|tag| OscArgument::read(tag, reader))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { address, args })
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.string(&self.address);
+        let tags = self.args.iter().map(OscArgument::tag);
+        writer.string(&std::iter::once(',').chain(tags).collect::<String>());
+        for arg in &self.args {
+            arg.write(writer);
+        }
+    }
+}
+
+impl OscPacket {
+    /// Decode the contents of a datagram.
+    pub fn decode(bytes: &[u8]) -> Result<Self, OscError> {
+        let mut reader = Reader { bytes };
+
+        let packet = match bytes.first() {
+            Some(b'/') => OscPacket::Message(OscMessage::read(&mut reader)?),
+            Some(b'#') if bytes.starts_with(BUNDLE_TAG) => {
+                reader.take(BUNDLE_TAG.len())?;
+                let time = reader.time()?;
+
+                let mut content = vec![];
+                while !reader.bytes.is_empty() {
+                    let length = reader.u32()? as usize;
+                    content.push(Self::decode(reader.take(length)?)?);
+                }
+
+                OscPacket::Bundle(OscBundle { time, content })
+            }
+            _ => return Err(OscError::NotOscPacket),
+        };
+
+        Ok(packet)
+    }
+
+    /// Encode the packet as the contents of a datagram.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        self.write(&mut writer);
+        writer.bytes
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        match self {
+            OscPacket::Message(message) => message.write(writer),
+            OscPacket::Bundle(bundle) => {
+                writer.bytes.extend_from_slice(BUNDLE_TAG);
+                writer.time(bundle.time);
+
+                for packet in &bundle.content {
+                    let element = packet.encode();
+                    writer
+                        .bytes
+                        .extend_from_slice(&(element.len() as u32).to_be_bytes());
+                    writer.bytes.extend_from_slice(&element);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{OscArgument, OscBundle, OscError, OscMessage, OscPacket, TimeTag};
+
+    #[test]
+    fn test_encode_message() {
+        // Example from the OSC 1.0 specification
+        let message = OscPacket::Message(OscMessage::new(
+            "/foo",
+            vec![
+                OscArgument::Int(1000),
+                OscArgument::Int(-1),
+                OscArgument::String("hello".into()),
+                OscArgument::Float(1.234),
+                OscArgument::Float(5.678),
+            ],
+        ));
+        let bytes = [
+            b"/foo\0\0\0\0,iisff\0\0".as_slice(),
+            &[0, 0, 0x03, 0xe8, 0xff, 0xff, 0xff, 0xff],
+            b"hello\0\0\0",
+            &[0x3f, 0x9d, 0xf3, 0xb6, 0x40, 0xb5, 0xb2, 0x2d],
+        ]
+        .concat();
+
+        assert_eq!(message.encode(), bytes);
+        assert_eq!(OscPacket::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn test_encode_bundle() {
+        let bundle = OscPacket::Bundle(OscBundle {
+            time: TimeTag {
+                seconds: 3,
+                fraction: 1 << 31,
+            },
+            content: vec![
+                OscPacket::Message(OscMessage::new(
+                    "/sobaka/1/gain",
+                    vec![
+                        OscArgument::Blob(vec![1, 2, 3]),
+                        OscArgument::Long(-2),
+                        OscArgument::Double(0.5),
+                        OscArgument::Bool(true),
+                        OscArgument::Nil,
+                        OscArgument::Time(TimeTag::IMMEDIATELY),
+                    ],
+                )),
+                OscPacket::Bundle(OscBundle {
+                    time: TimeTag::IMMEDIATELY,
+                    content: vec![OscPacket::Message(OscMessage::new("/sobaka/2", vec![]))],
+                }),
+            ],
+        });
+
+        let bytes = bundle.encode();
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(OscPacket::decode(&bytes), Ok(bundle));
+    }
+
+    #[test]
+    fn test_refuse_packets() {
+        assert_eq!(OscPacket::decode(b"foo\0"), Err(OscError::NotOscPacket));
+        assert_eq!(OscPacket::decode(b"/foo"), Err(OscError::InvalidString));
+        assert_eq!(
+            OscPacket::decode(b"/foo\0\0\0\0,i\0\0"),
+            Err(OscError::Truncated)
+        );
+        assert_eq!(
+            OscPacket::decode(b"/foo\0\0\0\0,x\0\0"),
+            Err(OscError::UnsupportedType('x'))
+        );
+        // Messages without type tags have no arguments
+        assert_eq!(
+            OscPacket::decode(b"/foo\0\0\0\0"),
+            Ok(OscPacket::Message(OscMessage::new("/foo", vec![])))
+        );
+    }
+
+    #[test]
+    fn test_time_tags() {
+        let time = TimeTag::from(UNIX_EPOCH + Duration::from_millis(1500));
+        assert_eq!(time.seconds, 2_208_988_801);
+        assert_eq!(time.fraction, 1 << 31);
+        assert_eq!(time.seconds_since(TimeTag::from(UNIX_EPOCH)), 1.5);
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    graph::{PortIndex, SumMode},
+    module::AudioModuleType,
+};
+
+/// Version of the patch format written by `get_patch`.
+/// Bump it whenever the format changes, so older patches can be told apart.
+pub const PATCH_VERSION: u32 = 1;
+
+/// Ids of modules and connections restored from a patch or the history are below this.
+/// Ids are handed out again once freed, so live graphs stay far below it.
+pub const MAX_ID: usize = 1 << 16;
+
+/// Snapshot of a whole graph.
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub struct Patch {
+    /// Version of the patch format
+    pub version: u32,
+    /// Modules in the graph, except the global input and output
+    pub modules: Vec<PatchModule>,
+    /// Connections between module ports, including those to the global output
+    pub connections: Vec<PatchConnection>,
+    /// Seed of the random sources, only set on the root patch.
+    /// Loading a patch without one keeps the current seed.
+    #[serde(default)]
+    pub seed: Option<u32>,
+}
+
+/// Snapshot of a module.
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub struct PatchModule {
+    /// Module id
+    pub id: usize,
+    /// Module params, updated by the commands sent to the module
+    pub module: AudioModuleType,
+    /// How the connections to each input are combined
+    pub sum_modes: Vec<SumMode>,
+    /// Inner graph of a subpatch
+    pub patch: Option<Patch>,
+}
+
+/// Snapshot of a connection.
+#[derive(Serialize, Deserialize, TS, Clone, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub struct PatchConnection {
+    /// Connection id
+    pub id: usize,
+    /// Module id and output port
+    pub from: (usize, PortIndex),
+    /// Module id and input port
+    pub to: (usize, PortIndex),
+}
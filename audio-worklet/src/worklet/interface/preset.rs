@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{interface::patch::PATCH_VERSION, module::AudioModuleType};
+
+/// Named params of a module, recalled on any module of the same type.
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub struct Preset {
+    /// Version of the patch format the params are written in
+    pub version: u32,
+    pub name: String,
+    /// Module params, as in patch snapshots.
+    /// Presets of polyphonic modules hold the params of the module they play.
+    pub module: AudioModuleType,
+}
+
+impl Preset {
+    pub fn new(name: &str, module: &AudioModuleType) -> Self {
+        let module = match module {
+            AudioModuleType::Poly(params) => &*params.module,
+            module => module,
+        };
+
+        Self {
+            version: PATCH_VERSION,
+            name: name.to_owned(),
+            module: module.clone(),
+        }
+    }
+}
+
+/// Saved presets by module type and name, see `AudioModuleType::type_name`.
+#[derive(Default)]
+pub struct Presets(BTreeMap<(String, String), Preset>);
+
+impl Presets {
+    /// Save a preset, replacing the preset of the same name for its module type.
+    pub fn save(&mut self, preset: Preset) {
+        let key = (preset.module.type_name(), preset.name.clone());
+        self.0.insert(key, preset);
+    }
+
+    pub fn get(&self, module_type: &str, name: &str) -> Option<&Preset> {
+        self.0.get(&(module_type.to_owned(), name.to_owned()))
+    }
+
+    /// Presets of a module type, by name.
+    pub fn list(&self, module_type: &str) -> Vec<Preset> {
+        self.0
+            .iter()
+            .filter(|((preset_type, _), _)| preset_type == module_type)
+            .map(|(_, preset)| preset.clone())
+            .collect()
+    }
+
+    /// Returns false when there is no such preset.
+    pub fn delete(&mut self, module_type: &str, name: &str) -> bool {
+        self.0
+            .remove(&(module_type.to_owned(), name.to_owned()))
+            .is_some()
+    }
+}
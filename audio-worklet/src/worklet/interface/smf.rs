@@ -0,0 +1,288 @@
+use std::fmt;
+
+use super::midi::MidiMessage;
+
+/// Tempo of files until they set their own, 120 BPM in microseconds per quarter note.
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Event of a track of a Standard MIDI File.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackEvent {
+    Message(MidiMessage),
+    /// Microseconds per quarter note from this event on
+    Tempo(u32),
+}
+
+/// Track of a Standard MIDI File.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Track {
+    /// Events with their time in ticks from the start of the file
+    pub events: Vec<(u64, TrackEvent)>,
+    /// Time of the end of the track in ticks
+    pub end: u64,
+}
+
+/// A Standard MIDI File of type 0 or 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MidiFile {
+    /// Ticks per quarter note
+    pub division: u16,
+    pub tracks: Vec<Track>,
+}
+
+/// Reasons a Standard MIDI File cannot be played.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MidiFileError {
+    /// The file does not start with a header chunk
+    NotMidiFile,
+    /// The file ends in the middle of a chunk or has fewer tracks than its header
+    Truncated,
+    /// Only single track and simultaneous tracks files, types 0 and 1, are played
+    UnsupportedFormat(u16),
+    /// Timecode divisions are not supported, only ticks per quarter note
+    UnsupportedDivision,
+    /// An event of a track cannot be parsed, with the index of the track
+    InvalidEvent(usize),
+}
+
+impl fmt::Display for MidiFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiFileError::NotMidiFile => write!(f, "not a MIDI file"),
+            MidiFileError::Truncated => write!(f, "MIDI file is truncated"),
+            MidiFileError::UnsupportedFormat(format) => {
+                write!(f, "MIDI files of type {} are not supported", format)
+            }
+            MidiFileError::UnsupportedDivision => {
+                write!(f, "MIDI files timed in frames are not supported")
+            }
+            MidiFileError::InvalidEvent(track) => write!(f, "invalid event in track {}", track),
+        }
+    }
+}
+
+impl std::error::Error for MidiFileError {}
+
+/// Reads the big endian fields of a file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], MidiFileError> {
+        if length > self.bytes.len() {
+            return Err(MidiFileError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, MidiFileError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MidiFileError> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Result<u32, MidiFileError> {
+        Ok(u32::from_be_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+
+    /// Variable length quantity, seven bits per byte with the high bit set on all but the last.
+    fn varlen(&mut self) -> Result<u32, MidiFileError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | (byte & 0x7f) as u32;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(MidiFileError::Truncated)
+    }
+}
+
+impl MidiFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, MidiFileError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4).ok() != Some(b"MThd".as_slice()) {
+            return Err(MidiFileError::NotMidiFile);
+        }
+
+        let length = reader.u32()? as usize;
+        let mut header = Reader {
+            bytes: reader.take(length)?,
+        };
+        let format = header.u16()?;
+        let count = header.u16()?;
+        let division = header.u16()?;
+
+        if format > 1 {
+            return Err(MidiFileError::UnsupportedFormat(format));
+        }
+        if division & 0x8000 != 0 {
+            return Err(MidiFileError::UnsupportedDivision);
+        }
+
+        let mut tracks = Vec::with_capacity(count as usize);
+        while tracks.len() < count as usize {
+            let kind = reader.take(4)?;
+            let length = reader.u32()? as usize;
+            let chunk = reader.take(length)?;
+
+            // Chunks of other types are skipped
+            if kind == b"MTrk" {
+                let track = Self::parse_track(chunk)
+                    .map_err(|_| MidiFileError::InvalidEvent(tracks.len()))?;
+                tracks.push(track);
+            }
+        }
+
+        Ok(Self { division, tracks })
+    }
+
+    fn parse_track(bytes: &[u8]) -> Result<Track, MidiFileError> {
+        let mut reader = Reader { bytes };
+        let mut track = Track::default();
+        let mut time = 0;
+        let mut running = None;
+
+        while !reader.bytes.is_empty() {
+            time += reader.varlen()? as u64;
+            track.end = time;
+
+            let status = match reader.bytes.first() {
+                Some(&status) if status >= 0x80 => reader.u8()?,
+                // Running status, the data of another event of the last channel message
+                _ => running.ok_or(MidiFileError::InvalidEvent(0))?,
+            };
+
+            match status {
+                0xff => {
+                    running = None;
+                    let kind = reader.u8()?;
+                    let length = reader.varlen()? as usize;
+                    let data = reader.take(length)?;
+
+                    match (kind, data) {
+                        (0x51, &[a, b, c]) => {
+                            let tempo = u32::from_be_bytes([0, a, b, c]);
+                            track.events.push((time, TrackEvent::Tempo(tempo)));
+                        }
+                        // End of track
+                        (0x2f, _) => break,
+                        _ => {}
+                    }
+                }
+                // System exclusive messages are skipped
+                0xf0 | 0xf7 => {
+                    running = None;
+                    let length = reader.varlen()? as usize;
+                    reader.take(length)?;
+                }
+                0x80..=0xef => {
+                    running = Some(status);
+                    let length = match status & 0xf0 {
+                        0xc0 | 0xd0 => 1,
+                        _ => 2,
+                    };
+                    let message = [&[status], reader.take(length)?].concat();
+                    let message =
+                        MidiMessage::parse(&message).ok_or(MidiFileError::InvalidEvent(0))?;
+                    track.events.push((time, TrackEvent::Message(message)));
+                }
+                _ => return Err(MidiFileError::InvalidEvent(0)),
+            }
+        }
+
+        Ok(track)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MidiFile, MidiFileError, TrackEvent};
+    use crate::interface::midi::MidiMessage;
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        [kind, &(data.len() as u32).to_be_bytes(), data].concat()
+    }
+
+    #[test]
+    fn test_parse_midi_file() {
+        let header = chunk(b"MThd", &[0, 1, 0, 2, 0, 96]);
+        // Tempo of 100 BPM, then the end of the track
+        let tempo = chunk(
+            b"MTrk",
+            &[0, 0xff, 0x51, 3, 0x09, 0x27, 0xc0, 0, 0xff, 0x2f, 0],
+        );
+        // A note with a running status note off, a skipped chunk before the track
+        let notes = chunk(
+            b"MTrk",
+            &[
+                0, 0x91, 60, 100, 0x81, 0x40, 60, 0, 0x10, 0xf0, 1, 0xf7, 0, 0xff, 0x2f, 0,
+            ],
+        );
+        let unknown = chunk(b"XFIH", &[1, 2, 3]);
+        let file = MidiFile::parse(&[header, tempo, unknown, notes].concat()).unwrap();
+
+        assert_eq!(file.division, 96);
+        assert_eq!(file.tracks[0].events, [(0, TrackEvent::Tempo(600_000))]);
+        assert_eq!(
+            file.tracks[1].events,
+            [
+                (
+                    0,
+                    TrackEvent::Message(MidiMessage::NoteOn {
+                        channel: 1,
+                        note: 60,
+                        velocity: 100
+                    })
+                ),
+                (
+                    192,
+                    TrackEvent::Message(MidiMessage::NoteOff {
+                        channel: 1,
+                        note: 60,
+                        velocity: 0
+                    })
+                )
+            ]
+        );
+        assert_eq!(file.tracks[1].end, 208);
+    }
+
+    #[test]
+    fn test_refuse_midi_files() {
+        let header = |format: u8, division: [u8; 2]| {
+            chunk(b"MThd", &[0, format, 0, 1, division[0], division[1]])
+        };
+        let track = chunk(b"MTrk", &[0, 0xff, 0x2f, 0]);
+
+        assert_eq!(MidiFile::parse(b"RIFF"), Err(MidiFileError::NotMidiFile));
+        assert_eq!(
+            MidiFile::parse(&header(0, [0, 96])),
+            Err(MidiFileError::Truncated)
+        );
+        assert_eq!(
+            MidiFile::parse(&[header(2, [0, 96]), track.clone()].concat()),
+            Err(MidiFileError::UnsupportedFormat(2))
+        );
+        assert_eq!(
+            MidiFile::parse(&[header(0, [0xe7, 40]), track].concat()),
+            Err(MidiFileError::UnsupportedDivision)
+        );
+        // Data without a status to run on
+        assert_eq!(
+            MidiFile::parse(&[header(0, [0, 96]), chunk(b"MTrk", &[0, 60, 100])].concat()),
+            Err(MidiFileError::InvalidEvent(0))
+        );
+    }
+}
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// When a command is applied, on the clock of the audio context.
+/// Commands timed in the past are applied at the start of the next block.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq)]
+#[ts(export)]
+pub enum CommandTime {
+    /// Sample frame, `currentFrame` in the AudioWorklet
+    Frame(#[ts(type = "number")] u64),
+    /// Time in seconds, `currentTime` of the AudioContext
+    Seconds(f64),
+}
@@ -1,190 +1,1082 @@
-use context::GeneralContext;
+use context::{GeneralContext, MessageError, ModuleContext};
+use dsp::param::Ramp;
+use engine::AudioEngine;
 use fundsp::{
-    hacker::AudioUnit32,
     hacker32::{U1, U3},
     DEFAULT_SR,
 };
-use graph::{Graph32, NodeIndex};
+use graph::{Graph32, NodeIndex, SumMode};
+use history::{Entry, History, Step};
 use interface::{
     address::{Address, Port},
-    error::SobakaError,
+    describe::{GraphDescription, ModuleInfo},
+    error::{PortDirection, SobakaError},
+    operation::{Operation, OperationResult},
+    patch::{Patch, PatchConnection, PatchModule, MAX_ID, PATCH_VERSION},
+    preset::{Preset, Presets},
+    time::CommandTime,
+};
+use module::{
+    param::ParamInfo, subpatch::subpatch, AudioModuleCommand, AudioModuleEvent, AudioModuleType,
+    ModuleUnit, NoOp,
 };
-use module::{AudioModuleCommand, AudioModuleEvent, AudioModuleType, ModuleUnit};
 use petgraph::graph::EdgeIndex;
-use std::sync::{Arc, Mutex, MutexGuard};
-use utils::observer::Observer;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+use topology::{Module, Topology};
+use utils::{atomic_float::AtomicFloat, observer::Observer};
 
 pub mod context;
 pub mod dsp;
+pub mod engine;
 pub mod graph;
+pub mod history;
 pub mod module;
+pub mod osc;
+pub mod render;
 pub mod rpc;
+pub mod topology;
 
 pub mod interface;
 pub mod utils;
 
 pub mod worklet;
 
-type SharedGraph = Arc<Mutex<Graph32>>;
 // AudioProcessor is the rust entry-point for Web Audio AudioWorkletProcessor
+// It runs on the control thread and queues edits for the `AudioEngine` on the audio thread.
 pub struct AudioProcessor {
-    graph: SharedGraph,
-    sample_rate: Mutex<f64>,
+    topology: Mutex<Topology>,
+    /// Locked after `topology`, so actions are recorded in the order they are made.
+    history: Mutex<History>,
+    /// Locked after `topology`, and never along with `history`.
+    presets: Mutex<Presets>,
+    sample_rate: AtomicFloat,
+    seed: AtomicU32,
 }
 
 pub type SobakaResult<T> = Result<T, SobakaError>;
 
-impl AudioProcessor {
-    pub fn new() -> Self {
-        let graph = Graph32::new::<U1, U3>();
+/// Find the topology of the subpatch at `path`, or the root topology when `path` is empty.
+fn resolve<'a>(topology: &'a mut Topology, path: &[usize]) -> SobakaResult<&'a mut Topology> {
+    topology.subpatch_mut(path).ok_or_else(|| {
+        // Subpatch cannot be found
+        let (&id, parents) = path.split_last().expect("root topology is always found");
+        let address = Address {
+            parents: parents.to_vec(),
+            id,
+            port: None,
+        };
+        SobakaError::UnknownNode(address.to_string())
+    })
+}
 
-        AudioProcessor {
-            graph: Arc::new(Mutex::new(graph)),
-            sample_rate: Mutex::new(DEFAULT_SR),
+/// Parse an address, replacing a leading `$name` placeholder with the address it stands for.
+fn resolve_placeholder(
+    target: &str,
+    placeholders: &HashMap<String, Address>,
+) -> SobakaResult<Address> {
+    let target = match target.strip_prefix('$') {
+        Some(target) => {
+            let (name, rest) = target.split_once('/').unwrap_or((target, ""));
+            let address = placeholders
+                .get(name)
+                .ok_or_else(|| SobakaError::UnknownPlaceholder(name.to_owned()))?;
+            format!("{}/{}", address, rest)
         }
+        None => target.to_owned(),
+    };
+
+    target
+        .parse()
+        .map_err(|_| SobakaError::InvalidAddress(target.clone()))
+}
+
+/// Parameter of a module by name.
+fn find_param(module: &AudioModuleType, name: &str) -> Option<ParamInfo> {
+    module.params().into_iter().find(|param| param.name == name)
+}
+
+/// Error of a command or parameter change refused by the module at `address`.
+fn message_error(address: &Address, error: MessageError) -> SobakaError {
+    match error {
+        MessageError::UnknownNode => SobakaError::UnknownNode(address.to_string()),
+        MessageError::CommandMismatch => SobakaError::CommandMismatch(address.to_string()),
+        MessageError::UnknownParam(tag) => SobakaError::UnknownParam {
+            address: address.to_string(),
+            name: tag.to_string(),
+        },
+    }
+}
+
+/// Record a new connection, so undoing removes it.
+fn record_connect(topology: &Topology, entry: &mut Entry, path: &[usize], edge: EdgeIndex) {
+    if let Some(connection) = topology.connection(edge) {
+        entry.push(
+            path,
+            Step::Connect(connection),
+            Step::Disconnect(edge.index()),
+        );
     }
+}
 
+impl AudioProcessor {
+    /// Create the control side of the processor along with the `AudioEngine` it drives.
+    pub fn new() -> (Self, AudioEngine) {
+        let (topology, engine) = Topology::new(Graph32::new::<U1, U3>());
+
+        (
+            AudioProcessor {
+                topology: Mutex::new(topology),
+                history: Mutex::new(History::default()),
+                presets: Mutex::new(Presets::default()),
+                sample_rate: AtomicFloat::new(DEFAULT_SR),
+                seed: AtomicU32::new(0),
+            },
+            engine,
+        )
+    }
+
+    /// Sample rate used to reset newly created modules.
     pub fn set_sample_rate(&self, sample_rate: f64) {
-        let mut sr = self.sample_rate.lock().unwrap();
-        *sr = sample_rate;
-        self.graph.lock().unwrap().reset(Some(sample_rate));
+        self.sample_rate.set(sample_rate);
+    }
+
+    /// Seed every random source of the graph from `seed`, e.g. noise and the segments
+    /// picked by samplers, so a patch sounds the same each time it is played with the same seed.
+    /// Modules draw from the new seed once they are reset, samplers on their next trigger.
+    pub fn set_seed(&self, seed: u32) -> SobakaResult<bool> {
+        let mut root = self.topology()?;
+        root.set_seed(seed.into());
+        self.seed.store(seed, Ordering::Relaxed);
+
+        Ok(true)
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed.load(Ordering::Relaxed)
+    }
+
+    /// Lock the root topology, refusing once the audio thread has fallen out of sync with it.
+    fn topology(&self) -> SobakaResult<MutexGuard<'_, Topology>> {
+        let topology = self.topology.lock().map_err(|_| SobakaError::GraphLocked)?;
+        if !topology.is_synced() {
+            return Err(SobakaError::OutOfSync);
+        }
+
+        Ok(topology)
     }
 
-    pub fn graph(&self) -> SharedGraph {
-        self.graph.clone()
+    fn history(&self) -> SobakaResult<MutexGuard<'_, History>> {
+        self.history.lock().map_err(|_| SobakaError::GraphLocked)
     }
 
-    pub fn graph_mut(&self) -> SobakaResult<MutexGuard<Graph32>> {
-        self.graph.lock().map_err(|_| SobakaError::Something)
+    fn presets(&self) -> SobakaResult<MutexGuard<'_, Presets>> {
+        self.presets.lock().map_err(|_| SobakaError::GraphLocked)
     }
 
-    pub fn create(&self, node: AudioModuleType) -> SobakaResult<Address> {
-        let (mut unit, context): (ModuleUnit, GeneralContext) = (&node).into(); // @todo there is probably a more semantic way to do this trait to use
+    /// Run an action on the root topology and record its steps, so it can be undone.
+    fn record<T>(
+        &self,
+        action: impl FnOnce(&mut Topology, &mut Entry) -> SobakaResult<T>,
+    ) -> SobakaResult<T> {
+        let mut root = self.topology()?;
+        let mut entry = Entry::default();
+
+        let result = action(&mut root, &mut entry)?;
+        self.history()?.record(entry);
+
+        Ok(result)
+    }
+
+    /// Build the module and audio unit for `node`.
+    fn build(&self, node: &AudioModuleType) -> SobakaResult<(Module, ModuleUnit)> {
+        if !node.is_valid() {
+            // Unsupported number of channels or module, or unregistered module type
+            return Err(SobakaError::InvalidModule);
+        }
+
+        let (mut unit, context, inner): (ModuleUnit, GeneralContext, Option<Topology>) = match node
+        {
+            AudioModuleType::Subpatch(params) => {
+                let (topology, engine) = subpatch(params);
+                let ctx = ModuleContext::<NoOp, NoOp>::default();
+                (Box::new(engine), ctx.boxed(), Some(topology))
+            }
+            _ => {
+                let (unit, context) = node.into(); // @todo there is probably a more semantic way to do this trait to use
+                (unit, context, None)
+            }
+        };
 
         // Reset `sample_rate` after construction because some
         // AudioNodes in fundsp reset `sample_rate` to default when constructed
         // @todo this should be adjusted in fundsp
-        unit.reset(Some(*self.sample_rate.lock().unwrap()));
+        unit.reset(Some(self.sample_rate.get()));
 
-        let address: Address = self.graph_mut()?.add(unit, context).into();
+        // Ports of polyphonic modules carry several channels
+        let channels = node.channels();
+        let mut module = Module::new(unit.inputs() / channels, unit.outputs() / channels, context);
+        module.channels = channels;
+        module.state = Some(node.clone());
+        module.subpatch = inner;
 
-        match node {
-            AudioModuleType::Scope(_) => {
-                // Connect scope output to global output
-                // This channel is not piped to audio output, just used for processing the graph.
-                self.graph_mut()?
-                    .connect_output(address.clone().into(), 0, 2);
+        Ok((module, unit))
+    }
+
+    /// Build a module from a snapshot, loading the inner patch of a subpatch.
+    fn restore(&self, snapshot: &PatchModule) -> SobakaResult<(Module, ModuleUnit)> {
+        let (mut module, unit) = self.build(&snapshot.module)?;
+
+        if let (Some(inner), Some(inner_patch)) = (module.subpatch.as_mut(), &snapshot.patch) {
+            self.load(inner, inner_patch)?;
+        }
+
+        Ok((module, unit))
+    }
+
+    /// Create a module, inside the subpatch at `parent` when given.
+    pub fn create(&self, node: AudioModuleType, parent: Option<Address>) -> SobakaResult<Address> {
+        self.record(|root, entry| self.create_in(root, node, parent, entry))
+    }
+
+    fn create_in(
+        &self,
+        root: &mut Topology,
+        node: AudioModuleType,
+        parent: Option<Address>,
+        entry: &mut Entry,
+    ) -> SobakaResult<Address> {
+        let (module, unit) = self.build(&node)?;
+
+        let path = parent.as_ref().map(Address::path).unwrap_or_default();
+        let topology = resolve(root, &path)?;
+
+        let inputs = module.inputs;
+        let id = topology.add(module, unit);
+
+        for input in 0..inputs {
+            let mode = node.sum_mode(input);
+            if mode != SumMode::default() {
+                topology.set_sum_mode(id, input, mode);
             }
-            AudioModuleType::Output => {
-                // Connect left and right channels to global output
-                self.graph_mut()?
-                    .connect_output(address.clone().into(), 0, 0);
-                self.graph_mut()?
-                    .connect_output(address.clone().into(), 1, 1);
+        }
+
+        let snapshot = topology
+            .module_patch(id)
+            .expect("created module has params");
+        entry.push(&path, Step::Add(snapshot), Step::Remove(id.index()));
+
+        let global_output = topology.global_output();
+        let global_outputs = topology
+            .get(global_output)
+            .expect("global output is never removed")
+            .inputs;
+        let outputs: &[(usize, usize)] = match node {
+            // Connect scope output to global output
+            // This channel is not piped to audio output, just used for processing the graph.
+            // Midi out is connected the same way, so it runs and sends its messages
+            AudioModuleType::Scope(_) | AudioModuleType::MidiOut(_) => &[(0, 2)],
+            // Connect left and right channels to global output
+            AudioModuleType::Output => &[(0, 0), (1, 1)],
+            _ => &[],
+        };
+
+        // Subpatches may have fewer outputs than the root graph
+        for &(port, global_port) in outputs {
+            if global_port < global_outputs {
+                let edge = topology.connect(id, port, global_output, global_port);
+                record_connect(topology, entry, &path, edge);
             }
-            _ => {}
         }
 
-        Ok(address)
+        Ok(match parent {
+            Some(parent) => parent.child(id.index()),
+            None => id.into(),
+        })
     }
 
     pub fn dispose(&self, address: Address) -> SobakaResult<bool> {
+        self.record(|root, entry| self.dispose_in(root, address, entry))
+    }
+
+    fn dispose_in(
+        &self,
+        root: &mut Topology,
+        address: Address,
+        entry: &mut Entry,
+    ) -> SobakaResult<bool> {
         if let Some(_port) = address.port {
             // Port should not be specified when targeting modules directly
-            return Err(SobakaError::Something);
+            return Err(SobakaError::InvalidAddress(address.to_string()));
         }
 
+        let path = address.parents.clone();
+        let topology = resolve(root, &path)?;
         let id: NodeIndex = address.into();
 
-        Ok(self.graph_mut()?.remove(id))
+        // Undoing restores the module along with its connections
+        let snapshot = topology.module_patch(id);
+        let connections = topology.connections(id);
+
+        let removed = topology.remove(id);
+        if let (true, Some(snapshot)) = (removed, snapshot) {
+            for connection in connections {
+                let id = connection.id;
+                entry.push(&path, Step::Disconnect(id), Step::Connect(connection));
+            }
+            entry.push(&path, Step::Remove(id.index()), Step::Add(snapshot));
+        }
+
+        Ok(removed)
+    }
+
+    /// Connect an output to an input. Choosing a `mode` sets how all
+    /// connections to the input are combined.
+    /// Both modules have to be in the same subpatch.
+    pub fn connect(
+        &self,
+        from: Address,
+        to: Address,
+        mode: Option<SumMode>,
+    ) -> SobakaResult<usize> {
+        self.record(|root, entry| self.connect_in(root, from, to, mode, entry))
     }
 
-    pub fn connect(&self, from: Address, to: Address) -> SobakaResult<usize> {
+    fn connect_in(
+        &self,
+        root: &mut Topology,
+        from: Address,
+        to: Address,
+        mode: Option<SumMode>,
+        entry: &mut Entry,
+    ) -> SobakaResult<usize> {
+        if from.parents != to.parents {
+            return Err(SobakaError::CrossSubpatch {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+
+        let path = from.parents.clone();
+        let topology = resolve(root, &path)?;
+
         let from_port = match from {
             Address {
-                id: _from_id,
                 port: Some(Port::Output(output)),
+                ..
             } => {
-                let outputs = self
-                    .graph_mut()?
-                    .get_mod(from.clone().into())
-                    // Node cannot be found
-                    .ok_or(SobakaError::Something)?
-                    .outputs();
+                let outputs = topology
+                    .get(from.clone().into())
+                    .ok_or_else(|| SobakaError::UnknownNode(from.module().to_string()))?
+                    .outputs;
 
                 if output >= outputs {
-                    // Output is out of range
-                    Err(SobakaError::Something)
+                    Err(SobakaError::PortOutOfRange {
+                        address: from.to_string(),
+                        ports: outputs,
+                    })
                 } else {
                     Ok(output)
                 }
             }
-            // Expecting from to target output port
-            _ => Err(SobakaError::Something),
+            _ => Err(SobakaError::WrongPortDirection {
+                address: from.to_string(),
+                expected: PortDirection::Output,
+            }),
         }?;
 
         let to_port = match to {
             Address {
-                id: _to_id,
                 port: Some(Port::Input(input)),
+                ..
             } => {
-                let inputs = self
-                    .graph_mut()?
-                    .get_mod(to.clone().into())
-                    // Node cannot be found
-                    .ok_or(SobakaError::Something)?
-                    .inputs();
+                let inputs = topology
+                    .get(to.clone().into())
+                    .ok_or_else(|| SobakaError::UnknownNode(to.module().to_string()))?
+                    .inputs;
 
                 if input >= inputs {
-                    // Input is out of range
-                    Err(SobakaError::Something)
+                    Err(SobakaError::PortOutOfRange {
+                        address: to.to_string(),
+                        ports: inputs,
+                    })
                 } else {
                     Ok(input)
                 }
             }
-            // Expecting to to target output port
-            _ => Err(SobakaError::Something),
+            _ => Err(SobakaError::WrongPortDirection {
+                address: to.to_string(),
+                expected: PortDirection::Input,
+            }),
         }?;
 
-        Ok(self
-            .graph_mut()?
-            .connect(from.into(), from_port, to.into(), to_port)
-            .index())
+        let target: NodeIndex = to.into();
+        let edge = topology.connect(from.into(), from_port, target, to_port);
+        record_connect(topology, entry, &path, edge);
+
+        if let Some(mode) = mode {
+            let previous = topology
+                .get(target)
+                .and_then(|module| module.sum_modes.get(to_port).copied())
+                .unwrap_or_default();
+            topology.set_sum_mode(target, to_port, mode);
+
+            let id = target.index();
+            entry.push(
+                &path,
+                Step::SetSumMode(id, to_port, mode),
+                Step::SetSumMode(id, to_port, previous),
+            );
+        }
+
+        Ok(edge.index())
     }
 
     fn subscribe(&self, node: Address) -> SobakaResult<Observer<AudioModuleEvent>> {
         match node {
-            Address { id: _, port: None } => {
-                self.graph_mut()?
-                    .get_mod(node.into())
-                    // Module not found
-                    .ok_or(SobakaError::Something)?
+            Address { port: None, .. } => {
+                let mut root = self.topology()?;
+                resolve(&mut root, &node.parents)?
+                    .get(node.clone().into())
+                    .ok_or_else(|| SobakaError::UnknownNode(node.to_string()))?
                     .context
                     .try_observe()
-                    // Module does not support subscription
-                    .map_err(|_| SobakaError::Something)
-            }
-            _ => {
-                // Bad address
-                Err(SobakaError::Something)
+                    .map_err(|_| SobakaError::NotSubscribable(node.to_string()))
             }
+            // Port should not be specified when subscribing to modules
+            _ => Err(SobakaError::InvalidAddress(node.to_string())),
+        }
+    }
+
+    /// Remove a connection, inside the subpatch at `parent` when given.
+    pub fn disconnect(&self, id: EdgeIndex, parent: Option<Address>) -> SobakaResult<bool> {
+        self.record(|root, entry| self.disconnect_in(root, id, parent, entry))
+    }
+
+    fn disconnect_in(
+        &self,
+        root: &mut Topology,
+        id: EdgeIndex,
+        parent: Option<Address>,
+        entry: &mut Entry,
+    ) -> SobakaResult<bool> {
+        let path = parent.as_ref().map(Address::path).unwrap_or_default();
+        let topology = resolve(root, &path)?;
+
+        let connection = topology.connection(id);
+        let removed = topology.disconnect(id);
+        if let (true, Some(connection)) = (removed, connection) {
+            entry.push(
+                &path,
+                Step::Disconnect(id.index()),
+                Step::Connect(connection),
+            );
         }
+
+        Ok(removed)
     }
 
-    pub fn disconnect(&self, id: EdgeIndex) -> SobakaResult<bool> {
-        Ok(self.graph_mut()?.disconnect(id))
+    /// Send a command to a module, on the sample at `at` when given.
+    /// Otherwise the command is applied before the next block is processed.
+    /// Parameters changed by the command ramp to their new value when `ramp` is given.
+    pub fn message(
+        &self,
+        address: Address,
+        message: AudioModuleCommand,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> SobakaResult<bool> {
+        self.record(|root, entry| self.message_in(root, address, message, at, ramp, entry))
     }
 
-    pub fn message(&self, address: Address, message: AudioModuleCommand) -> SobakaResult<bool> {
-        self.graph_mut()?
-            .get_mod(address.into())
-            // Node cannot be found
-            .ok_or(SobakaError::Something)?
-            .context
-            .try_notify(message)
-            // Node does not support sending
-            .map_err(|_| SobakaError::Something)?;
+    fn message_in(
+        &self,
+        root: &mut Topology,
+        address: Address,
+        message: AudioModuleCommand,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+        entry: &mut Entry,
+    ) -> SobakaResult<bool> {
+        let path = address.parents.clone();
+        let topology = resolve(root, &path)?;
+        let id: NodeIndex = address.clone().into();
+
+        let module = topology
+            .get(id)
+            .ok_or_else(|| SobakaError::UnknownNode(address.to_string()))?;
+
+        message
+            .validate()
+            .map_err(|reason| SobakaError::InvalidCommand {
+                address: address.to_string(),
+                reason,
+            })?;
+
+        // Commands that do not change params, e.g. notes, are not recorded
+        let restore = module
+            .state
+            .as_ref()
+            .and_then(|state| state.restore(&message));
+
+        match at {
+            Some(time) => {
+                let notify = topology
+                    .prepare(id, message.clone(), ramp)
+                    .map_err(|error| message_error(&address, error))?;
+                root.schedule(time, notify);
+            }
+            None => topology
+                .message(id, message.clone(), ramp)
+                .map_err(|error| message_error(&address, error))?,
+        }
+
+        if let Some(restore) = restore {
+            let id = id.index();
+            entry.push(
+                &path,
+                Step::Message(id, message),
+                Step::Message(id, restore),
+            );
+        }
 
         Ok(true) // @todo - confirmation that message was handled / matched?
     }
+
+    /// Set the parameter `name` of a module, clamped to its range, see `AudioModuleType::params`.
+    /// Timed and ramped like the commands sent with `message`.
+    pub fn set_param(
+        &self,
+        address: Address,
+        name: &str,
+        value: f64,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> SobakaResult<bool> {
+        self.record(|root, entry| self.set_param_in(root, address, name, value, at, ramp, entry))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_param_in(
+        &self,
+        root: &mut Topology,
+        address: Address,
+        name: &str,
+        value: f64,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+        entry: &mut Entry,
+    ) -> SobakaResult<bool> {
+        let path = address.parents.clone();
+        let topology = resolve(root, &path)?;
+        let id: NodeIndex = address.clone().into();
+
+        let module = topology
+            .get(id)
+            .ok_or_else(|| SobakaError::UnknownNode(address.to_string()))?;
+        let unknown = || SobakaError::UnknownParam {
+            address: address.to_string(),
+            name: name.to_owned(),
+        };
+        let (param, previous) = module
+            .state
+            .as_ref()
+            .and_then(|state| {
+                let param = find_param(state, name)?;
+                let previous = state.get_param(param.tag)?;
+                Some((param, previous))
+            })
+            .ok_or_else(unknown)?;
+        let refused = |error| match error {
+            MessageError::UnknownParam(_) => unknown(),
+            error => message_error(&address, error),
+        };
+
+        match at {
+            Some(time) => {
+                let notify = topology
+                    .prepare_param(id, param.tag, value, ramp)
+                    .map_err(refused)?;
+                root.schedule(time, notify);
+            }
+            None => topology
+                .set_param(id, param.tag, value, ramp)
+                .map_err(refused)?,
+        }
+
+        let id = id.index();
+        entry.push(
+            &path,
+            Step::SetParam(id, param.tag, param.clamp(value)),
+            Step::SetParam(id, param.tag, previous),
+        );
+
+        Ok(true)
+    }
+
+    /// Current value of the parameter `name` of a module.
+    pub fn get_param(&self, address: Address, name: &str) -> SobakaResult<f64> {
+        let mut root = self.topology()?;
+        let topology = resolve(&mut root, &address.parents)?;
+
+        let module = topology
+            .get(address.clone().into())
+            .ok_or_else(|| SobakaError::UnknownNode(address.to_string()))?;
+
+        module
+            .state
+            .as_ref()
+            .and_then(|state| state.get_param(find_param(state, name)?.tag))
+            .ok_or_else(|| SobakaError::UnknownParam {
+                address: address.to_string(),
+                name: name.to_owned(),
+            })
+    }
+
+    /// Save the current params of a module as a preset for its module type,
+    /// replacing the preset of the same name.
+    pub fn save_preset(&self, address: Address, name: &str) -> SobakaResult<Preset> {
+        let mut root = self.topology()?;
+        let topology = resolve(&mut root, &address.parents)?;
+
+        let preset = topology
+            .get(address.clone().into())
+            .and_then(|module| module.state.as_ref())
+            .map(|state| Preset::new(name, state))
+            .ok_or_else(|| SobakaError::UnknownNode(address.to_string()))?;
+        self.presets()?.save(preset.clone());
+
+        Ok(preset)
+    }
+
+    /// Presets saved for a module type, by name, see `AudioModuleType::type_name`.
+    pub fn list_presets(&self, module_type: &str) -> SobakaResult<Vec<Preset>> {
+        Ok(self.presets()?.list(module_type))
+    }
+
+    /// Returns false when there is no such preset.
+    pub fn delete_preset(&self, module_type: &str, name: &str) -> SobakaResult<bool> {
+        Ok(self.presets()?.delete(module_type, name))
+    }
+
+    /// Save presets, e.g. listed by `list_presets` in an earlier session.
+    /// Nothing is saved when one of them is invalid.
+    pub fn load_presets(&self, presets: Vec<Preset>) -> SobakaResult<bool> {
+        for preset in &presets {
+            if preset.version != PATCH_VERSION {
+                return Err(SobakaError::InvalidPatch(format!(
+                    "unsupported version {} of preset {}",
+                    preset.version, preset.name
+                )));
+            }
+            if !preset.module.is_valid() {
+                return Err(SobakaError::InvalidModule);
+            }
+        }
+
+        let mut saved = self.presets()?;
+        for preset in presets {
+            saved.save(preset);
+        }
+
+        Ok(true)
+    }
+
+    /// Set a module to a preset saved for its module type.
+    /// Timed and ramped like the commands sent with `message`, and undone as a whole.
+    /// Params that cannot change on a running module are kept, e.g. the range of a `Parameter`.
+    pub fn apply_preset(
+        &self,
+        address: Address,
+        name: &str,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> SobakaResult<bool> {
+        self.record(|root, entry| {
+            root.begin();
+            match self.apply_preset_in(root, address, name, at, ramp, entry) {
+                Ok(applied) => {
+                    root.commit();
+                    Ok(applied)
+                }
+                Err(error) => {
+                    root.rollback();
+                    Err(error)
+                }
+            }
+        })
+    }
+
+    fn apply_preset_in(
+        &self,
+        root: &mut Topology,
+        address: Address,
+        name: &str,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+        entry: &mut Entry,
+    ) -> SobakaResult<bool> {
+        let topology = resolve(root, &address.parents)?;
+        let module_type = topology
+            .get(address.clone().into())
+            .and_then(|module| module.state.as_ref())
+            .map(AudioModuleType::type_name)
+            .ok_or_else(|| SobakaError::UnknownNode(address.to_string()))?;
+
+        let preset = self
+            .presets()?
+            .get(&module_type, name)
+            .map(|preset| preset.module.clone())
+            .ok_or_else(|| SobakaError::UnknownPreset {
+                module_type,
+                name: name.to_owned(),
+            })?;
+
+        for param in preset.params() {
+            if let Some(value) = preset.get_param(param.tag) {
+                self.set_param_in(root, address.clone(), &param.name, value, at, ramp, entry)?;
+            }
+        }
+        for command in preset.commands() {
+            self.message_in(root, address.clone(), command, at, ramp, entry)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Apply a batch of operations at the same block boundary.
+    /// Modules created in the batch can be referred to by placeholder, see `Operation`.
+    /// When an operation fails the whole batch is rolled back and nothing is applied.
+    /// The batch is undone as a whole.
+    pub fn apply(&self, operations: Vec<Operation>) -> SobakaResult<Vec<OperationResult>> {
+        self.record(|root, entry| {
+            let mut placeholders = HashMap::new();
+            let mut results = vec![];
+
+            root.begin();
+            for (index, operation) in operations.into_iter().enumerate() {
+                match self.apply_in(root, operation, &mut placeholders, entry) {
+                    Ok(result) => results.push(result),
+                    Err(error) => {
+                        root.rollback();
+                        return Err(SobakaError::Operation {
+                            index,
+                            error: Box::new(error),
+                        });
+                    }
+                }
+            }
+            root.commit();
+
+            Ok(results)
+        })
+    }
+
+    fn apply_in(
+        &self,
+        root: &mut Topology,
+        operation: Operation,
+        placeholders: &mut HashMap<String, Address>,
+        entry: &mut Entry,
+    ) -> SobakaResult<OperationResult> {
+        let address = |target: &str| resolve_placeholder(target, placeholders);
+
+        Ok(match operation {
+            Operation::Create {
+                node,
+                parent,
+                placeholder,
+            } => {
+                let parent = parent.as_deref().map(address).transpose()?;
+                let created = self.create_in(root, node, parent, entry)?;
+
+                if let Some(placeholder) = placeholder {
+                    if placeholders.contains_key(&placeholder) {
+                        return Err(SobakaError::DuplicatePlaceholder(placeholder));
+                    }
+                    placeholders.insert(placeholder, created.clone());
+                }
+
+                OperationResult::Address(created.to_string())
+            }
+            Operation::Dispose { address: target } => {
+                OperationResult::Done(self.dispose_in(root, address(&target)?, entry)?)
+            }
+            Operation::Connect { from, to, mode } => OperationResult::Connection(self.connect_in(
+                root,
+                address(&from)?,
+                address(&to)?,
+                mode,
+                entry,
+            )?),
+            Operation::Disconnect { id, parent } => {
+                let parent = parent.as_deref().map(address).transpose()?;
+                OperationResult::Done(self.disconnect_in(
+                    root,
+                    EdgeIndex::new(id),
+                    parent,
+                    entry,
+                )?)
+            }
+            Operation::SetParam {
+                address: target,
+                name,
+                value,
+                at,
+                ramp,
+            } => OperationResult::Done(self.set_param_in(
+                root,
+                address(&target)?,
+                &name,
+                value,
+                at,
+                ramp,
+                entry,
+            )?),
+            Operation::ApplyPreset {
+                address: target,
+                name,
+                at,
+                ramp,
+            } => OperationResult::Done(self.apply_preset_in(
+                root,
+                address(&target)?,
+                &name,
+                at,
+                ramp,
+                entry,
+            )?),
+            Operation::Message {
+                address: target,
+                message,
+                at,
+                ramp,
+            } => OperationResult::Done(self.message_in(
+                root,
+                address(&target)?,
+                message,
+                at,
+                ramp,
+                entry,
+            )?),
+        })
+    }
+
+    /// Snapshot of the whole patch, including subpatches, along with the seed.
+    pub fn get_patch(&self) -> SobakaResult<Patch> {
+        // The patch can still be saved once the audio thread is out of sync
+        let root = self.topology.lock().map_err(|_| SobakaError::GraphLocked)?;
+        let mut patch = root.patch();
+        patch.seed = Some(self.seed());
+
+        Ok(patch)
+    }
+
+    /// The whole patch along with the ports of every module.
+    pub fn describe(&self) -> SobakaResult<GraphDescription> {
+        Ok(self.topology()?.describe())
+    }
+
+    /// Every module that can be created, with its ports and default params.
+    pub fn list_modules(&self) -> Vec<ModuleInfo> {
+        AudioModuleType::catalogue()
+            .into_iter()
+            .map(ModuleInfo::from)
+            .collect()
+    }
+
+    /// Replace the whole patch with a snapshot from `get_patch`.
+    /// Modules and connections keep their ids. Nothing changes when the patch is invalid.
+    /// Loading a patch cannot be undone, and clears the history.
+    pub fn load_patch(&self, patch: Patch) -> SobakaResult<bool> {
+        let mut root = self.topology()?;
+
+        // Rebuild the graph in one batch, so no half built graph is ever processed
+        root.begin();
+        // Seed first, so modules are seeded as they are added
+        if let Some(seed) = patch.seed {
+            root.set_seed(seed.into());
+        }
+        if let Err(error) = self.load(&mut root, &patch) {
+            root.rollback();
+            return Err(error);
+        }
+        root.commit();
+        if let Some(seed) = patch.seed {
+            self.seed.store(seed, Ordering::Relaxed);
+        }
+        self.history()?.clear();
+
+        Ok(true)
+    }
+
+    /// Undo the last action. Returns false when there is nothing to undo.
+    /// Disposed modules come back with their connections and params.
+    pub fn undo(&self) -> SobakaResult<bool> {
+        let mut root = self.topology()?;
+        self.history()?
+            .undo(|entry| self.replay(&mut root, entry.undo()))
+    }
+
+    /// Redo the last undone action. Returns false when there is nothing to redo.
+    pub fn redo(&self) -> SobakaResult<bool> {
+        let mut root = self.topology()?;
+        self.history()?
+            .redo(|entry| self.replay(&mut root, entry.redo()))
+    }
+
+    /// Apply recorded steps in one batch, or none of them.
+    fn replay<'a>(
+        &self,
+        root: &mut Topology,
+        steps: impl Iterator<Item = (&'a [usize], &'a Step)>,
+    ) -> SobakaResult<()> {
+        root.begin();
+        for (path, step) in steps {
+            if let Err(error) = self.step_in(root, path, step) {
+                root.rollback();
+                return Err(error);
+            }
+        }
+        root.commit();
+
+        Ok(())
+    }
+
+    fn step_in(&self, root: &mut Topology, path: &[usize], step: &Step) -> SobakaResult<()> {
+        let topology = resolve(root, path)?;
+
+        let done = match step {
+            Step::Add(snapshot) => {
+                let (module, unit) = self.restore(snapshot)?;
+                let id = NodeIndex::new(snapshot.id);
+                let added = topology.add_at(id, module, unit);
+
+                for (port, &mode) in snapshot.sum_modes.iter().enumerate() {
+                    topology.set_sum_mode(id, port, mode);
+                }
+
+                added
+            }
+            Step::Remove(id) => topology.remove(NodeIndex::new(*id)),
+            Step::Connect(PatchConnection { id, from, to }) => topology.connect_at(
+                EdgeIndex::new(*id),
+                NodeIndex::new(from.0),
+                from.1,
+                NodeIndex::new(to.0),
+                to.1,
+            ),
+            Step::Disconnect(id) => topology.disconnect(EdgeIndex::new(*id)),
+            Step::SetSumMode(id, port, mode) => {
+                topology.set_sum_mode(NodeIndex::new(*id), *port, *mode);
+                true
+            }
+            Step::SetParam(id, tag, value) => topology
+                .set_param(NodeIndex::new(*id), *tag, *value, None)
+                .is_ok(),
+            Step::Message(id, message) => topology
+                .message(NodeIndex::new(*id), message.clone(), None)
+                .is_ok(),
+        };
+
+        if done {
+            Ok(())
+        } else {
+            // The graph does not match the recorded history
+            Err(SobakaError::HistoryMismatch)
+        }
+    }
+
+    /// Build every module of `patch` and check its connections before touching `topology`.
+    fn load(&self, topology: &mut Topology, patch: &Patch) -> SobakaResult<()> {
+        if patch.version != PATCH_VERSION {
+            return Err(SobakaError::InvalidPatch(format!(
+                "unsupported version {}",
+                patch.version
+            )));
+        }
+
+        let mut ports: HashMap<usize, (usize, usize)> = HashMap::new();
+        for global in [topology.global_input(), topology.global_output()] {
+            let module = topology
+                .get(global)
+                .expect("global input and output are never removed");
+            ports.insert(global.index(), (module.inputs, module.outputs));
+        }
+
+        let mut modules = vec![];
+        for snapshot in &patch.modules {
+            if snapshot.id >= MAX_ID {
+                return Err(SobakaError::InvalidPatch(format!(
+                    "module id {} is too high",
+                    snapshot.id
+                )));
+            }
+            let (module, unit) = self.restore(snapshot)?;
+
+            if ports
+                .insert(snapshot.id, (module.inputs, module.outputs))
+                .is_some()
+            {
+                return Err(SobakaError::InvalidPatch(format!(
+                    "duplicate module id {}",
+                    snapshot.id
+                )));
+            }
+
+            modules.push((
+                NodeIndex::new(snapshot.id),
+                module,
+                unit,
+                &snapshot.sum_modes,
+            ));
+        }
+
+        let mut connections = HashSet::new();
+        for connection in &patch.connections {
+            let (from, output) = connection.from;
+            let (to, input) = connection.to;
+
+            let valid = matches!(ports.get(&from), Some(&(_, outputs)) if output < outputs)
+                && matches!(ports.get(&to), Some(&(inputs, _)) if input < inputs)
+                && connection.id < MAX_ID
+                && connections.insert(connection.id);
+
+            if !valid {
+                return Err(SobakaError::InvalidPatch(format!(
+                    "unknown port, duplicate or too high id in connection {}",
+                    connection.id
+                )));
+            }
+        }
+
+        topology.clear();
+
+        for (id, module, unit, sum_modes) in modules {
+            topology.add_at(id, module, unit);
+
+            for (port, &mode) in sum_modes.iter().enumerate() {
+                topology.set_sum_mode(id, port, mode);
+            }
+        }
+
+        let mut connections = patch.connections.iter().collect::<Vec<_>>();
+        connections.sort_by_key(|connection| connection.id);
+        for connection in connections {
+            topology.connect_at(
+                EdgeIndex::new(connection.id),
+                NodeIndex::new(connection.from.0),
+                connection.from.1,
+                NodeIndex::new(connection.to.0),
+                connection.to.1,
+            );
+        }
+
+        Ok(())
+    }
 }
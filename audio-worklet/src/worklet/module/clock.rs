@@ -1,15 +1,28 @@
 use crate::{
     context::ModuleContext,
-    dsp::{messaging::MessageHandler, shared::Share},
+    dsp::{
+        clock::{clock_sync, DIVISIONS},
+        messaging::MessageHandler,
+        param::param,
+        shared::Share,
+    },
+    interface::midi::MidiMessage,
+    utils::observer::Observable,
 };
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct ClockParams {
     pub bpm: f32,
+    /// Follow the MIDI clock, transport and song position sent to the module instead of `bpm`
+    #[serde(default)]
+    pub midi_sync: bool,
+    /// Emit MIDI clock events for other devices to follow
+    #[serde(default)]
+    pub midi_out: bool,
 }
 
 /// Incoming commands into the clock module
@@ -18,23 +31,49 @@ pub struct ClockParams {
 pub enum ClockCommand {
     /// Sets the BPM of the clock
     SetBPM(f64),
+    /// Raw MIDI message, e.g. `[0xf8]` for a clock from Web MIDI. Other messages are ignored
+    Bytes(Vec<u8>),
+    /// Parsed MIDI clock, transport or song position message
+    Message(MidiMessage),
 }
 
-pub fn clock(params: &ClockParams, context: &mut ModuleContext<ClockCommand>) -> impl AudioUnit32 {
+/// Events emitted by the clock module
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub enum ClockEvent {
+    /// MIDI message to send to other devices, see `ClockParams::midi_out`
+    Midi(MidiMessage),
+}
+
+pub fn clock(
+    params: &ClockParams,
+    context: &mut ModuleContext<ClockCommand, ClockEvent>,
+) -> impl AudioUnit32 {
     let clock_square = || sine() >> map(|f| if f[0] > 0.0 { 1.0 } else { -1.0 });
 
-    let divide = [1.0, 2.0, 4.0, 8.0, 16.0];
+    let clock_divider_node =
+        branch::<U5, _, _, _>(|n| mul(DIVISIONS[n as usize] as f32) >> clock_square());
+
+    let bpm = ((pass() + param(0, params.bpm, 0.0)) >> map(|f| bpm_hz(f[0]))).share();
 
-    let clock_divider_node = branch::<U5, _, _, _>(|n| mul(divide[n as usize]) >> clock_square());
+    context.set_params(bpm.clone().param_handler());
 
-    let bpm = ((pass() + tag(0, params.bpm)) >> map(|f| bpm_hz(f[0]))).share();
+    let sync = clock_sync(clock_divider_node, params.midi_sync, params.midi_out).share();
 
     context.set_tx(
-        bpm.clone()
+        sync.clone()
             .message_handler(|unit, command: ClockCommand| match command {
-                ClockCommand::SetBPM(bpm) => unit.set(0, bpm.clamp(0.0, 600.0)),
+                // Sent as a parameter change instead
+                ClockCommand::SetBPM(_) => {}
+                ClockCommand::Bytes(bytes) => {
+                    if let Some(message) = MidiMessage::parse(&bytes) {
+                        unit.handle(message)
+                    }
+                }
+                ClockCommand::Message(message) => unit.handle(message),
             }),
     );
+    context.set_rx(sync.clone().map(ClockEvent::Midi));
 
-    bpm >> clock_divider_node
+    bpm >> sync
 }
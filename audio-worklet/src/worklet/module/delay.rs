@@ -1,12 +1,12 @@
 use crate::{
     context::ModuleContext,
-    dsp::{messaging::MessageHandler, shared::Share, trigger::reset_trigger},
+    dsp::{messaging::MessageHandler, param::param, shared::Share, trigger::reset_trigger},
 };
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct DelayParams {
     pub time: f32,
@@ -21,16 +21,11 @@ pub enum DelayCommand {
 }
 
 pub fn delay(params: &DelayParams, context: &mut ModuleContext<DelayCommand>) -> impl AudioUnit32 {
-    let inputs = pass() | (pass() + tag(0, params.time));
+    let inputs = pass() | (pass() + param(0, params.time, 0.05));
     // @todo resetting the tap delay is expensive so I should add a way to limit it
     let unit = reset_trigger(inputs >> tap(0.0, 10.0)).share();
 
-    context.set_tx(
-        unit.clone()
-            .message_handler(|unit, command: DelayCommand| match command {
-                DelayCommand::SetDelay(time) => unit.set(0, time.clamp(0.0, 10.0)),
-            }),
-    );
+    context.set_params(unit.clone().param_handler());
 
     unit
 }
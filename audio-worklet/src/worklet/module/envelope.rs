@@ -1,7 +1,8 @@
 use crate::{
     context::ModuleContext,
     dsp::{
-        envelope::dsp_envelope, messaging::MessageHandler, shared::Share, trigger::SchmittTrigger,
+        envelope::dsp_envelope, messaging::MessageHandler, param::param, shared::Share,
+        trigger::SchmittTrigger,
     },
     utils::atomic_float::AtomicFloat,
 };
@@ -9,7 +10,7 @@ use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct EnvelopeParams {
     pub attack: f32,
@@ -102,20 +103,13 @@ pub fn envelope(
     });
 
     let params = (pass() | // Gate input
-        tag(0, params.attack) |
-        tag(1, params.decay) |
-        tag(2, params.sustain) |
-        tag(3, params.release))
+        param(0, params.attack, 0.0) |
+        param(1, params.decay, 0.0) |
+        param(2, params.sustain, 0.01) |
+        param(3, params.release, 0.0))
     .share();
 
-    context.set_tx(params.clone().message_handler(
-        |unit, command: EnvelopeCommand| match command {
-            EnvelopeCommand::SetAttack(attack) => unit.set(0, attack.clamp(0.0, 10.0)),
-            EnvelopeCommand::SetDecay(decay) => unit.set(1, decay.clamp(0.0, 10.0)),
-            EnvelopeCommand::SetSustain(sustain) => unit.set(2, sustain.clamp(0.0, 1.0)),
-            EnvelopeCommand::SetRelease(release) => unit.set(3, release.clamp(0.0, 10.0)),
-        },
-    ));
+    context.set_params(params.clone().param_handler());
 
     params >> env >> declick::<f32, f32>()
 }
@@ -1,12 +1,12 @@
 use crate::{
     context::ModuleContext,
-    dsp::{messaging::MessageHandler, param::param, shared::Share, volt_hz},
+    dsp::{messaging::MessageHandler, param::fade_in_param, shared::Share, volt_hz},
 };
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct FilterParams {
     pub frequency: f32,
@@ -28,18 +28,13 @@ pub fn filter(
     context: &mut ModuleContext<FilterCommand>,
 ) -> impl AudioUnit32 {
     let input = (pass()
-        | ((pass() + param(0, params.frequency)) >> map(|f| volt_hz(f[0])) >> clip_to(2e1, 2e4))
-        | (pass() + param(1, params.q)) >> clip_to(0.0, 10.0))
+        | ((pass() + fade_in_param(0, params.frequency, 0.1))
+            >> map(|f| volt_hz(f[0]))
+            >> clip_to(2e1, 2e4))
+        | (pass() + fade_in_param(1, params.q, 0.1)) >> clip_to(0.0, 10.0))
     .share();
 
-    context.set_tx(
-        input
-            .clone()
-            .message_handler(|unit, message: FilterCommand| match message {
-                FilterCommand::SetFrequency(frequency) => unit.set(0, frequency.clamp(0.0, 10.0)),
-                FilterCommand::SetQ(q) => unit.set(1, q.clamp(0.0, 10.0)),
-            }),
-    );
+    context.set_params(input.clone().param_handler());
 
     input
         >> (lowpass::<f32, f32>()
@@ -1,40 +1,108 @@
 use crate::{
     context::ModuleContext,
-    dsp::{messaging::MessageHandler, midi::midi_poly, shared::Share},
+    dsp::{
+        messaging::MessageHandler,
+        midi::{midi_poly, Allocation, VoiceSelect, CONTROLLERS},
+        shared::Share,
+    },
+    interface::midi::MidiMessage,
 };
+
+use super::poly::MAX_CHANNELS;
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[serde(default)]
+#[ts(export)]
+pub struct MidiParams {
+    /// Channel to listen to (0-15), every channel when not set
+    pub channel: Option<u8>,
+    /// Range of pitch bend in semitones either way
+    pub bend_range: f32,
+    /// Controllers sent to the `cc_1` to `cc_4` outputs
+    pub controllers: [u8; CONTROLLERS],
+    /// Number of voices (1-16), carried as channels of every output
+    pub voices: usize,
+    /// How notes are given to the voices
+    pub allocation: Allocation,
+    /// Change the note of a held voice without closing its gate
+    pub legato: bool,
+}
+
+impl MidiParams {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CHANNELS).contains(&self.voices)
+    }
+}
+
+impl Default for MidiParams {
+    fn default() -> Self {
+        Self {
+            channel: None,
+            bend_range: 2.0,
+            // Brightness, resonance, volume and expression
+            controllers: [74, 71, 7, 11],
+            voices: 1,
+            allocation: Allocation::default(),
+            legato: false,
+        }
+    }
+}
+
 /// Incoming commands into the midi module
 #[derive(Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub enum MidiCommand {
-    /// Command when note starts (0-127)
+    /// Command when note starts (0-127), at full velocity on any channel
     NoteOn(u8),
     /// Command when note ends (0-127)
     NoteOff(u8),
+    /// Raw MIDI message, e.g. `[0x90, 60, 100]` from Web MIDI. Unsupported messages are ignored
+    Bytes(Vec<u8>),
+    /// Parsed MIDI message
+    Message(MidiMessage),
 }
 
-pub fn midi(_params: (), context: &mut ModuleContext<MidiCommand>) -> impl AudioUnit32 {
-    let notes = midi_poly::<U1, _>().share();
+/// Most voices of the module, as many as the `MAX_CHANNELS` of a polyphonic cable.
+pub type MaxVoices = U16;
+
+pub fn midi(params: &MidiParams, context: &mut ModuleContext<MidiCommand>) -> impl AudioUnit32 {
+    let notes = midi_poly::<MaxVoices, _>(
+        params.channel,
+        params.bend_range as f64,
+        params.controllers,
+        params.voices,
+        params.allocation,
+        params.legato,
+    )
+    .share();
 
     context.set_tx(
         notes
             .clone()
             .message_handler(|unit, command: MidiCommand| match command {
                 MidiCommand::NoteOn(n) => {
-                    unit.note_on(n)
+                    unit.note_on(n, 127)
                     // on note will cycle to the next branch and set the note / gate
                 }
                 MidiCommand::NoteOff(n) => {
                     unit.note_off(n)
                     // note off will set the gate for the note to 0
                 }
+                MidiCommand::Bytes(bytes) => {
+                    if let Some(message) = MidiMessage::parse(&bytes) {
+                        unit.handle(message)
+                    }
+                }
+                MidiCommand::Message(message) => unit.handle(message),
             }),
     );
+    context.set_params(notes.clone().param_handler());
 
-    // outputs 1-N gate channels
-    // outputs N-8 pitch channels
-    notes
+    // output 0: polyphonic gate port
+    // output 1: polyphonic pitch port
+    // output 2-9: polyphonic velocity, aftertouch, bend, mod wheel and controller ports
+    VoiceSelect::new(notes, MAX_CHANNELS, params.voices)
 }
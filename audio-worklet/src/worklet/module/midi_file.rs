@@ -0,0 +1,129 @@
+use std::convert::TryFrom;
+
+use crate::{
+    context::ModuleContext,
+    dsp::{
+        messaging::MessageHandler,
+        midi::{Allocation, VoiceSelect},
+        midi_file::{midi_file_player, Playlist},
+        shared::Share,
+    },
+    interface::smf::MidiFile,
+};
+use fundsp::prelude::*;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{midi::MaxVoices, poly::MAX_CHANNELS, AudioModuleCommand};
+
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[serde(default)]
+#[ts(export)]
+pub struct MidiFileParams {
+    /// Standard MIDI File of type 0 or 1
+    pub data: Option<Vec<u8>>,
+    /// Tracks played, every track when empty
+    pub tracks: Vec<usize>,
+    /// Number of voices (1-16), carried as channels of every output
+    pub voices: usize,
+    /// How notes are given to the voices
+    pub allocation: Allocation,
+    /// Pulses of the clock input per quarter note, e.g. 4 from the `1/4` output of a clock.
+    /// Files play at their own tempo when not set
+    pub clock_division: Option<u32>,
+    /// Play again from the start at the end of the file
+    pub looping: bool,
+}
+
+impl Default for MidiFileParams {
+    fn default() -> Self {
+        Self {
+            data: None,
+            tracks: vec![],
+            voices: 1,
+            allocation: Allocation::default(),
+            clock_division: None,
+            looping: true,
+        }
+    }
+}
+
+impl MidiFileParams {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CHANNELS).contains(&self.voices)
+            && self.clock_division != Some(0)
+            && self
+                .data
+                .as_ref()
+                .is_none_or(|data| MidiFile::parse(data).is_ok())
+    }
+}
+
+/// Incoming commands into the midi file module
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub enum MidiFileCommand {
+    /// Play a Standard MIDI File from the start
+    Load(Vec<u8>),
+    /// Play other tracks from the start, every track when empty
+    SetTracks(Vec<usize>),
+}
+
+/// Commands of the midi file module as handled by the player.
+/// Files are parsed and merged on the control thread, when the command is converted.
+#[derive(Clone)]
+pub enum PlayerCommand {
+    Load(Playlist),
+    SetTracks(Vec<usize>),
+}
+
+impl TryFrom<AudioModuleCommand> for PlayerCommand {
+    type Error = ();
+
+    fn try_from(command: AudioModuleCommand) -> Result<Self, Self::Error> {
+        match command {
+            AudioModuleCommand::MidiFile(MidiFileCommand::Load(data)) => MidiFile::parse(&data)
+                .map(|file| PlayerCommand::Load(Playlist::from(&file)))
+                .map_err(|_| ()),
+            AudioModuleCommand::MidiFile(MidiFileCommand::SetTracks(tracks)) => {
+                Ok(PlayerCommand::SetTracks(tracks))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn midi_file(
+    params: &MidiFileParams,
+    context: &mut ModuleContext<PlayerCommand>,
+) -> impl AudioUnit32 {
+    let mut player = midi_file_player::<MaxVoices, _>(
+        params.voices,
+        params.allocation,
+        params.clock_division,
+        params.looping,
+    );
+    player.select(params.tracks.clone());
+    if let Some(file) = params
+        .data
+        .as_ref()
+        .and_then(|data| MidiFile::parse(data).ok())
+    {
+        player.load(Playlist::from(&file));
+    }
+
+    let player = player.share();
+
+    context.set_tx(
+        player
+            .clone()
+            .message_handler(|unit, command: PlayerCommand| match command {
+                PlayerCommand::Load(playlist) => unit.load(playlist),
+                PlayerCommand::SetTracks(tracks) => unit.select(tracks),
+            }),
+    );
+
+    // input 0: clock, input 1: reset
+    // output 0-2: polyphonic gate, pitch and velocity ports
+    VoiceSelect::new(player, MAX_CHANNELS, params.voices)
+}
@@ -0,0 +1,53 @@
+use crate::{
+    context::ModuleContext,
+    dsp::{midi::CONTROLLERS, midi_out::midi_out as dsp_midi_out, shared::Share},
+    interface::midi::MidiMessage,
+    utils::observer::Observable,
+};
+use fundsp::prelude::*;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::NoOp;
+
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
+#[serde(default)]
+#[ts(export)]
+pub struct MidiOutParams {
+    /// Channel messages are sent on (0-15)
+    pub channel: u8,
+    /// Controllers sent by the `cc_1` to `cc_4` inputs, not sent when unset
+    pub controllers: [Option<u8>; CONTROLLERS],
+}
+
+impl MidiOutParams {
+    pub fn is_valid(&self) -> bool {
+        self.channel <= 15
+            && self
+                .controllers
+                .iter()
+                .flatten()
+                .all(|controller| *controller <= 127)
+    }
+}
+
+/// Events emitted by the midi out module
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub enum MidiOutEvent {
+    /// MIDI message to send to other devices
+    Midi(MidiMessage),
+}
+
+pub fn midi_out(
+    params: &MidiOutParams,
+    context: &mut ModuleContext<NoOp, MidiOutEvent>,
+) -> impl AudioUnit32 {
+    let notes = dsp_midi_out(params.channel, params.controllers).share();
+
+    context.set_rx(notes.clone().map(MidiOutEvent::Midi));
+
+    // input 0: gate, input 1: pitch, input 2: velocity, input 3-6: controllers
+    // output 0: pitch of the last note played
+    notes
+}
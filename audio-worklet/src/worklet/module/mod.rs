@@ -1,15 +1,22 @@
 use derive_more::{From, TryInto};
 use fundsp::prelude::*;
+use std::convert::TryFrom;
 pub mod clock;
 pub mod delay;
 pub mod envelope;
 pub mod filter;
 pub mod lfo;
 pub mod midi;
+pub mod midi_file;
+pub mod midi_out;
 pub mod noise;
 pub mod oscillator;
+pub mod param;
 pub mod parameter;
+pub mod poly;
+pub mod port;
 pub mod quantiser;
+pub mod registry;
 pub mod reverb;
 pub mod sample_and_hold;
 pub mod sampler;
@@ -17,22 +24,29 @@ pub mod scope;
 pub mod sequencer;
 pub mod step_sequencer;
 pub mod string;
+pub mod subpatch;
 pub mod vca;
 
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use self::{
-    clock::{clock, ClockCommand, ClockParams},
+    clock::{clock, ClockCommand, ClockEvent, ClockParams},
     delay::{delay, DelayCommand, DelayParams},
     envelope::{envelope, EnvelopeCommand, EnvelopeParams},
     filter::{filter, FilterCommand, FilterParams},
     lfo::{lfo, LfoCommand, LfoParams},
-    midi::{midi, MidiCommand},
+    midi::{midi, MidiCommand, MidiParams},
+    midi_file::{midi_file, MidiFileCommand, MidiFileParams},
+    midi_out::{midi_out, MidiOutEvent, MidiOutParams},
     noise::noise,
     oscillator::{oscillator, OscillatorCommand, OscillatorParams},
+    param::{ParamInfo, ParamUnit},
     parameter::{parameter, ParameterCommand, ParameterParams},
+    poly::{poly, PolyParams},
+    port::{numbered, PortInfo, SignalKind},
     quantiser::{quantiser, QuantiserCommand, QuantiserParams},
+    registry::{RegisteredMessage, RegisteredModule},
     reverb::{reverb, ReverbCommand, ReverbParams},
     sample_and_hold::sample_and_hold,
     sampler::{sampler, SamplerCommand, SamplerEvent, SamplerParams},
@@ -42,18 +56,27 @@ use self::{
         step_sequencer, StepSequencerCommand, StepSequencerEvent, StepSequencerParams,
     },
     string::{string, StringCommand, StringParams},
+    subpatch::{subpatch, SubpatchParams},
     vca::{vca, VcaCommand, VcaParams},
 };
-use crate::context::{GeneralContext, ModuleContext};
+use crate::{
+    context::{GeneralContext, ModuleContext},
+    dsp::{midi::CONTROLLERS, param::Curve},
+    graph::{PortIndex, SumMode},
+    interface::smf::MidiFile,
+};
 
-#[derive(Serialize, Deserialize, TS)]
+#[derive(Serialize, Deserialize, TS, Clone)]
 #[serde(tag = "node_type", content = "data")]
 #[ts(export)]
 pub enum AudioModuleType {
     Delay(DelayParams),
     Envelope(EnvelopeParams),
     // Input(InputNode),
-    Midi,
+    /// Params are optional, so patches saved before the module had params still load
+    Midi(Option<MidiParams>),
+    MidiFile(MidiFileParams),
+    MidiOut(MidiOutParams),
     // Filter(FilterNode),
     Filter(FilterParams),
     Clock(ClockParams),
@@ -71,6 +94,10 @@ pub enum AudioModuleType {
     Lfo(LfoParams),
     // Sum(SumNode),
     Vca(VcaParams),
+    Subpatch(SubpatchParams),
+    Poly(PolyParams),
+    /// Module of a type registered with `registry::register`
+    Registered(RegisteredModule),
 
     Output,
 }
@@ -84,6 +111,7 @@ pub enum AudioModuleCommand {
     Clock(ClockCommand),
     Delay(DelayCommand),
     Midi(MidiCommand),
+    MidiFile(MidiFileCommand),
     Envelope(EnvelopeCommand),
     Filter(FilterCommand),
     Oscillator(OscillatorCommand),
@@ -95,20 +123,101 @@ pub enum AudioModuleCommand {
     Scope(ScopeCommand),
     String(StringCommand),
     Lfo(LfoCommand),
+    /// Command to a registered module, in the format of its module type
+    Registered(RegisteredMessage),
 
     #[serde(skip)]
     NoOp(NoOp),
 }
 
+impl AudioModuleCommand {
+    /// Tag and value of the parameter set by the command, see `AudioModuleType::params`.
+    /// These commands are sent as parameter changes, clamped to the range of the parameter.
+    pub fn param(&self) -> Option<(Tag, f64)> {
+        Some(match self {
+            AudioModuleCommand::Clock(ClockCommand::SetBPM(bpm)) => (0, *bpm),
+            AudioModuleCommand::Delay(DelayCommand::SetDelay(time)) => (0, *time),
+            AudioModuleCommand::Envelope(command) => match command {
+                EnvelopeCommand::SetAttack(attack) => (0, *attack),
+                EnvelopeCommand::SetDecay(decay) => (1, *decay),
+                EnvelopeCommand::SetSustain(sustain) => (2, *sustain),
+                EnvelopeCommand::SetRelease(release) => (3, *release),
+            },
+            AudioModuleCommand::Filter(command) => match command {
+                FilterCommand::SetFrequency(frequency) => (0, *frequency),
+                FilterCommand::SetQ(q) => (1, *q),
+            },
+            AudioModuleCommand::Lfo(LfoCommand::SetBPM(bpm)) => (0, *bpm),
+            AudioModuleCommand::Oscillator(command) => match command {
+                OscillatorCommand::SetSawLevel(level) => (0, *level as f64),
+                OscillatorCommand::SetSineLevel(level) => (1, *level as f64),
+                OscillatorCommand::SetSquareLevel(level) => (2, *level as f64),
+                OscillatorCommand::SetTriangleLevel(level) => (3, *level as f64),
+                OscillatorCommand::SetPitch(pitch) => (4, *pitch as f64),
+            },
+            AudioModuleCommand::Parameter(ParameterCommand::SetParameter(value)) => (0, *value),
+            AudioModuleCommand::Reverb(command) => match command {
+                ReverbCommand::SetWet(wet) => (0, *wet),
+                ReverbCommand::SetDelay(time) => (1, *time),
+            },
+            AudioModuleCommand::Sequencer(SequencerCommand::UpdateStep(i, value)) => {
+                (Tag::try_from(*i).ok()?, *value)
+            }
+            AudioModuleCommand::Vca(VcaCommand::SetLevel(level)) => (0, *level),
+            _ => return None,
+        })
+    }
+
+    /// Name of the command type, as in `node_type`. Matches `AudioModuleType::type_name`
+    /// of the modules taking the command, apart from registered modules.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            AudioModuleCommand::Sequencer(_) => "Sequencer",
+            AudioModuleCommand::StepSequencer(_) => "StepSequencer",
+            AudioModuleCommand::Clock(_) => "Clock",
+            AudioModuleCommand::Delay(_) => "Delay",
+            AudioModuleCommand::Midi(_) => "Midi",
+            AudioModuleCommand::MidiFile(_) => "MidiFile",
+            AudioModuleCommand::Envelope(_) => "Envelope",
+            AudioModuleCommand::Filter(_) => "Filter",
+            AudioModuleCommand::Oscillator(_) => "Oscillator",
+            AudioModuleCommand::Parameter(_) => "Parameter",
+            AudioModuleCommand::Quantiser(_) => "Quantiser",
+            AudioModuleCommand::Reverb(_) => "Reverb",
+            AudioModuleCommand::Sampler(_) => "Sampler",
+            AudioModuleCommand::Vca(_) => "Vca",
+            AudioModuleCommand::Scope(_) => "Scope",
+            AudioModuleCommand::String(_) => "String",
+            AudioModuleCommand::Lfo(_) => "Lfo",
+            AudioModuleCommand::Registered(_) => "Registered",
+            AudioModuleCommand::NoOp(_) => "NoOp",
+        }
+    }
+
+    /// Checks the data carried by the command, with the reason it cannot be used.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            AudioModuleCommand::MidiFile(MidiFileCommand::Load(data)) => MidiFile::parse(data)
+                .map(|_| ())
+                .map_err(|error| error.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, From, Clone, TS)]
 #[serde(tag = "node_type", content = "data")]
 #[ts(export)]
 pub enum AudioModuleEvent {
+    Clock(ClockEvent),
+    MidiOut(MidiOutEvent),
     Sequencer(SequencerEvent),
     StepSequencer(StepSequencerEvent),
     Sampler(SamplerEvent),
 
     Scope(ScopeEvent),
+    /// Event of a registered module, in the format of its module type
+    Registered(RegisteredMessage),
 
     #[serde(skip)]
     NoOp(NoOp),
@@ -116,6 +225,656 @@ pub enum AudioModuleEvent {
 
 pub type ModuleUnit = Box<dyn AudioUnit32 + Send>;
 
+impl AudioModuleType {
+    /// How multiple connections to `input` are combined,
+    /// unless a mode is chosen when connecting.
+    pub fn sum_mode(&self, input: PortIndex) -> SumMode {
+        // Polyphonic modules combine inputs like the module they play
+        let module = match self {
+            AudioModuleType::Poly(params) => &*params.module,
+            module => module,
+        };
+
+        match (module, input) {
+            // Average the audio output to avoid clipping when mixing many modules
+            (AudioModuleType::Output, _) => SumMode::Average,
+            // Gates are open while any of their sources is, a low gate (-1) does not close another
+            (AudioModuleType::Delay(_), 0)
+            | (AudioModuleType::Envelope(_), 0)
+            | (AudioModuleType::Oscillator(_), 0)
+            | (AudioModuleType::SampleAndHold, 1)
+            | (AudioModuleType::MidiFile(_), 0..=1)
+            | (AudioModuleType::MidiOut(_), 0)
+            | (AudioModuleType::Sampler(_), 0)
+            | (AudioModuleType::Sequencer(_), 0..=1)
+            | (AudioModuleType::StepSequencer(_), 0..=1)
+            | (AudioModuleType::Lfo(_), 0) => SumMode::Max,
+            _ => SumMode::Sum,
+        }
+    }
+
+    /// Number of channels carried by each port of the module.
+    pub fn channels(&self) -> usize {
+        match self {
+            AudioModuleType::Midi(params) => params.as_ref().map_or(1, |params| params.voices),
+            AudioModuleType::MidiFile(params) => params.voices,
+            AudioModuleType::Poly(params) => params.channels,
+            _ => 1,
+        }
+    }
+
+    /// Named input and output ports of the module.
+    /// Each port carries `channels()` channels.
+    pub fn ports(&self) -> (Vec<PortInfo>, Vec<PortInfo>) {
+        use SignalKind::*;
+        let port = PortInfo::new;
+
+        match self {
+            AudioModuleType::Delay(_) => (
+                vec![
+                    port("reset", Gate),
+                    port("signal", Audio),
+                    port("seconds_cv", Unipolar).range(0.0, 10.0),
+                ],
+                vec![port("output", Audio)],
+            ),
+            AudioModuleType::Envelope(_) => {
+                (vec![port("gate", Gate)], vec![port("envelope", Unipolar)])
+            }
+            AudioModuleType::Midi(_) => (
+                vec![],
+                [
+                    vec![
+                        port("gate", Gate),
+                        port("pitch", Pitch),
+                        port("velocity", Unipolar),
+                        port("aftertouch", Unipolar),
+                        port("bend", Bipolar),
+                        port("mod_wheel", Unipolar),
+                    ],
+                    numbered("cc", Unipolar, CONTROLLERS),
+                ]
+                .concat(),
+            ),
+            AudioModuleType::Filter(_) => (
+                vec![
+                    port("signal", Audio),
+                    port("cutoff_cv", Pitch),
+                    port("q_cv", Unipolar).range(0.0, 10.0),
+                ],
+                vec![
+                    port("lowpass", Audio),
+                    port("highpass", Audio),
+                    port("bandpass", Audio),
+                    port("moog", Audio),
+                ],
+            ),
+            AudioModuleType::Clock(_) => (
+                vec![port("bpm_cv", Unipolar).range(0.0, 600.0)],
+                // Square waves at divisions of the beat
+                [1, 2, 4, 8, 16]
+                    .iter()
+                    .map(|division| port(&format!("1/{}", division), Gate).range(-1.0, 1.0))
+                    .collect(),
+            ),
+            AudioModuleType::Noise => (vec![], vec![port("noise", Audio)]),
+            AudioModuleType::Parameter(params) => {
+                let kind = if params.min < 0.0 { Bipolar } else { Unipolar };
+                (
+                    vec![],
+                    vec![port("output", kind).range(params.min, params.max)],
+                )
+            }
+            AudioModuleType::Oscillator(_) => (
+                [vec![port("reset", Gate)], numbered("pitch", Pitch, 4)].concat(),
+                vec![port("output", Audio)],
+            ),
+            AudioModuleType::Quantiser(_) => {
+                (numbered("signal", Pitch, 4), numbered("output", Pitch, 4))
+            }
+            AudioModuleType::String(_) => (
+                vec![port("excitation", Audio), port("pitch", Pitch)],
+                vec![port("output", Audio)],
+            ),
+            AudioModuleType::Reverb(_) | AudioModuleType::Output => (
+                vec![port("left", Audio), port("right", Audio)],
+                vec![port("left", Audio), port("right", Audio)],
+            ),
+            AudioModuleType::SampleAndHold => (
+                vec![port("signal", Bipolar), port("gate", Gate)],
+                vec![port("output", Bipolar)],
+            ),
+            AudioModuleType::MidiFile(_) => (
+                vec![port("clock", Gate), port("reset", Gate)],
+                vec![
+                    port("gate", Gate),
+                    port("pitch", Pitch),
+                    port("velocity", Unipolar),
+                ],
+            ),
+            AudioModuleType::MidiOut(_) => (
+                [
+                    vec![
+                        port("gate", Gate),
+                        port("pitch", Pitch),
+                        port("velocity", Unipolar),
+                    ],
+                    numbered("cc", Unipolar, CONTROLLERS),
+                ]
+                .concat(),
+                // Pitch of the notes sent, quantised
+                vec![port("pitch", Pitch)],
+            ),
+            AudioModuleType::Sampler(_) => (vec![port("gate", Gate)], vec![port("output", Audio)]),
+            AudioModuleType::Sequencer(_) => (
+                vec![port("gate", Gate), port("reset", Gate)],
+                vec![port("output", Pitch).range(0.0, 8.0)],
+            ),
+            AudioModuleType::StepSequencer(_) => (
+                vec![port("gate", Gate), port("reset", Gate)],
+                numbered("output", Gate, 4),
+            ),
+            AudioModuleType::Scope(_) => (
+                // The second input is not used yet
+                vec![port("signal", Audio), port("unused", Audio)],
+                vec![port("output", Audio)],
+            ),
+            AudioModuleType::Lfo(_) => (
+                vec![
+                    port("reset", Gate),
+                    port("bpm_cv", Unipolar).range(0.0, 600.0),
+                ],
+                vec![port("signal", Unipolar)],
+            ),
+            AudioModuleType::Vca(_) => (
+                vec![port("signal", Audio), port("cv", Bipolar)],
+                vec![port("output", Audio)],
+            ),
+            AudioModuleType::Subpatch(params) => (
+                numbered("input", Audio, params.inputs),
+                numbered("output", Audio, params.outputs),
+            ),
+            AudioModuleType::Poly(params) => params.module.ports(),
+            AudioModuleType::Registered(module) => module.ports().unwrap_or_default(),
+        }
+    }
+
+    /// Whether the module can be built, e.g. registered modules need a registered type.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            AudioModuleType::Poly(params) => params.is_valid(),
+            AudioModuleType::Registered(module) => module.is_valid(),
+            AudioModuleType::Midi(Some(params)) => params.is_valid(),
+            AudioModuleType::MidiFile(params) => params.is_valid(),
+            AudioModuleType::MidiOut(params) => params.is_valid(),
+            AudioModuleType::Subpatch(params) => params.is_valid(),
+            AudioModuleType::Parameter(params) => params.is_valid(),
+            _ => true,
+        }
+    }
+
+    /// Every module that can be created, with default params, registered modules last.
+    /// `Poly` is left out as it plays any of these modules, with the same ports.
+    pub fn catalogue() -> Vec<AudioModuleType> {
+        let modules = vec![
+            AudioModuleType::Delay(DelayParams { time: 2.0 }),
+            AudioModuleType::Envelope(EnvelopeParams {
+                attack: 0.1,
+                decay: 0.1,
+                sustain: 0.1,
+                release: 0.1,
+            }),
+            AudioModuleType::Midi(Some(MidiParams::default())),
+            AudioModuleType::MidiFile(MidiFileParams::default()),
+            AudioModuleType::MidiOut(MidiOutParams::default()),
+            AudioModuleType::Filter(FilterParams {
+                frequency: 0.1,
+                q: 0.1,
+            }),
+            AudioModuleType::Clock(ClockParams {
+                bpm: 120.0,
+                ..Default::default()
+            }),
+            AudioModuleType::Noise,
+            AudioModuleType::Parameter(ParameterParams {
+                min: 0.0,
+                max: 10.0,
+                default: 0.5,
+            }),
+            AudioModuleType::Oscillator(OscillatorParams {
+                pitch: 0.0,
+                saw: 0.0,
+                sine: 0.0,
+                square: 0.0,
+                triangle: 0.0,
+            }),
+            AudioModuleType::Quantiser(QuantiserParams { notes: [false; 12] }),
+            AudioModuleType::String(StringParams {
+                gain_per_second: 0.5,
+                damping: 0.5,
+            }),
+            AudioModuleType::Reverb(ReverbParams {
+                wet: 0.1,
+                length: 0.1,
+            }),
+            AudioModuleType::SampleAndHold,
+            AudioModuleType::Sampler(SamplerParams {
+                audio_data: None,
+                threshold: 45.0,
+            }),
+            AudioModuleType::Sequencer(SequencerParams { steps: [1.0; 8] }),
+            AudioModuleType::StepSequencer(StepSequencerParams {
+                steps: [[false; 8]; 4],
+            }),
+            AudioModuleType::Scope(ScopeParams { rate: 30 }),
+            AudioModuleType::Lfo(LfoParams { bpm: 120.0 }),
+            AudioModuleType::Vca(VcaParams { value: 0.5 }),
+            AudioModuleType::Subpatch(SubpatchParams {
+                inputs: 2,
+                outputs: 2,
+            }),
+            AudioModuleType::Output,
+        ];
+
+        let registered = registry::registered()
+            .into_iter()
+            .map(AudioModuleType::Registered);
+        modules.into_iter().chain(registered).collect()
+    }
+
+    /// Update params with a command sent to the module, so they describe its current state.
+    /// Parameters are clamped to their range, see `params`.
+    pub fn update(&mut self, command: &AudioModuleCommand) {
+        if let Some((tag, value)) = command.param() {
+            self.set_param(tag, value);
+            return;
+        }
+
+        match (self, command) {
+            (AudioModuleType::Poly(params), command) => params.module.update(command),
+            (AudioModuleType::Quantiser(params), AudioModuleCommand::Quantiser(command)) => {
+                match command {
+                    QuantiserCommand::UpdateNotes(notes) => params.notes = *notes,
+                }
+            }
+            (AudioModuleType::MidiFile(params), AudioModuleCommand::MidiFile(command)) => {
+                match command {
+                    MidiFileCommand::Load(data) => params.data = Some(data.clone()),
+                    MidiFileCommand::SetTracks(tracks) => params.tracks = tracks.clone(),
+                }
+            }
+            (AudioModuleType::Sampler(params), AudioModuleCommand::Sampler(command)) => {
+                match command {
+                    SamplerCommand::UpdateData(audio_data) => {
+                        params.audio_data = Some(audio_data.clone())
+                    }
+                    SamplerCommand::SetThreshold(threshold) => params.threshold = *threshold,
+                }
+            }
+            (
+                AudioModuleType::StepSequencer(params),
+                AudioModuleCommand::StepSequencer(command),
+            ) => match command {
+                StepSequencerCommand::UpdateStep((x, y), value) => {
+                    if let Some(step) = params.steps.get_mut(*x).and_then(|row| row.get_mut(*y)) {
+                        *step = *value;
+                    }
+                }
+            },
+            (AudioModuleType::String(params), AudioModuleCommand::String(command)) => match command
+            {
+                StringCommand::SetGainPerSecond(gain) => params.gain_per_second = *gain,
+                StringCommand::SetDamping(damping) => params.damping = *damping,
+            },
+            // Remaining commands do not change params, e.g. notes sent to the midi module
+            _ => {}
+        }
+    }
+
+    /// Tagged parameters of the module, in the order clients should show them.
+    pub fn params(&self) -> Vec<ParamInfo> {
+        use ParamUnit::*;
+        let param = ParamInfo::new;
+
+        match self {
+            AudioModuleType::Clock(_) | AudioModuleType::Lfo(_) => {
+                vec![param("bpm", 0, Bpm).default(120.0)]
+            }
+            AudioModuleType::Delay(_) => vec![param("time", 0, Seconds)
+                .default(2.0)
+                .curve(Curve::Exponential)],
+            AudioModuleType::Envelope(_) => vec![
+                param("attack", 0, Seconds)
+                    .default(0.1)
+                    .curve(Curve::Exponential),
+                param("decay", 1, Seconds)
+                    .default(0.1)
+                    .curve(Curve::Exponential),
+                param("sustain", 2, Level).default(0.1),
+                param("release", 3, Seconds)
+                    .default(0.1)
+                    .curve(Curve::Exponential),
+            ],
+            AudioModuleType::Filter(_) => vec![
+                param("frequency", 0, Volts).default(0.1),
+                param("q", 1, Number).default(0.1),
+            ],
+            AudioModuleType::Midi(_) => {
+                vec![param("bend_range", 0, Number).range(0.0, 24.0).default(2.0)]
+            }
+            AudioModuleType::Oscillator(_) => vec![
+                param("pitch", 4, Volts).range(-10.0, 10.0),
+                param("saw", 0, Level),
+                param("sine", 1, Level),
+                param("square", 2, Level),
+                param("triangle", 3, Level),
+            ],
+            AudioModuleType::Parameter(params) => {
+                let info = param("value", 0, Volts).range(params.min as f64, params.max as f64);
+                let default = info.clamp(params.default as f64);
+                vec![info.default(default)]
+            }
+            AudioModuleType::Reverb(_) => vec![
+                param("wet", 0, Level).default(0.1),
+                param("length", 1, Seconds)
+                    .default(0.1)
+                    .curve(Curve::Exponential),
+            ],
+            AudioModuleType::Sequencer(_) => (0..8)
+                .map(|i| param(&format!("step_{}", i + 1), i, Volts).default(1.0))
+                .collect(),
+            AudioModuleType::Vca(_) => vec![param("level", 0, Level).default(0.5)],
+            AudioModuleType::Poly(params) => params.module.params(),
+            AudioModuleType::Registered(module) => module.params(),
+            _ => vec![],
+        }
+    }
+
+    /// Parameter of the module with `tag`.
+    pub fn param(&self, tag: Tag) -> Option<ParamInfo> {
+        self.params().into_iter().find(|param| param.tag == tag)
+    }
+
+    /// Current value of the parameter with `tag`.
+    pub fn get_param(&self, tag: Tag) -> Option<f64> {
+        Some(match (self, tag) {
+            (AudioModuleType::Clock(params), 0) => params.bpm as f64,
+            (AudioModuleType::Delay(params), 0) => params.time as f64,
+            (AudioModuleType::Envelope(params), 0) => params.attack as f64,
+            (AudioModuleType::Envelope(params), 1) => params.decay as f64,
+            (AudioModuleType::Envelope(params), 2) => params.sustain as f64,
+            (AudioModuleType::Envelope(params), 3) => params.release as f64,
+            (AudioModuleType::Filter(params), 0) => params.frequency as f64,
+            (AudioModuleType::Filter(params), 1) => params.q as f64,
+            (AudioModuleType::Lfo(params), 0) => params.bpm as f64,
+            (AudioModuleType::Midi(params), 0) => {
+                params.clone().unwrap_or_default().bend_range as f64
+            }
+            (AudioModuleType::Oscillator(params), 0) => params.saw as f64,
+            (AudioModuleType::Oscillator(params), 1) => params.sine as f64,
+            (AudioModuleType::Oscillator(params), 2) => params.square as f64,
+            (AudioModuleType::Oscillator(params), 3) => params.triangle as f64,
+            (AudioModuleType::Oscillator(params), 4) => params.pitch as f64,
+            (AudioModuleType::Parameter(params), 0) => params.default as f64,
+            (AudioModuleType::Reverb(params), 0) => params.wet as f64,
+            (AudioModuleType::Reverb(params), 1) => params.length as f64,
+            (AudioModuleType::Sequencer(params), tag) => {
+                *params.steps.get(usize::try_from(tag).ok()?)? as f64
+            }
+            (AudioModuleType::Vca(params), 0) => params.value as f64,
+            (AudioModuleType::Poly(params), tag) => return params.module.get_param(tag),
+            (AudioModuleType::Registered(module), tag) => return module.get_param(tag),
+            _ => return None,
+        })
+    }
+
+    /// Set the parameter with `tag`, clamped to its range.
+    /// Returns the value set, None when the module has no such parameter.
+    pub fn set_param(&mut self, tag: Tag, value: f64) -> Option<f64> {
+        let value = self.param(tag)?.clamp(value);
+        let field = match (self, tag) {
+            (AudioModuleType::Clock(params), 0) => &mut params.bpm,
+            (AudioModuleType::Delay(params), 0) => &mut params.time,
+            (AudioModuleType::Envelope(params), 0) => &mut params.attack,
+            (AudioModuleType::Envelope(params), 1) => &mut params.decay,
+            (AudioModuleType::Envelope(params), 2) => &mut params.sustain,
+            (AudioModuleType::Envelope(params), 3) => &mut params.release,
+            (AudioModuleType::Filter(params), 0) => &mut params.frequency,
+            (AudioModuleType::Filter(params), 1) => &mut params.q,
+            (AudioModuleType::Lfo(params), 0) => &mut params.bpm,
+            (AudioModuleType::Midi(params), 0) => {
+                &mut params.get_or_insert_with(Default::default).bend_range
+            }
+            (AudioModuleType::Oscillator(params), 0) => &mut params.saw,
+            (AudioModuleType::Oscillator(params), 1) => &mut params.sine,
+            (AudioModuleType::Oscillator(params), 2) => &mut params.square,
+            (AudioModuleType::Oscillator(params), 3) => &mut params.triangle,
+            (AudioModuleType::Oscillator(params), 4) => &mut params.pitch,
+            (AudioModuleType::Parameter(params), 0) => &mut params.default,
+            (AudioModuleType::Reverb(params), 0) => &mut params.wet,
+            (AudioModuleType::Reverb(params), 1) => &mut params.length,
+            (AudioModuleType::Sequencer(params), tag) => {
+                params.steps.get_mut(usize::try_from(tag).ok()?)?
+            }
+            (AudioModuleType::Vca(params), 0) => &mut params.value,
+            (AudioModuleType::Poly(params), tag) => return params.module.set_param(tag, value),
+            (AudioModuleType::Registered(module), tag) => return module.set_param(tag, value),
+            _ => return None,
+        };
+        *field = value as f32;
+
+        Some(value)
+    }
+
+    /// Name of the module type, as in `node_type`. Polyphonic modules take the type of
+    /// the module they play, and registered modules the name they are registered under.
+    pub fn type_name(&self) -> String {
+        match self {
+            AudioModuleType::Poly(params) => return params.module.type_name(),
+            AudioModuleType::Registered(module) => return module.name.clone(),
+            AudioModuleType::Delay(_) => "Delay",
+            AudioModuleType::Envelope(_) => "Envelope",
+            AudioModuleType::Midi(_) => "Midi",
+            AudioModuleType::MidiFile(_) => "MidiFile",
+            AudioModuleType::MidiOut(_) => "MidiOut",
+            AudioModuleType::Filter(_) => "Filter",
+            AudioModuleType::Clock(_) => "Clock",
+            AudioModuleType::Noise => "Noise",
+            AudioModuleType::Parameter(_) => "Parameter",
+            AudioModuleType::Oscillator(_) => "Oscillator",
+            AudioModuleType::Quantiser(_) => "Quantiser",
+            AudioModuleType::String(_) => "String",
+            AudioModuleType::Reverb(_) => "Reverb",
+            AudioModuleType::SampleAndHold => "SampleAndHold",
+            AudioModuleType::Sampler(_) => "Sampler",
+            AudioModuleType::Sequencer(_) => "Sequencer",
+            AudioModuleType::StepSequencer(_) => "StepSequencer",
+            AudioModuleType::Scope(_) => "Scope",
+            AudioModuleType::Lfo(_) => "Lfo",
+            AudioModuleType::Vca(_) => "Vca",
+            AudioModuleType::Subpatch(_) => "Subpatch",
+            AudioModuleType::Output => "Output",
+        }
+        .to_owned()
+    }
+
+    /// Commands setting a module of the same type to these params.
+    /// Tagged parameters are left out, they are set through `set_param`.
+    pub fn commands(&self) -> Vec<AudioModuleCommand> {
+        match self {
+            AudioModuleType::Poly(params) => params.module.commands(),
+            AudioModuleType::Quantiser(params) => vec![AudioModuleCommand::Quantiser(
+                QuantiserCommand::UpdateNotes(params.notes),
+            )],
+            AudioModuleType::MidiFile(params) => {
+                let tracks = MidiFileCommand::SetTracks(params.tracks.clone());
+                let data = params.data.clone().map(MidiFileCommand::Load);
+                Some(tracks)
+                    .into_iter()
+                    .chain(data)
+                    .map(AudioModuleCommand::MidiFile)
+                    .collect()
+            }
+            AudioModuleType::Sampler(params) => {
+                let data = params.audio_data.clone().map(SamplerCommand::UpdateData);
+                let threshold = SamplerCommand::SetThreshold(params.threshold);
+                data.into_iter()
+                    .chain(Some(threshold))
+                    .map(AudioModuleCommand::Sampler)
+                    .collect()
+            }
+            AudioModuleType::StepSequencer(params) => (0..params.steps.len())
+                .flat_map(|x| (0..params.steps[x].len()).map(move |y| (x, y)))
+                .map(|(x, y)| {
+                    AudioModuleCommand::StepSequencer(StepSequencerCommand::UpdateStep(
+                        (x, y),
+                        params.steps[x][y],
+                    ))
+                })
+                .collect(),
+            AudioModuleType::String(params) => vec![
+                AudioModuleCommand::String(StringCommand::SetGainPerSecond(params.gain_per_second)),
+                AudioModuleCommand::String(StringCommand::SetDamping(params.damping)),
+            ],
+            _ => vec![],
+        }
+    }
+
+    /// Command setting back the value that `command` changes, taken from the current params.
+    /// None when `command` does not change params or the previous value cannot be sent back.
+    pub fn restore(&self, command: &AudioModuleCommand) -> Option<AudioModuleCommand> {
+        Some(match (self, command) {
+            (AudioModuleType::Poly(params), command) => return params.module.restore(command),
+            (AudioModuleType::Clock(params), AudioModuleCommand::Clock(command)) => match command {
+                ClockCommand::SetBPM(_) => {
+                    AudioModuleCommand::Clock(ClockCommand::SetBPM(params.bpm as f64))
+                }
+                ClockCommand::Bytes(_) | ClockCommand::Message(_) => return None,
+            },
+            (AudioModuleType::Delay(params), AudioModuleCommand::Delay(command)) => match command {
+                DelayCommand::SetDelay(_) => {
+                    AudioModuleCommand::Delay(DelayCommand::SetDelay(params.time as f64))
+                }
+            },
+            (AudioModuleType::Envelope(params), AudioModuleCommand::Envelope(command)) => {
+                AudioModuleCommand::Envelope(match command {
+                    EnvelopeCommand::SetAttack(_) => {
+                        EnvelopeCommand::SetAttack(params.attack as f64)
+                    }
+                    EnvelopeCommand::SetDecay(_) => EnvelopeCommand::SetDecay(params.decay as f64),
+                    EnvelopeCommand::SetSustain(_) => {
+                        EnvelopeCommand::SetSustain(params.sustain as f64)
+                    }
+                    EnvelopeCommand::SetRelease(_) => {
+                        EnvelopeCommand::SetRelease(params.release as f64)
+                    }
+                })
+            }
+            (AudioModuleType::Filter(params), AudioModuleCommand::Filter(command)) => {
+                AudioModuleCommand::Filter(match command {
+                    FilterCommand::SetFrequency(_) => {
+                        FilterCommand::SetFrequency(params.frequency as f64)
+                    }
+                    FilterCommand::SetQ(_) => FilterCommand::SetQ(params.q as f64),
+                })
+            }
+            (AudioModuleType::Lfo(params), AudioModuleCommand::Lfo(command)) => match command {
+                LfoCommand::SetBPM(_) => {
+                    AudioModuleCommand::Lfo(LfoCommand::SetBPM(params.bpm as f64))
+                }
+            },
+            (AudioModuleType::Oscillator(params), AudioModuleCommand::Oscillator(command)) => {
+                AudioModuleCommand::Oscillator(match command {
+                    OscillatorCommand::SetPitch(_) => OscillatorCommand::SetPitch(params.pitch),
+                    OscillatorCommand::SetSawLevel(_) => OscillatorCommand::SetSawLevel(params.saw),
+                    OscillatorCommand::SetSineLevel(_) => {
+                        OscillatorCommand::SetSineLevel(params.sine)
+                    }
+                    OscillatorCommand::SetSquareLevel(_) => {
+                        OscillatorCommand::SetSquareLevel(params.square)
+                    }
+                    OscillatorCommand::SetTriangleLevel(_) => {
+                        OscillatorCommand::SetTriangleLevel(params.triangle)
+                    }
+                })
+            }
+            (AudioModuleType::Parameter(params), AudioModuleCommand::Parameter(command)) => {
+                match command {
+                    ParameterCommand::SetParameter(_) => AudioModuleCommand::Parameter(
+                        ParameterCommand::SetParameter(params.default as f64),
+                    ),
+                }
+            }
+            (AudioModuleType::Quantiser(params), AudioModuleCommand::Quantiser(command)) => {
+                match command {
+                    QuantiserCommand::UpdateNotes(_) => {
+                        AudioModuleCommand::Quantiser(QuantiserCommand::UpdateNotes(params.notes))
+                    }
+                }
+            }
+            (AudioModuleType::Reverb(params), AudioModuleCommand::Reverb(command)) => {
+                AudioModuleCommand::Reverb(match command {
+                    ReverbCommand::SetWet(_) => ReverbCommand::SetWet(params.wet as f64),
+                    ReverbCommand::SetDelay(_) => ReverbCommand::SetDelay(params.length as f64),
+                })
+            }
+            (AudioModuleType::MidiFile(params), AudioModuleCommand::MidiFile(command)) => {
+                AudioModuleCommand::MidiFile(match command {
+                    MidiFileCommand::Load(_) => MidiFileCommand::Load(params.data.clone()?),
+                    MidiFileCommand::SetTracks(_) => {
+                        MidiFileCommand::SetTracks(params.tracks.clone())
+                    }
+                })
+            }
+            (AudioModuleType::Sampler(params), AudioModuleCommand::Sampler(command)) => {
+                AudioModuleCommand::Sampler(match command {
+                    // Samplers created without audio data cannot go back to having none
+                    SamplerCommand::UpdateData(_) => {
+                        SamplerCommand::UpdateData(params.audio_data.clone()?)
+                    }
+                    SamplerCommand::SetThreshold(_) => {
+                        SamplerCommand::SetThreshold(params.threshold)
+                    }
+                })
+            }
+            (AudioModuleType::Sequencer(params), AudioModuleCommand::Sequencer(command)) => {
+                match command {
+                    SequencerCommand::UpdateStep(i, _) => AudioModuleCommand::Sequencer(
+                        SequencerCommand::UpdateStep(*i, *params.steps.get(*i)? as f64),
+                    ),
+                }
+            }
+            (
+                AudioModuleType::StepSequencer(params),
+                AudioModuleCommand::StepSequencer(command),
+            ) => match command {
+                StepSequencerCommand::UpdateStep((x, y), _) => {
+                    let step = *params.steps.get(*x)?.get(*y)?;
+                    AudioModuleCommand::StepSequencer(StepSequencerCommand::UpdateStep(
+                        (*x, *y),
+                        step,
+                    ))
+                }
+            },
+            (AudioModuleType::String(params), AudioModuleCommand::String(command)) => {
+                AudioModuleCommand::String(match command {
+                    StringCommand::SetGainPerSecond(_) => {
+                        StringCommand::SetGainPerSecond(params.gain_per_second)
+                    }
+                    StringCommand::SetDamping(_) => StringCommand::SetDamping(params.damping),
+                })
+            }
+            (AudioModuleType::Vca(params), AudioModuleCommand::Vca(command)) => match command {
+                VcaCommand::SetLevel(_) => {
+                    AudioModuleCommand::Vca(VcaCommand::SetLevel(params.value as f64))
+                }
+            },
+            // Remaining commands do not change params
+            _ => return None,
+        })
+    }
+}
+
 impl From<&AudioModuleType> for (ModuleUnit, GeneralContext) {
     fn from(node_type: &AudioModuleType) -> Self {
         match node_type {
@@ -187,14 +946,36 @@ impl From<&AudioModuleType> for (ModuleUnit, GeneralContext) {
                 let mut ctx = ModuleContext::<NoOp, NoOp>::default();
                 (Box::new(sample_and_hold((), &mut ctx)), ctx.boxed())
             }
-            AudioModuleType::Midi => {
+            AudioModuleType::Midi(params) => {
                 let mut ctx = ModuleContext::default();
-                (Box::new(midi((), &mut ctx)), ctx.boxed())
+                let params = params.clone().unwrap_or_default();
+                (Box::new(midi(&params, &mut ctx)), ctx.boxed())
+            }
+            AudioModuleType::MidiFile(params) => {
+                let mut ctx = ModuleContext::default();
+                (Box::new(midi_file(params, &mut ctx)), ctx.boxed())
+            }
+            AudioModuleType::MidiOut(params) => {
+                let mut ctx = ModuleContext::default();
+                (Box::new(midi_out(params, &mut ctx)), ctx.boxed())
             }
             AudioModuleType::Sampler(params) => {
                 let mut ctx = ModuleContext::default();
                 (Box::new(sampler(params, &mut ctx)), ctx.boxed())
             }
+            AudioModuleType::Poly(params) => poly(params),
+            AudioModuleType::Registered(module) => module.create().unwrap_or_else(|| {
+                // Unregistered modules are refused by `is_valid` before they are built
+                let ctx = ModuleContext::<NoOp, NoOp>::default();
+                (Box::new(multipass::<U0, f32>()), ctx.boxed())
+            }),
+            AudioModuleType::Subpatch(params) => {
+                // Without its topology the inner graph cannot be edited and stays silent,
+                // `AudioProcessor::create` keeps hold of it instead
+                let ctx = ModuleContext::<NoOp, NoOp>::default();
+                let (_topology, engine) = subpatch(params);
+                (Box::new(engine), ctx.boxed())
+            }
         }
     }
 }
@@ -202,3 +983,189 @@ impl From<&AudioModuleType> for (ModuleUnit, GeneralContext) {
 /// Placeholder type used to represent no-op event or command
 #[derive(Clone)]
 pub struct NoOp;
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{
+        midi_file::{MidiFileCommand, MidiFileParams, PlayerCommand},
+        midi_out::MidiOutParams,
+        poly::PolyParams,
+        port::SignalKind,
+        AudioModuleCommand, AudioModuleType, MidiParams, ModuleUnit, ParameterParams,
+        SequencerCommand, SequencerParams, StepSequencerParams, VcaCommand, VcaParams,
+    };
+    use crate::{context::GeneralContext, graph::SumMode};
+
+    #[test]
+    fn test_ports_match_units() {
+        let mut modules = AudioModuleType::catalogue();
+        modules.push(AudioModuleType::Poly(PolyParams {
+            channels: 3,
+            module: Box::new(modules[0].clone()),
+        }));
+        modules.push(AudioModuleType::Midi(Some(MidiParams {
+            voices: 5,
+            ..Default::default()
+        })));
+
+        for module in modules {
+            let (unit, _context): (ModuleUnit, GeneralContext) = (&module).into();
+            let (inputs, outputs) = module.ports();
+            let channels = module.channels();
+
+            assert_eq!(inputs.len() * channels, unit.inputs());
+            assert_eq!(outputs.len() * channels, unit.outputs());
+        }
+    }
+
+    #[test]
+    fn test_params_match_units() {
+        let mut modules = AudioModuleType::catalogue();
+        modules.push(AudioModuleType::Poly(PolyParams {
+            channels: 3,
+            module: Box::new(AudioModuleType::Vca(VcaParams { value: 0.5 })),
+        }));
+
+        for mut module in modules {
+            let (unit, _context): (ModuleUnit, GeneralContext) = (&module).into();
+
+            for param in module.params() {
+                // Units are tagged with the values of their params
+                let value = module.get_param(param.tag).unwrap();
+                assert_eq!(unit.get(param.tag), Some(value as f32 as f64));
+
+                // Values are clamped to the range of the param
+                assert_eq!(
+                    module.set_param(param.tag, param.max + 1.0),
+                    Some(param.max)
+                );
+                assert_eq!(module.get_param(param.tag), Some(param.max));
+            }
+        }
+    }
+
+    #[test]
+    fn test_type_names_match_node_types() {
+        for module in AudioModuleType::catalogue() {
+            let node_type = serde_json::to_value(&module).unwrap()["node_type"].clone();
+            match module {
+                AudioModuleType::Registered(_) => {
+                    assert_eq!(node_type, "Registered")
+                }
+                module => assert_eq!(node_type, module.type_name()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sum_modes_per_input() {
+        // The signal is mixed while the gate is open when any of its sources is
+        let module = AudioModuleType::SampleAndHold;
+        assert_eq!(module.sum_mode(0), SumMode::Sum);
+        assert_eq!(module.sum_mode(1), SumMode::Max);
+
+        // Every gate input declares a mode
+        for module in AudioModuleType::catalogue() {
+            for (input, port) in module.ports().0.iter().enumerate() {
+                if port.kind == SignalKind::Gate {
+                    assert_eq!(module.sum_mode(input), SumMode::Max, "{}", port.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parameter_range() {
+        let module = AudioModuleType::Parameter(ParameterParams {
+            min: 1.0,
+            max: 5.0,
+            default: 2.0,
+        });
+        assert!(module.is_valid());
+        assert_eq!(module.params()[0].default, 2.0);
+
+        // Inverted ranges are refused, their params can still be listed
+        let module = AudioModuleType::Parameter(ParameterParams {
+            min: 1.0,
+            max: -1.0,
+            default: 0.0,
+        });
+        assert!(!module.is_valid());
+        assert_eq!(module.params()[0].clamp(0.5), -1.0);
+    }
+
+    #[test]
+    fn test_midi_file_defaults() {
+        let params: MidiFileParams = serde_json::from_str("{}").unwrap();
+        assert_eq!(params.voices, 1);
+        assert!(params.looping);
+
+        // Files are parsed when the command is converted, before it is sent
+        let command = AudioModuleCommand::MidiFile(MidiFileCommand::Load(vec![0; 4]));
+        assert!(PlayerCommand::try_from(command).is_err());
+    }
+
+    #[test]
+    fn test_midi_out_range() {
+        let params = MidiOutParams {
+            channel: 15,
+            controllers: [Some(127), None, Some(1), None],
+        };
+        assert!(AudioModuleType::MidiOut(params.clone()).is_valid());
+
+        // Channels and controllers past the range of MIDI are refused
+        let channel = MidiOutParams {
+            channel: 16,
+            ..params.clone()
+        };
+        assert!(!AudioModuleType::MidiOut(channel).is_valid());
+        let controllers = MidiOutParams {
+            controllers: [Some(128), None, None, None],
+            ..params
+        };
+        assert!(!AudioModuleType::MidiOut(controllers).is_valid());
+    }
+
+    #[test]
+    fn test_commands_set_params() {
+        let mut vca = AudioModuleType::Vca(VcaParams { value: 0.5 });
+        let command = AudioModuleCommand::Vca(VcaCommand::SetLevel(2.0));
+        assert_eq!(command.param(), Some((0, 2.0)));
+
+        vca.update(&command);
+        assert_eq!(vca.get_param(0), Some(1.0));
+
+        // Steps past the end of the sequencer are not params
+        let command = AudioModuleCommand::Sequencer(SequencerCommand::UpdateStep(8, 1.0));
+        let sequencer = AudioModuleType::Sequencer(SequencerParams { steps: [1.0; 8] });
+        let (tag, _) = command.param().unwrap();
+        assert_eq!(sequencer.param(tag), None);
+    }
+
+    #[test]
+    fn test_commands_recreate_params() {
+        let mut steps = [[false; 8]; 4];
+        steps[1][2] = true;
+        let module = AudioModuleType::StepSequencer(StepSequencerParams { steps });
+        let mut other = AudioModuleType::StepSequencer(StepSequencerParams {
+            steps: [[true; 8]; 4],
+        });
+
+        for command in module.commands() {
+            other.update(&command);
+        }
+        assert_eq!(
+            serde_json::to_value(&other).unwrap(),
+            serde_json::to_value(&module).unwrap()
+        );
+
+        // Polyphonic modules share the type of the module they play
+        let poly = AudioModuleType::Poly(PolyParams {
+            channels: 2,
+            module: Box::new(module),
+        });
+        assert_eq!(poly.type_name(), "StepSequencer");
+    }
+}
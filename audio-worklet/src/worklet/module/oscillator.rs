@@ -3,6 +3,7 @@ use crate::dsp::{
     join::dsp_join,
     messaging::MessageHandler,
     oscillator::{sobaka_saw, sobaka_square, sobaka_triangle},
+    param::param,
     shared::Share,
     trigger::reset_trigger,
     volt_hz,
@@ -11,7 +12,7 @@ use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct OscillatorParams {
     pub pitch: f32,
@@ -43,12 +44,12 @@ pub fn oscillator(
 ) -> impl AudioUnit32 {
     let multi_osc = stack::<U4, _, _, _>(|_n| {
         let input = split::<U2, _>()
-            >> ((pass() + tag(4, 0.0)) | pass())
+            >> ((pass() + param(4, params.pitch, 0.0)) | pass())
             >> (map::<_, _, U1, _>(|pitch| volt_hz(pitch[0])) | pass());
-        let attenuated_saw = sobaka_saw() * tag(0, params.saw);
-        let attenuated_sine = sine_phase(0.0) * tag(1, params.sine);
-        let attenuated_square = sobaka_square() * tag(2, params.square);
-        let attenuated_triangle = sobaka_triangle() * tag(3, params.triangle);
+        let attenuated_saw = sobaka_saw() * param(0, params.saw, 0.01);
+        let attenuated_sine = sine_phase(0.0) * param(1, params.sine, 0.01);
+        let attenuated_square = sobaka_square() * param(2, params.square, 0.01);
+        let attenuated_triangle = sobaka_triangle() * param(3, params.triangle, 0.01);
 
         input
             >> ((attenuated_saw & attenuated_sine & attenuated_square & attenuated_triangle)
@@ -60,20 +61,7 @@ pub fn oscillator(
 
     let out = reset_trigger(oversample(multi_osc)).share();
 
-    context.set_tx(
-        out.clone()
-            .message_handler(|unit, command: OscillatorCommand| match command {
-                OscillatorCommand::SetPitch(pitch) => unit.set(4, pitch.into()),
-                OscillatorCommand::SetSawLevel(level) => unit.set(0, level.clamp(0.0, 1.0).into()),
-                OscillatorCommand::SetSineLevel(level) => unit.set(1, level.clamp(0.0, 1.0).into()),
-                OscillatorCommand::SetSquareLevel(level) => {
-                    unit.set(2, level.clamp(0.0, 1.0).into())
-                }
-                OscillatorCommand::SetTriangleLevel(level) => {
-                    unit.set(3, level.clamp(0.0, 1.0).into())
-                }
-            }),
-    );
+    context.set_params(out.clone().param_handler());
 
     out
 }
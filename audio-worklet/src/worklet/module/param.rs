@@ -0,0 +1,87 @@
+use fundsp::hacker::Tag;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::dsp::param::Curve;
+
+/// What a parameter value measures, so clients know how to display it.
+#[derive(Serialize, TS, Clone, Copy, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub enum ParamUnit {
+    /// Volts, e.g. pitch in volts per octave
+    Volts,
+    /// Time in seconds
+    Seconds,
+    /// Beats per minute
+    Bpm,
+    /// Level between zero and one
+    Level,
+    /// Plain number, e.g. the Q factor of a filter
+    Number,
+}
+
+impl ParamUnit {
+    /// Range usually taken by parameters in the unit.
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            ParamUnit::Volts | ParamUnit::Seconds | ParamUnit::Number => (0.0, 10.0),
+            ParamUnit::Bpm => (0.0, 600.0),
+            ParamUnit::Level => (0.0, 1.0),
+        }
+    }
+}
+
+/// A named module parameter, set through its tag.
+#[derive(Serialize, TS, Clone, Debug, PartialEq)]
+#[ts(export)]
+pub struct ParamInfo {
+    pub name: String,
+    #[ts(type = "number")]
+    pub tag: Tag,
+    /// Lowest value, lower values are clamped
+    pub min: f64,
+    /// Highest value, higher values are clamped
+    pub max: f64,
+    /// Value of a new module
+    pub default: f64,
+    pub unit: ParamUnit,
+    /// How a control should move through the range, e.g. exponential for times
+    pub curve: Curve,
+}
+
+impl ParamInfo {
+    /// Parameter taking the usual range of `unit`, starting at its lowest value.
+    pub fn new(name: &str, tag: Tag, unit: ParamUnit) -> Self {
+        let (min, max) = unit.range();
+        Self {
+            name: name.to_owned(),
+            tag,
+            min,
+            max,
+            default: min,
+            unit,
+            curve: Curve::Linear,
+        }
+    }
+
+    /// Override the range.
+    pub fn range(self, min: f64, max: f64) -> Self {
+        Self { min, max, ..self }
+    }
+
+    /// Override the value of a new module.
+    pub fn default(self, default: f64) -> Self {
+        Self { default, ..self }
+    }
+
+    /// Override how a control moves through the range.
+    pub fn curve(self, curve: Curve) -> Self {
+        Self { curve, ..self }
+    }
+
+    /// Clamp `value` to the range of the parameter.
+    /// Unlike `f64::clamp`, an inverted range does not panic.
+    pub fn clamp(&self, value: f64) -> f64 {
+        value.max(self.min).min(self.max)
+    }
+}
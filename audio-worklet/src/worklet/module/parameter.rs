@@ -1,10 +1,10 @@
 use super::ModuleContext;
-use crate::dsp::{messaging::MessageHandler, param::param32, shared::Share};
+use crate::dsp::{messaging::MessageHandler, param::fade_in_param32, shared::Share};
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct ParameterParams {
     pub min: f32,
@@ -12,6 +12,12 @@ pub struct ParameterParams {
     pub default: f32,
 }
 
+impl ParameterParams {
+    pub fn is_valid(&self) -> bool {
+        self.min <= self.max
+    }
+}
+
 /// Incoming commands into the parameter module
 #[derive(Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
@@ -24,20 +30,9 @@ pub fn parameter(
     params: &ParameterParams,
     context: &mut ModuleContext<ParameterCommand>,
 ) -> impl AudioUnit32 {
-    let min = params.min;
-    let max = params.max;
-
-    let param = param32(0, params.default).share();
+    let param = fade_in_param32(0, params.default, 0.1).share();
 
-    context.set_tx(
-        param
-            .clone()
-            .message_handler(move |unit, command: ParameterCommand| match command {
-                ParameterCommand::SetParameter(value) => {
-                    unit.set(0, value.clamp(min as f64, max as f64))
-                }
-            }),
-    );
+    context.set_params(param.clone().param_handler());
 
     param
 }
@@ -0,0 +1,103 @@
+use fundsp::hacker::Tag;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{AudioModuleCommand, AudioModuleEvent, AudioModuleType, ModuleUnit};
+use crate::{
+    context::{GeneralContext, GeneralMessaging, MessageError, Notify},
+    dsp::{param::Ramp, poly::PolyUnit},
+    utils::observer::Observer,
+};
+
+/// Highest number of channels carried by a polyphonic cable.
+pub const MAX_CHANNELS: usize = 16;
+
+/// Polyphonic version of another module, with one voice per channel.
+#[derive(Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub struct PolyParams {
+    /// Number of voices (1-16)
+    pub channels: usize,
+    /// Module played by each voice
+    pub module: Box<AudioModuleType>,
+}
+
+impl PolyParams {
+    /// Subpatches and modules that are already polyphonic cannot be made polyphonic.
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CHANNELS).contains(&self.channels)
+            && self.module.is_valid()
+            && self.module.channels() == 1
+            && !matches!(
+                *self.module,
+                AudioModuleType::Poly(_) | AudioModuleType::Subpatch(_)
+            )
+    }
+}
+
+/// Context forwarding commands to every voice.
+struct PolyContext(Vec<GeneralContext>);
+
+impl GeneralMessaging for PolyContext {
+    fn try_notify(&self, message: AudioModuleCommand, ramp: Option<Ramp>) -> Result<(), ()> {
+        self.0
+            .iter()
+            .try_for_each(|voice| voice.try_notify(message.clone(), ramp))
+    }
+
+    fn try_prepare(
+        &self,
+        message: AudioModuleCommand,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError> {
+        let voices = self
+            .0
+            .iter()
+            .map(|voice| voice.try_prepare(message.clone(), ramp))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Box::new(move || {
+            voices.into_iter().for_each(|notify| notify())
+        }))
+    }
+
+    fn try_prepare_param(
+        &self,
+        tag: Tag,
+        value: f64,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError> {
+        let voices = self
+            .0
+            .iter()
+            .map(|voice| voice.try_prepare_param(tag, value, ramp))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Box::new(move || {
+            voices.into_iter().for_each(|notify| notify())
+        }))
+    }
+
+    /// Events of all voices are merged into one stream
+    fn try_observe(&self) -> Result<Observer<AudioModuleEvent>, ()> {
+        let observers = self
+            .0
+            .iter()
+            .map(|voice| voice.try_observe())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(stream::select_all(observers).boxed())
+    }
+}
+
+pub fn poly(params: &PolyParams) -> (ModuleUnit, GeneralContext) {
+    let (units, contexts): (Vec<ModuleUnit>, Vec<GeneralContext>) = (0..params.channels)
+        .map(|_| (&*params.module).into())
+        .unzip();
+
+    (
+        Box::new(PolyUnit::new(units)),
+        Box::new(PolyContext(contexts)),
+    )
+}
@@ -0,0 +1,66 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+/// What a port carries, so clients know which ports make sense to connect.
+#[derive(Serialize, TS, Clone, Copy, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub enum SignalKind {
+    /// Audio rate signal
+    Audio,
+    /// Pitch in volts per octave, 0V is C0
+    Pitch,
+    /// Gate or trigger, open while high
+    Gate,
+    /// Control voltage between zero and a maximum
+    Unipolar,
+    /// Control voltage around zero
+    Bipolar,
+}
+
+impl SignalKind {
+    /// Range usually carried by the kind of signal.
+    pub fn range(&self) -> (f32, f32) {
+        match self {
+            SignalKind::Audio | SignalKind::Bipolar => (-1.0, 1.0),
+            SignalKind::Pitch => (0.0, 10.0),
+            SignalKind::Gate | SignalKind::Unipolar => (0.0, 1.0),
+        }
+    }
+}
+
+/// A named module port.
+#[derive(Serialize, TS, Clone, Debug, PartialEq)]
+#[ts(export)]
+pub struct PortInfo {
+    pub name: String,
+    pub kind: SignalKind,
+    /// Lowest expected value
+    pub min: f32,
+    /// Highest expected value
+    pub max: f32,
+}
+
+impl PortInfo {
+    /// Port expecting the usual range of `kind`.
+    pub fn new(name: &str, kind: SignalKind) -> Self {
+        let (min, max) = kind.range();
+        Self {
+            name: name.to_owned(),
+            kind,
+            min,
+            max,
+        }
+    }
+
+    /// Override the expected range.
+    pub fn range(self, min: f32, max: f32) -> Self {
+        Self { min, max, ..self }
+    }
+}
+
+/// Ports numbered from 1, e.g. `pitch_1` to `pitch_4`.
+pub fn numbered(name: &str, kind: SignalKind, count: usize) -> Vec<PortInfo> {
+    (1..=count)
+        .map(|n| PortInfo::new(&format!("{}_{}", name, n), kind))
+        .collect()
+}
@@ -0,0 +1,315 @@
+//! Module types registered at runtime, e.g. from other crates or behind cargo features,
+//! so adding a module does not require a variant of `AudioModuleType`.
+//!
+//! The built-in modules keep their variants, which fix the format of patches and of the
+//! generated TypeScript types. Registered modules sit next to them as
+//! `AudioModuleType::Registered`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use fundsp::hacker::Tag;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::{ExportError, TS};
+
+use super::{param::ParamInfo, port::PortInfo, ModuleUnit};
+use crate::context::{GeneralContext, ModuleContext};
+
+/// Command or event of a registered module, as JSON in the format of its module type.
+#[derive(Serialize, Deserialize, TS, Clone, Debug, PartialEq)]
+#[ts(export)]
+pub struct RegisteredMessage(#[ts(type = "unknown")] pub Value);
+
+/// Context of registered modules, see `AudioModuleCommand::Registered`
+/// and `AudioModuleEvent::Registered`.
+pub type RegisteredContext = ModuleContext<RegisteredMessage, RegisteredMessage>;
+
+/// Builds modules of a type registered with `register`.
+pub trait ModuleFactory: Send + Sync + 'static {
+    /// Params of the module, stored as JSON in patches.
+    /// Fields named after the params listed by `params` hold their current values.
+    type Params: Serialize + DeserializeOwned + TS;
+
+    /// Name of the module type, unique among registered modules
+    fn name(&self) -> &'static str;
+
+    /// Params of a new module, listed by `list_modules`
+    fn default_params(&self) -> Self::Params;
+
+    /// Named input and output ports of the module
+    fn ports(&self, params: &Self::Params) -> (Vec<PortInfo>, Vec<PortInfo>);
+
+    /// Tagged parameters of the module, see `AudioModuleType::params`
+    fn params(&self, _params: &Self::Params) -> Vec<ParamInfo> {
+        vec![]
+    }
+
+    /// Build the audio unit, setting the command and parameter handlers on `context`
+    fn create(&self, params: &Self::Params, context: &mut RegisteredContext) -> ModuleUnit;
+}
+
+/// `ModuleFactory` taking its params as JSON, so factories of any params can be stored together.
+trait Factory: Send + Sync {
+    fn default_params(&self) -> Value;
+
+    /// None when `params` are not params of the module
+    fn ports(&self, params: &Value) -> Option<(Vec<PortInfo>, Vec<PortInfo>)>;
+
+    fn params(&self, params: &Value) -> Option<Vec<ParamInfo>>;
+
+    fn create(&self, params: &Value) -> Option<(ModuleUnit, GeneralContext)>;
+
+    /// Name of the params type in TypeScript, along with its bindings
+    fn bindings(&self) -> Result<(String, String), ExportError>;
+}
+
+impl<F: ModuleFactory> Factory for F {
+    fn default_params(&self) -> Value {
+        serde_json::to_value(ModuleFactory::default_params(self)).unwrap_or(Value::Null)
+    }
+
+    fn ports(&self, params: &Value) -> Option<(Vec<PortInfo>, Vec<PortInfo>)> {
+        let params = serde_json::from_value(params.clone()).ok()?;
+        Some(ModuleFactory::ports(self, &params))
+    }
+
+    fn params(&self, params: &Value) -> Option<Vec<ParamInfo>> {
+        let params = serde_json::from_value(params.clone()).ok()?;
+        Some(ModuleFactory::params(self, &params))
+    }
+
+    fn create(&self, params: &Value) -> Option<(ModuleUnit, GeneralContext)> {
+        let params = serde_json::from_value(params.clone()).ok()?;
+        let mut ctx = RegisteredContext::default();
+        let unit = ModuleFactory::create(self, &params, &mut ctx);
+        Some((unit, ctx.boxed()))
+    }
+
+    fn bindings(&self) -> Result<(String, String), ExportError> {
+        Ok((F::Params::name(), F::Params::export_to_string()?))
+    }
+}
+
+static FACTORIES: RwLock<BTreeMap<&'static str, Arc<dyn Factory>>> = RwLock::new(BTreeMap::new());
+
+fn factory(name: &str) -> Option<Arc<dyn Factory>> {
+    FACTORIES.read().ok()?.get(name).cloned()
+}
+
+/// Register a module type, so it can be created as `AudioModuleType::Registered`.
+/// Returns false when a module type is already registered under the same name.
+pub fn register(factory: impl ModuleFactory) -> bool {
+    let mut factories = match FACTORIES.write() {
+        Ok(factories) => factories,
+        Err(_) => return false,
+    };
+    if factories.contains_key(factory.name()) {
+        return false;
+    }
+    factories.insert(factory.name(), Arc::new(factory));
+
+    true
+}
+
+/// Every registered module type, with default params.
+pub fn registered() -> Vec<RegisteredModule> {
+    FACTORIES
+        .read()
+        .map(|factories| {
+            factories
+                .iter()
+                .map(|(name, factory)| RegisteredModule {
+                    name: name.to_string(),
+                    params: factory.default_params(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Export TypeScript bindings of registered modules into `directory`, next to the
+/// bindings of this crate: the params of each module, and `RegisteredModules.ts`
+/// with the type of every registered module.
+pub fn export_bindings(directory: impl AsRef<Path>) -> Result<(), ExportError> {
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+
+    let mut imports = vec![];
+    let mut modules = vec![];
+    let factories = FACTORIES.read().map(|factories| factories.clone());
+    for (name, factory) in factories.unwrap_or_default() {
+        let (params, bindings) = factory.bindings()?;
+        fs::write(directory.join(format!("{}.ts", params)), bindings)?;
+
+        imports.push(format!("import type {{ {0} }} from \"./{0}\";\n", params));
+        modules.push(format!("{{ name: \"{}\", params: {} }}", name, params));
+    }
+    if modules.is_empty() {
+        modules.push("never".to_owned());
+    }
+
+    let bindings = format!(
+        "{}\nexport type RegisteredModules = {};",
+        imports.concat(),
+        modules.join(" | ")
+    );
+    fs::write(directory.join("RegisteredModules.ts"), bindings)?;
+
+    Ok(())
+}
+
+/// Module of a registered type, see `register`.
+#[derive(Serialize, Deserialize, TS, Clone, Debug, PartialEq)]
+#[ts(export)]
+pub struct RegisteredModule {
+    /// Name the module type is registered under
+    pub name: String,
+    #[ts(type = "unknown")]
+    pub params: Value,
+}
+
+impl RegisteredModule {
+    /// The module type is registered and accepts the params.
+    pub fn is_valid(&self) -> bool {
+        self.ports().is_some()
+    }
+
+    pub fn ports(&self) -> Option<(Vec<PortInfo>, Vec<PortInfo>)> {
+        factory(&self.name)?.ports(&self.params)
+    }
+
+    pub fn params(&self) -> Vec<ParamInfo> {
+        factory(&self.name)
+            .and_then(|factory| factory.params(&self.params))
+            .unwrap_or_default()
+    }
+
+    /// Current value of the parameter with `tag`, held by the field of the same name.
+    pub fn get_param(&self, tag: Tag) -> Option<f64> {
+        let param = self.params().into_iter().find(|param| param.tag == tag)?;
+        self.params.get(&param.name)?.as_f64()
+    }
+
+    /// Set the parameter with `tag`, clamped to its range.
+    pub fn set_param(&mut self, tag: Tag, value: f64) -> Option<f64> {
+        let param = self.params().into_iter().find(|param| param.tag == tag)?;
+        let value = param.clamp(value);
+        *self.params.get_mut(&param.name)? = value.into();
+
+        Some(value)
+    }
+
+    /// Build the module, None when it is not valid.
+    pub fn create(&self) -> Option<(ModuleUnit, GeneralContext)> {
+        factory(&self.name)?.create(&self.params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fundsp::prelude::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use ts_rs::TS;
+
+    use super::{register, registered, ModuleFactory, RegisteredContext, RegisteredModule};
+    use crate::{
+        dsp::{messaging::MessageHandler, param::param32, shared::Share},
+        module::{
+            param::{ParamInfo, ParamUnit},
+            port::{PortInfo, SignalKind},
+            AudioModuleType, ModuleUnit,
+        },
+    };
+
+    #[derive(Serialize, Deserialize, TS)]
+    struct GainParams {
+        level: f32,
+    }
+
+    struct Gain;
+
+    impl ModuleFactory for Gain {
+        type Params = GainParams;
+
+        fn name(&self) -> &'static str {
+            "Gain"
+        }
+
+        fn default_params(&self) -> GainParams {
+            GainParams { level: 0.5 }
+        }
+
+        fn ports(&self, _params: &GainParams) -> (Vec<PortInfo>, Vec<PortInfo>) {
+            (
+                vec![PortInfo::new("signal", SignalKind::Audio)],
+                vec![PortInfo::new("output", SignalKind::Audio)],
+            )
+        }
+
+        fn params(&self, _params: &GainParams) -> Vec<ParamInfo> {
+            vec![ParamInfo::new("level", 0, ParamUnit::Level).default(0.5)]
+        }
+
+        fn create(&self, params: &GainParams, context: &mut RegisteredContext) -> ModuleUnit {
+            let unit = (pass() * param32(0, params.level, 0.0)).share();
+            context.set_params(unit.clone().param_handler());
+            Box::new(unit)
+        }
+    }
+
+    #[test]
+    fn test_registered_modules() {
+        register(Gain);
+        // Names are unique
+        assert!(!register(Gain));
+
+        let gain = RegisteredModule {
+            name: "Gain".to_owned(),
+            params: json!({ "level": 0.5 }),
+        };
+        assert!(registered().contains(&gain));
+
+        let mut module = AudioModuleType::Registered(gain);
+        assert!(module.is_valid());
+        assert_eq!(module.ports().0[0].name, "signal");
+        assert_eq!(module.set_param(0, 2.0), Some(1.0));
+        assert_eq!(module.get_param(0), Some(1.0));
+
+        let (mut unit, _context): (ModuleUnit, _) = (&module).into();
+        assert_eq!(unit.get(0), Some(1.0));
+        let mut output = [0.0];
+        unit.tick(&[0.5], &mut output);
+        assert_eq!(output, [0.5]);
+
+        // Unregistered types and params of another module cannot be built
+        let unknown = RegisteredModule {
+            name: "Unknown".to_owned(),
+            params: json!({ "level": 0.5 }),
+        };
+        let invalid = RegisteredModule {
+            name: "Gain".to_owned(),
+            params: json!({ "steps": [] }),
+        };
+        assert!(!AudioModuleType::Registered(unknown).is_valid());
+        assert!(!AudioModuleType::Registered(invalid).is_valid());
+    }
+
+    #[test]
+    fn test_export_bindings() {
+        register(Gain);
+
+        let directory = std::env::temp_dir().join("sobaka-registered-bindings");
+        super::export_bindings(&directory).unwrap();
+
+        let modules = std::fs::read_to_string(directory.join("RegisteredModules.ts")).unwrap();
+        assert!(modules.contains("import type { GainParams } from \"./GainParams\";"));
+        assert!(modules.contains("{ name: \"Gain\", params: GainParams }"));
+        assert!(directory.join("GainParams.ts").exists());
+    }
+}
@@ -1,10 +1,10 @@
 use super::ModuleContext;
-use crate::dsp::{messaging::MessageHandler, param::param, shared::Share};
+use crate::dsp::{messaging::MessageHandler, param::fade_in_param, shared::Share};
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct ReverbParams {
     pub wet: f32,
@@ -52,7 +52,7 @@ where
     ];
 
     let line = stack::<U32, T, _, _>(|i| {
-        let a = param::<T>(1, T::from_f64(time))
+        let a = fade_in_param::<T>(1, T::from_f64(time), 0.1)
             >> map(|t: &Frame<T, U1>| T::from_f32(fast_pow(db_amp(-60.0), 0.03 / t[0].to_f32())));
 
         delay::<T>(DELAYS[i as usize])
@@ -63,9 +63,9 @@ where
     // The feedback structure.
     let reverb = fdn::<U32, T, _>(line);
 
-    let wet_mix = (pass() | pass()) * (param::<T>(0, wet) >> (pass() ^ pass()));
-    let dry_mix =
-        (pass() | pass()) * (param::<T>(0, wet) >> map(|f| T::one() - f[0]) >> (pass() ^ pass()));
+    let wet_mix = (pass() | pass()) * (fade_in_param::<T>(0, wet, 0.1) >> (pass() ^ pass()));
+    let dry_mix = (pass() | pass())
+        * (fade_in_param::<T>(0, wet, 0.1) >> map(|f| T::one() - f[0]) >> (pass() ^ pass()));
 
     // Multiplex stereo into 32 channels, reverberate, then average them back.
     // Bus the reverb with the dry signal. Operator precedences work perfectly for us here.
@@ -78,14 +78,7 @@ pub fn reverb(
 ) -> impl AudioUnit32 {
     let reverb = reverb_stereo::<f32, f32>(params.wet, params.length.into()).share();
 
-    context.set_tx(
-        reverb
-            .clone()
-            .message_handler(|unit, message: ReverbCommand| match message {
-                ReverbCommand::SetWet(wet) => unit.set(0, wet.clamp(0.0, 1.0)),
-                ReverbCommand::SetDelay(time) => unit.set(1, time.clamp(0.0, 10.0)),
-            }),
-    );
+    context.set_params(reverb.clone().param_handler());
 
     reverb
 }
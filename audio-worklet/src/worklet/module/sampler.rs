@@ -19,7 +19,7 @@ pub struct AudioData {
     pub sample_rate: f32,
 }
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct SamplerParams {
     pub audio_data: Option<AudioData>,
@@ -2,6 +2,7 @@ use super::ModuleContext;
 use crate::{
     dsp::{
         messaging::MessageHandler,
+        param::param,
         shared::Share,
         stepped::{stepped, SteppedEvent},
     },
@@ -11,7 +12,7 @@ use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct SequencerParams {
     pub steps: [f32; 8],
@@ -37,13 +38,9 @@ pub fn sequencer(
     params: &SequencerParams,
     context: &mut ModuleContext<SequencerCommand, SequencerEvent>,
 ) -> impl AudioUnit32 {
-    let steps = branch::<U8, _, _, _>(|i| tag(i, params.steps[i as usize])).share();
+    let steps = branch::<U8, _, _, _>(|i| param(i, params.steps[i as usize], 0.0)).share();
 
-    context.set_tx(steps.clone().message_handler(
-        |unit, command: SequencerCommand| match command {
-            SequencerCommand::UpdateStep(i, value) => unit.set(i as i64, value),
-        },
-    ));
+    context.set_params(steps.clone().param_handler());
 
     let stepped = stepped::<U8, U1, _>(false).share();
 
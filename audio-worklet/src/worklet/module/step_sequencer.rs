@@ -2,6 +2,7 @@ use super::ModuleContext;
 use crate::{
     dsp::{
         messaging::MessageHandler,
+        param::param,
         shared::Share,
         stepped::{stepped, SteppedEvent},
     },
@@ -11,7 +12,7 @@ use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct StepSequencerParams {
     pub steps: [[bool; 8]; 4],
@@ -39,13 +40,14 @@ pub fn step_sequencer(
 ) -> impl AudioUnit32 {
     let steps = branch::<U4, _, _, _>(|x| {
         branch::<U8, _, _, _>(|y| {
-            tag(
+            param(
                 y,
                 if params.steps[x as usize][y as usize] {
                     1.0
                 } else {
                     0.0
                 },
+                0.0,
             )
         })
     })
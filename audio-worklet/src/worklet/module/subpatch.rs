@@ -0,0 +1,26 @@
+use crate::{engine::AudioEngine, graph::Graph32, topology::Topology};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Highest number of inputs or outputs of a subpatch.
+pub const MAX_PORTS: usize = 64;
+
+/// A module wrapping an inner graph.
+/// Inside the subpatch, module 0 outputs the subpatch inputs
+/// and module 1 takes the subpatch outputs.
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
+#[ts(export)]
+pub struct SubpatchParams {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl SubpatchParams {
+    pub fn is_valid(&self) -> bool {
+        self.inputs <= MAX_PORTS && self.outputs <= MAX_PORTS
+    }
+}
+
+pub fn subpatch(params: &SubpatchParams) -> (Topology, AudioEngine) {
+    Topology::new(Graph32::with_ports(params.inputs, params.outputs))
+}
@@ -1,10 +1,10 @@
 use super::ModuleContext;
-use crate::dsp::{messaging::MessageHandler, param::param32, shared::Share};
+use crate::dsp::{messaging::MessageHandler, param::fade_in_param32, shared::Share};
 use fundsp::prelude::*;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-#[derive(Default, Serialize, Deserialize, TS)]
+#[derive(Default, Serialize, Deserialize, TS, Clone)]
 #[ts(export)]
 pub struct VcaParams {
     pub value: f32,
@@ -19,14 +19,9 @@ pub enum VcaCommand {
 }
 
 pub fn vca(params: &VcaParams, context: &mut ModuleContext<VcaCommand>) -> impl AudioUnit32 {
-    let unit = (pass() * (pass() + param32(0, params.value))).share();
+    let unit = (pass() * (pass() + fade_in_param32(0, params.value, 0.1))).share();
 
-    context.set_tx(
-        unit.clone()
-            .message_handler(|unit, message: VcaCommand| match message {
-                VcaCommand::SetLevel(value) => unit.set(0, value.clamp(0.0, 1.0)),
-            }),
-    );
+    context.set_params(unit.clone().param_handler());
 
     unit
 }
@@ -0,0 +1,367 @@
+//! OSC interface to the processor, for controllers such as TouchOSC.
+//!
+//! Messages sent under `/sobaka` map to operations, named by the last part of their address:
+//! - `/sobaka/2/frequency 440.0` sets the parameter `frequency` of module 2.
+//! - `/sobaka/2/message "<json>"` sends a command to module 2, e.g. `{ "node_type": "Clock", ... }`.
+//! - `/sobaka/2/preset "name"` applies a preset to module 2.
+//! - `/sobaka/2/dispose` disposes of module 2.
+//! - `/sobaka/create "<json>" ["placeholder"]` creates a module, `/sobaka/4/create` inside subpatch 4.
+//! - `/sobaka/connect "/sobaka/2/out-0" "/sobaka/3/in-0" ["Average"]` connects two ports.
+//! - `/sobaka/disconnect 7`, or `/sobaka/4/disconnect 7` inside subpatch 4, removes a connection.
+//!
+//! The messages of a bundle are applied as one batch, so they can refer to modules created
+//! earlier in the bundle by placeholder, e.g. `/sobaka/$osc/frequency`.
+
+use std::{convert::TryFrom, sync::Arc};
+
+use serde_json::Value;
+
+use crate::{
+    interface::{
+        address::Address,
+        error::SobakaError,
+        operation::{Operation, OperationResult},
+        osc::{OscArgument, OscMessage, OscPacket, TimeTag},
+        time::CommandTime,
+    },
+    module::AudioModuleEvent,
+    AudioProcessor, SobakaResult,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod udp;
+
+/// Applies OSC packets to a processor.
+pub struct OscRouter {
+    processor: Arc<AudioProcessor>,
+    /// Time at which the clock of the audio context started, bundles are applied
+    /// as soon as they are received when not set
+    epoch: Option<TimeTag>,
+}
+
+impl OscRouter {
+    pub fn new(processor: Arc<AudioProcessor>, epoch: Option<TimeTag>) -> Self {
+        Self { processor, epoch }
+    }
+
+    /// Apply the messages of a packet as one batch.
+    pub fn apply(&self, packet: &OscPacket) -> SobakaResult<Vec<OperationResult>> {
+        let mut operations = vec![];
+        self.operations(packet, None, &mut operations)?;
+        self.processor.apply(operations)
+    }
+
+    fn operations(
+        &self,
+        packet: &OscPacket,
+        at: Option<CommandTime>,
+        operations: &mut Vec<Operation>,
+    ) -> SobakaResult<()> {
+        match packet {
+            OscPacket::Message(message) => operations.push(operation(message, at)?),
+            OscPacket::Bundle(bundle) => {
+                let at = match self.epoch {
+                    Some(epoch) if bundle.time != TimeTag::IMMEDIATELY => {
+                        Some(CommandTime::Seconds(bundle.time.seconds_since(epoch)))
+                    }
+                    _ => at,
+                };
+
+                for packet in &bundle.content {
+                    self.operations(packet, at, operations)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(message: &OscMessage, reason: &str) -> SobakaError {
+    SobakaError::InvalidOsc(format!("{} {}", message.address, reason))
+}
+
+fn string_arg(message: &OscMessage, index: usize) -> SobakaResult<String> {
+    message
+        .args
+        .get(index)
+        .and_then(OscArgument::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| invalid(message, &format!("expects a string argument {}", index)))
+}
+
+fn json_arg<T: serde::de::DeserializeOwned>(message: &OscMessage, index: usize) -> SobakaResult<T> {
+    serde_json::from_str(&string_arg(message, index)?)
+        .map_err(|error| invalid(message, &error.to_string()))
+}
+
+/// Operation of a message, timed at `at` when it sends a command or sets a parameter.
+pub fn operation(message: &OscMessage, at: Option<CommandTime>) -> SobakaResult<Operation> {
+    let parts = message
+        .address
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+
+    let (method, path) = match parts[..] {
+        ["sobaka", ref path @ .., method] => (method, path),
+        _ => return Err(invalid(message, "is not a sobaka address")),
+    };
+
+    // Placeholders stand for the start of an address, e.g. `$voice/1`
+    let target = match path {
+        [] => None,
+        [first, ..] if first.starts_with('$') => Some(path.join("/")),
+        _ => Some(format!("/sobaka/{}", path.join("/"))),
+    };
+    let address = || {
+        target
+            .clone()
+            .ok_or_else(|| invalid(message, "needs a module"))
+    };
+    let ramp = None;
+
+    Ok(match method {
+        "create" => Operation::Create {
+            node: json_arg(message, 0)?,
+            parent: target.clone(),
+            placeholder: string_arg(message, 1).ok(),
+        },
+        "dispose" => Operation::Dispose {
+            address: address()?,
+        },
+        "connect" => Operation::Connect {
+            from: string_arg(message, 0)?,
+            to: string_arg(message, 1)?,
+            mode: match message.args.get(2) {
+                Some(_) => Some(
+                    serde_json::from_value(Value::String(string_arg(message, 2)?))
+                        .map_err(|error| invalid(message, &error.to_string()))?,
+                ),
+                None => None,
+            },
+        },
+        "disconnect" => Operation::Disconnect {
+            id: match message.args.first().and_then(OscArgument::as_f64) {
+                Some(id) if id >= 0.0 => id as usize,
+                _ => return Err(invalid(message, "expects a connection id")),
+            },
+            parent: target.clone(),
+        },
+        "message" => Operation::Message {
+            address: address()?,
+            message: json_arg(message, 0)?,
+            at,
+            ramp,
+        },
+        "preset" => Operation::ApplyPreset {
+            address: address()?,
+            name: string_arg(message, 0)?,
+            at,
+            ramp,
+        },
+        name => Operation::SetParam {
+            address: address()?,
+            name: name.to_owned(),
+            value: message
+                .args
+                .first()
+                .and_then(OscArgument::as_f64)
+                .ok_or_else(|| invalid(message, "expects a number"))?,
+            at,
+            ramp,
+        },
+    })
+}
+
+/// Message of an event of the module at `address`, named after the variants of the event.
+/// Fields are sent as arguments by name, e.g. `/sobaka/2/Midi/NoteOn 0 60 100`
+/// for the channel, note and velocity.
+pub fn event_message(address: &Address, event: &AudioModuleEvent) -> Option<OscMessage> {
+    let mut value = serde_json::to_value(event).ok()?.get_mut("data")?.take();
+    let mut name = address.to_string();
+
+    // Variants of externally tagged enums are objects with a single field, or strings
+    let value = loop {
+        value = match value {
+            Value::Object(object) if object.len() == 1 => {
+                let (variant, inner) = object.into_iter().next()?;
+                name = format!("{}/{}", name, variant);
+                inner
+            }
+            Value::String(variant) => {
+                name = format!("{}/{}", name, variant);
+                break Value::Null;
+            }
+            value => break value,
+        }
+    };
+
+    let mut args = vec![];
+    flatten(value, &mut args);
+    Some(OscMessage::new(name, args))
+}
+
+/// Arguments of a JSON value, the items of arrays in order and the fields of objects by name.
+fn flatten(value: Value, args: &mut Vec<OscArgument>) {
+    match value {
+        Value::Null => {}
+        Value::Bool(value) => args.push(OscArgument::Bool(value)),
+        Value::Number(number) => args.push(match (number.as_i64(), number.as_f64()) {
+            (Some(value), _) => match i32::try_from(value) {
+                Ok(value) => OscArgument::Int(value),
+                Err(_) => OscArgument::Long(value),
+            },
+            (None, Some(value)) => OscArgument::Float(value as f32),
+            _ => OscArgument::Nil,
+        }),
+        Value::String(value) => args.push(OscArgument::String(value)),
+        Value::Array(values) => values.into_iter().for_each(|value| flatten(value, args)),
+        Value::Object(fields) => fields
+            .into_iter()
+            .for_each(|(_, value)| flatten(value, args)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        interface::{
+            address::Address,
+            midi::MidiMessage,
+            operation::{Operation, OperationResult},
+            osc::{OscArgument, OscBundle, OscMessage, OscPacket, TimeTag},
+            time::CommandTime,
+        },
+        module::{midi_out::MidiOutEvent, AudioModuleEvent},
+        AudioProcessor,
+    };
+
+    use super::{event_message, operation, OscRouter};
+
+    fn message(address: &str, args: Vec<OscArgument>) -> OscMessage {
+        OscMessage::new(address, args)
+    }
+
+    fn string(value: &str) -> OscArgument {
+        OscArgument::String(value.to_owned())
+    }
+
+    #[test]
+    fn test_map_messages() {
+        let set = operation(
+            &message("/sobaka/3/7/frequency", vec![OscArgument::Int(440)]),
+            Some(CommandTime::Seconds(1.0)),
+        )
+        .unwrap();
+        assert!(matches!(
+            set,
+            Operation::SetParam { address, name, value, at: Some(_), .. }
+                if address == "/sobaka/3/7" && name == "frequency" && value == 440.0
+        ));
+
+        let connect = operation(
+            &message(
+                "/sobaka/connect",
+                vec![
+                    string("$osc/out-0"),
+                    string("/sobaka/0/in-0"),
+                    string("Average"),
+                ],
+            ),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            connect,
+            Operation::Connect { from, mode: Some(_), .. } if from == "$osc/out-0"
+        ));
+
+        let dispose = operation(&message("/sobaka/$osc/dispose", vec![]), None).unwrap();
+        assert!(matches!(dispose, Operation::Dispose { address } if address == "$osc"));
+
+        // Parameters need a module and a value, other addresses are refused
+        assert!(operation(
+            &message("/sobaka/frequency", vec![OscArgument::Float(1.0)]),
+            None
+        )
+        .is_err());
+        assert!(operation(&message("/sobaka/2/frequency", vec![string("loud")]), None).is_err());
+        assert!(operation(
+            &message("/other/2/frequency", vec![OscArgument::Float(1.0)]),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_apply_bundles() {
+        let (processor, _engine) = AudioProcessor::new();
+        let processor = Arc::new(processor);
+        let router = OscRouter::new(processor.clone(), None);
+
+        let bundle = OscPacket::Bundle(OscBundle {
+            time: TimeTag::IMMEDIATELY,
+            content: vec![
+                OscPacket::Message(message(
+                    "/sobaka/create",
+                    vec![
+                        string(r#"{ "node_type": "Vca", "data": { "value": 0.5 } }"#),
+                        string("vca"),
+                    ],
+                )),
+                OscPacket::Message(message(
+                    "/sobaka/$vca/level",
+                    vec![OscArgument::Float(0.25)],
+                )),
+            ],
+        });
+        let results = router.apply(&bundle).unwrap();
+
+        let address = match &results[0] {
+            OperationResult::Address(address) => address.parse::<Address>().unwrap(),
+            result => panic!("expected an address, got {:?}", result),
+        };
+        assert_eq!(processor.get_param(address.clone(), "level").unwrap(), 0.25);
+
+        // Bundles are applied as a whole, or not at all
+        let failing = OscPacket::Bundle(OscBundle {
+            time: TimeTag::IMMEDIATELY,
+            content: vec![
+                OscPacket::Message(message("/sobaka/2/level", vec![OscArgument::Float(1.0)])),
+                OscPacket::Message(message("/sobaka/9/level", vec![OscArgument::Float(1.0)])),
+            ],
+        });
+        assert!(router.apply(&failing).is_err());
+        assert_eq!(processor.get_param(address, "level").unwrap(), 0.25);
+    }
+
+    #[test]
+    fn test_event_messages() {
+        let address = "/sobaka/4".parse::<Address>().unwrap();
+        let event = AudioModuleEvent::MidiOut(MidiOutEvent::Midi(MidiMessage::NoteOn {
+            channel: 0,
+            note: 60,
+            velocity: 100,
+        }));
+        assert_eq!(
+            event_message(&address, &event),
+            Some(message(
+                "/sobaka/4/Midi/NoteOn",
+                vec![
+                    OscArgument::Int(0),
+                    OscArgument::Int(60),
+                    OscArgument::Int(100)
+                ]
+            ))
+        );
+
+        let event = AudioModuleEvent::MidiOut(MidiOutEvent::Midi(MidiMessage::Clock));
+        assert_eq!(
+            event_message(&address, &event),
+            Some(message("/sobaka/4/Midi/Clock", vec![]))
+        );
+    }
+}
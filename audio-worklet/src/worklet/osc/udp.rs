@@ -0,0 +1,206 @@
+//! OSC over UDP, for hosts running the processor natively.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+    thread,
+};
+
+use futures::{executor::block_on, future, StreamExt};
+
+use super::{event_message, OscRouter};
+use crate::{
+    interface::{
+        address::Address,
+        error::SobakaError,
+        osc::{OscArgument, OscMessage, OscPacket, TimeTag},
+    },
+    AudioProcessor, SobakaResult,
+};
+
+/// Largest payload of a UDP datagram.
+const MAX_PACKET_SIZE: usize = 65_507;
+
+/// Errors about a single peer rather than the socket, e.g. some platforms report
+/// an earlier reply to a closed port as a reset on the next receive.
+fn is_peer_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// Receives OSC packets on a UDP socket and applies them to a processor,
+/// sending the events of modules back as OSC.
+pub struct OscSocket {
+    socket: Arc<UdpSocket>,
+    router: OscRouter,
+    processor: Arc<AudioProcessor>,
+}
+
+impl OscSocket {
+    /// Bind to a local address, e.g. `127.0.0.1:9000`. See `OscRouter` for `epoch`.
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        processor: Arc<AudioProcessor>,
+        epoch: Option<TimeTag>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            socket: Arc::new(UdpSocket::bind(address)?),
+            router: OscRouter::new(processor.clone(), epoch),
+            processor,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Wait for a packet and apply it, returning its sender.
+    /// Packets that cannot be applied are answered with `/sobaka/error`,
+    /// with the code and message of the error. Replies that cannot be sent are dropped.
+    pub fn receive(&self) -> io::Result<SocketAddr> {
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        let (length, peer) = self.socket.recv_from(&mut buffer)?;
+
+        let result = OscPacket::decode(&buffer[..length])
+            .map_err(|error| SobakaError::InvalidOsc(error.to_string()))
+            .and_then(|packet| self.router.apply(&packet));
+
+        if let Err(error) = result {
+            let reply = OscMessage::new(
+                "/sobaka/error",
+                vec![
+                    OscArgument::Int(error.code() as i32),
+                    OscArgument::String(error.to_string()),
+                ],
+            );
+            // Replies that cannot be sent are dropped, as on any UDP link
+            let _ = self
+                .socket
+                .send_to(&OscPacket::Message(reply).encode(), peer);
+        }
+
+        Ok(peer)
+    }
+
+    /// Apply packets as they are received, until the socket fails.
+    /// Errors about a single peer, see `is_peer_error`, do not stop serving.
+    pub fn serve(&self) -> io::Result<()> {
+        loop {
+            match self.receive() {
+                Err(error) if !is_peer_error(&error) => return Err(error),
+                _ => {}
+            }
+        }
+    }
+
+    /// Send the events of the module at `address` to `peer` from another thread,
+    /// until the module is disposed of. See `event_message` for their format.
+    pub fn forward(&self, address: Address, peer: SocketAddr) -> SobakaResult<()> {
+        let events = self.processor.subscribe(address.clone())?;
+        let socket = self.socket.clone();
+
+        thread::spawn(move || {
+            block_on(events.for_each(|event| {
+                if let Some(message) = event_message(&address, &event) {
+                    // Events that cannot be sent are dropped, as on any UDP link
+                    let _ = socket.send_to(&OscPacket::Message(message).encode(), peer);
+                }
+                future::ready(())
+            }))
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, sync::Arc, time::Duration};
+
+    use fundsp::{hacker32::AudioUnit32, MAX_BUFFER_SIZE};
+
+    use super::OscSocket;
+    use crate::{
+        interface::{
+            address::Address,
+            osc::{OscArgument, OscBundle, OscMessage, OscPacket, TimeTag},
+        },
+        AudioProcessor,
+    };
+
+    fn receive(client: &UdpSocket) -> OscPacket {
+        let mut buffer = [0; 1024];
+        let (length, _) = client.recv_from(&mut buffer).unwrap();
+        OscPacket::decode(&buffer[..length]).unwrap()
+    }
+
+    #[test]
+    fn test_loopback() {
+        let (processor, mut engine) = AudioProcessor::new();
+        let server = OscSocket::bind("127.0.0.1:0", Arc::new(processor), None).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let create = OscPacket::Bundle(OscBundle {
+            time: TimeTag::IMMEDIATELY,
+            content: vec![OscPacket::Message(OscMessage::new(
+                "/sobaka/create",
+                vec![OscArgument::String(
+                    r#"{ "node_type": "MidiOut", "data": { "controllers": [74, null, null, null] } }"#
+                        .into(),
+                )],
+            ))],
+        });
+        client
+            .send_to(&create.encode(), server.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(server.receive().unwrap(), client.local_addr().unwrap());
+
+        // The controller of the first module sends its first value once processed
+        let address = "/sobaka/2".parse::<Address>().unwrap();
+        server
+            .forward(address, client.local_addr().unwrap())
+            .unwrap();
+
+        let mut outputs = [[0.0; MAX_BUFFER_SIZE]; 3];
+        let [left, right, scope] = &mut outputs;
+        engine.process(MAX_BUFFER_SIZE, &[], &mut [left, right, scope]);
+
+        assert_eq!(
+            receive(&client),
+            OscPacket::Message(OscMessage::new(
+                "/sobaka/2/Midi/ControlChange",
+                vec![
+                    OscArgument::Int(0),
+                    OscArgument::Int(74),
+                    OscArgument::Int(0)
+                ]
+            ))
+        );
+
+        // Failures are answered with the error
+        let set = OscPacket::Message(OscMessage::new(
+            "/sobaka/9/level",
+            vec![OscArgument::Float(1.0)],
+        ));
+        client
+            .send_to(&set.encode(), server.local_addr().unwrap())
+            .unwrap();
+        server.receive().unwrap();
+
+        match receive(&client) {
+            OscPacket::Message(message) => {
+                assert_eq!(message.address, "/sobaka/error");
+                assert_eq!(message.args[0], OscArgument::Int(-32001));
+            }
+            packet => panic!("expected an error, got {:?}", packet),
+        }
+    }
+}
@@ -5,7 +5,16 @@ use jsonrpc_core::Result;
 use jsonrpc_derive::rpc;
 use jsonrpc_pubsub::{typed, SubscriptionId};
 
-use crate::interface::address::Address;
+use crate::dsp::param::Ramp;
+use crate::graph::SumMode;
+use crate::interface::{
+    address::Address,
+    describe::{GraphDescription, ModuleInfo},
+    operation::{Operation, OperationResult},
+    patch::Patch,
+    preset::Preset,
+    time::CommandTime,
+};
 use crate::module::{AudioModuleCommand, AudioModuleEvent, AudioModuleType};
 
 #[rpc(server)]
@@ -13,24 +22,119 @@ pub trait SobakaGraphRpc {
     type Metadata;
 
     /// Create a new instane of node
+    /// Optionally inside the subpatch at `parent`
     #[rpc(name = "create")]
-    fn create(&self, node_state: AudioModuleType) -> Result<Address>;
+    fn create(&self, node_state: AudioModuleType, parent: Option<Address>) -> Result<Address>;
 
     /// Dispose of node instance
     #[rpc(name = "dispose")]
     fn dispose(&self, address: Address) -> Result<bool>;
 
     /// Connect the output of module a to the input type of module b
+    /// Optionally sets how multiple connections to the input are combined
     #[rpc(name = "connect")]
-    fn connect(&self, from: Address, to: Address) -> Result<usize>;
+    fn connect(&self, from: Address, to: Address, mode: Option<SumMode>) -> Result<usize>;
 
     /// Remove connection by connection id
+    /// Connections inside a subpatch are found through its `parent` address
     #[rpc(name = "disconnect")]
-    fn disconnect(&self, id: usize) -> Result<bool>;
+    fn disconnect(&self, id: usize, parent: Option<Address>) -> Result<bool>;
 
     /// Update the state of a node
+    /// Optionally on the exact sample at `at`, otherwise before the next block
+    /// Parameters changed by the message ramp to their new value along `ramp` when given
     #[rpc(name = "message")]
-    fn message(&self, address: Address, message: AudioModuleCommand) -> Result<bool>;
+    fn message(
+        &self,
+        address: Address,
+        message: AudioModuleCommand,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> Result<bool>;
+
+    /// Set a parameter of a node by name, clamped to its range
+    /// Timed and ramped like `message`
+    #[rpc(name = "set_param")]
+    fn set_param(
+        &self,
+        address: Address,
+        name: String,
+        value: f64,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> Result<bool>;
+
+    /// Current value of a parameter of a node by name
+    #[rpc(name = "get_param")]
+    fn get_param(&self, address: Address, name: String) -> Result<f64>;
+
+    /// Save the current params of a node as a preset for its module type
+    /// Replaces the preset of the same name
+    #[rpc(name = "save_preset")]
+    fn save_preset(&self, address: Address, name: String) -> Result<Preset>;
+
+    /// Presets saved for a module type, e.g. `Envelope`
+    #[rpc(name = "list_presets")]
+    fn list_presets(&self, module_type: String) -> Result<Vec<Preset>>;
+
+    /// Set a node to a preset saved for its module type
+    /// Timed and ramped like `message`
+    #[rpc(name = "apply_preset")]
+    fn apply_preset(
+        &self,
+        address: Address,
+        name: String,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> Result<bool>;
+
+    /// Delete a preset of a module type
+    /// Returns false when there is no such preset
+    #[rpc(name = "delete_preset")]
+    fn delete_preset(&self, module_type: String, name: String) -> Result<bool>;
+
+    /// Save presets listed in an earlier session, or none of them
+    #[rpc(name = "load_presets")]
+    fn load_presets(&self, presets: Vec<Preset>) -> Result<bool>;
+
+    /// Apply a batch of operations together, or none of them
+    #[rpc(name = "apply")]
+    fn apply(&self, operations: Vec<Operation>) -> Result<Vec<OperationResult>>;
+
+    /// Snapshot of the whole patch
+    #[rpc(name = "get_patch")]
+    fn get_patch(&self) -> Result<Patch>;
+
+    /// The whole patch with the named ports of every module
+    #[rpc(name = "describe")]
+    fn describe(&self) -> Result<GraphDescription>;
+
+    /// Every module that can be created, with its ports and default params
+    #[rpc(name = "list_modules")]
+    fn list_modules(&self) -> Result<Vec<ModuleInfo>>;
+
+    /// Replace the whole patch with a snapshot
+    #[rpc(name = "load_patch")]
+    fn load_patch(&self, patch: Patch) -> Result<bool>;
+
+    /// Seed every random source, so the patch sounds the same each time it is played
+    /// Setting a new seed re-rolls them, e.g. the segments picked by samplers
+    #[rpc(name = "set_seed")]
+    fn set_seed(&self, seed: u32) -> Result<bool>;
+
+    /// Seed of the random sources, also stored in patch snapshots
+    #[rpc(name = "get_seed")]
+    fn get_seed(&self) -> Result<u32>;
+
+    /// Undo the last change to the patch
+    /// Returns false when there is nothing to undo
+    #[rpc(name = "undo")]
+    fn undo(&self) -> Result<bool>;
+
+    /// Redo the last undone change to the patch
+    /// Returns false when there is nothing to redo
+    #[rpc(name = "redo")]
+    fn redo(&self) -> Result<bool>;
 
     /// Subscribe to node state changes
     #[pubsub(subscription = "node", subscribe, name = "subscribe")]
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use futures::{FutureExt, SinkExt, StreamExt};
-use jsonrpc_core::{Error, ErrorCode, Result};
+use jsonrpc_core::{serde_json, Error, ErrorCode, Result};
 use jsonrpc_pubsub::{manager::SubscriptionManager, typed::Subscriber, Session, SubscriptionId};
 use petgraph::graph::EdgeIndex;
 use wasm_bindgen::JsValue;
@@ -10,7 +10,17 @@ use web_sys::console;
 pub mod interface;
 
 use crate::{
-    interface::address::Address,
+    dsp::param::Ramp,
+    graph::SumMode,
+    interface::{
+        address::Address,
+        describe::{GraphDescription, ModuleInfo},
+        error::SobakaError,
+        operation::{Operation, OperationResult},
+        patch::Patch,
+        preset::Preset,
+        time::CommandTime,
+    },
     module::{AudioModuleCommand, AudioModuleEvent, AudioModuleType},
     utils::{id_provider::AtomicIdProvider, wasm_executer::WasmSpawner},
     AudioProcessor,
@@ -37,37 +47,137 @@ impl AudioProcessorRpc {
     }
 }
 
+/// Refused edits keep their reason, with the error as `data` so the frontend can explain it.
+impl From<SobakaError> for Error {
+    fn from(error: SobakaError) -> Self {
+        Error {
+            code: ErrorCode::ServerError(error.code()),
+            message: error.to_string(),
+            data: serde_json::to_value(&error).ok(),
+        }
+    }
+}
+
 impl SobakaGraphRpc for AudioProcessorRpc {
     type Metadata = Arc<Session>;
 
-    fn create(&self, node: AudioModuleType) -> Result<Address> {
-        self.processor
-            .create(node)
-            .map_err(|_| Error::invalid_request())
+    fn create(&self, node: AudioModuleType, parent: Option<Address>) -> Result<Address> {
+        self.processor.create(node, parent).map_err(Error::from)
     }
 
     fn dispose(&self, address: Address) -> Result<bool> {
+        self.processor.dispose(address).map_err(Error::from)
+    }
+
+    fn connect(&self, from: Address, to: Address, mode: Option<SumMode>) -> Result<usize> {
+        self.processor.connect(from, to, mode).map_err(Error::from)
+    }
+
+    fn disconnect(&self, id: usize, parent: Option<Address>) -> Result<bool> {
+        self.processor
+            .disconnect(EdgeIndex::new(id), parent)
+            .map_err(Error::from)
+    }
+
+    fn message(
+        &self,
+        address: Address,
+        message: AudioModuleCommand,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> Result<bool> {
+        self.processor
+            .message(address, message, at, ramp)
+            .map_err(Error::from)
+    }
+
+    fn set_param(
+        &self,
+        address: Address,
+        name: String,
+        value: f64,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> Result<bool> {
+        self.processor
+            .set_param(address, &name, value, at, ramp)
+            .map_err(Error::from)
+    }
+
+    fn get_param(&self, address: Address, name: String) -> Result<f64> {
+        self.processor
+            .get_param(address, &name)
+            .map_err(Error::from)
+    }
+
+    fn save_preset(&self, address: Address, name: String) -> Result<Preset> {
         self.processor
-            .dispose(address)
-            .map_err(|_| Error::invalid_request())
+            .save_preset(address, &name)
+            .map_err(Error::from)
     }
 
-    fn connect(&self, from: Address, to: Address) -> Result<usize> {
+    fn list_presets(&self, module_type: String) -> Result<Vec<Preset>> {
         self.processor
-            .connect(from, to)
-            .map_err(|_| Error::invalid_request())
+            .list_presets(&module_type)
+            .map_err(Error::from)
     }
 
-    fn disconnect(&self, id: usize) -> Result<bool> {
+    fn apply_preset(
+        &self,
+        address: Address,
+        name: String,
+        at: Option<CommandTime>,
+        ramp: Option<Ramp>,
+    ) -> Result<bool> {
         self.processor
-            .disconnect(EdgeIndex::new(id))
-            .map_err(|_| Error::invalid_request())
+            .apply_preset(address, &name, at, ramp)
+            .map_err(Error::from)
     }
 
-    fn message(&self, address: Address, message: AudioModuleCommand) -> Result<bool> {
+    fn delete_preset(&self, module_type: String, name: String) -> Result<bool> {
         self.processor
-            .message(address, message)
-            .map_err(|_| Error::invalid_request())
+            .delete_preset(&module_type, &name)
+            .map_err(Error::from)
+    }
+
+    fn load_presets(&self, presets: Vec<Preset>) -> Result<bool> {
+        self.processor.load_presets(presets).map_err(Error::from)
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> Result<Vec<OperationResult>> {
+        self.processor.apply(operations).map_err(Error::from)
+    }
+
+    fn get_patch(&self) -> Result<Patch> {
+        self.processor.get_patch().map_err(Error::from)
+    }
+
+    fn describe(&self) -> Result<GraphDescription> {
+        self.processor.describe().map_err(Error::from)
+    }
+
+    fn list_modules(&self) -> Result<Vec<ModuleInfo>> {
+        Ok(self.processor.list_modules())
+    }
+
+    fn load_patch(&self, patch: Patch) -> Result<bool> {
+        self.processor.load_patch(patch).map_err(Error::from)
+    }
+
+    fn set_seed(&self, seed: u32) -> Result<bool> {
+        self.processor.set_seed(seed).map_err(Error::from)
+    }
+
+    fn get_seed(&self) -> Result<u32> {
+        Ok(self.processor.seed())
+    }
+
+    fn undo(&self) -> Result<bool> {
+        self.processor.undo().map_err(Error::from)
+    }
+
+    fn redo(&self) -> Result<bool> {
+        self.processor.redo().map_err(Error::from)
     }
 
     fn subscribe(
@@ -76,11 +186,7 @@ impl SobakaGraphRpc for AudioProcessorRpc {
         subscriber: Subscriber<AudioModuleEvent>,
         node: Address,
     ) {
-        match self
-            .processor
-            .subscribe(node)
-            .map_err(|_| Error::invalid_request())
-        {
+        match self.processor.subscribe(node) {
             Ok(stream) => {
                 self.subscriptions.add(subscriber, |sink| {
                     stream
@@ -93,15 +199,9 @@ impl SobakaGraphRpc for AudioProcessorRpc {
                         .map(|_| ())
                 });
             }
-            Err(_error) => {
+            Err(error) => {
                 // Failed to subscribe
-                subscriber
-                    .reject(Error {
-                        code: ErrorCode::ParseError,
-                        message: "Node not subscribable. Subscription rejected.".into(),
-                        data: None,
-                    })
-                    .unwrap();
+                subscriber.reject(error.into()).unwrap();
             }
         }
     }
@@ -117,21 +217,24 @@ impl SobakaGraphRpc for AudioProcessorRpc {
 
 #[cfg(test)]
 mod tests {
+    use fundsp::{hacker::AudioUnit32, MAX_BUFFER_SIZE};
     use futures::{channel::mpsc, executor::ThreadPool};
+    use jsonrpc_core::{serde_json, Value};
     use jsonrpc_pubsub::{manager::SubscriptionManager, PubSubHandler, Session};
     use std::sync::Arc;
 
-    use crate::{utils::id_provider::AtomicIdProvider, AudioProcessor};
+    use crate::{engine::AudioEngine, utils::id_provider::AtomicIdProvider, AudioProcessor};
 
     use super::{interface::SobakaGraphRpc, AudioProcessorRpc};
 
-    fn build_rpc() -> (PubSubHandler<Arc<Session>>, Arc<Session>) {
+    fn build_rpc() -> (PubSubHandler<Arc<Session>>, Arc<Session>, AudioEngine) {
         let mut handler = PubSubHandler::default();
 
         let executor = ThreadPool::new().unwrap();
+        let (processor, engine) = AudioProcessor::new();
 
         let rpc = AudioProcessorRpc {
-            processor: Arc::new(AudioProcessor::new()),
+            processor: Arc::new(processor),
             subscriptions: SubscriptionManager::with_id_provider(
                 AtomicIdProvider::default(),
                 Arc::new(executor),
@@ -143,12 +246,12 @@ mod tests {
         let (tx, _rx) = mpsc::unbounded();
 
         let meta = Arc::new(Session::new(tx));
-        (handler, meta)
+        (handler, meta, engine)
     }
 
     #[test]
     fn test_module_creation() {
-        let (handler, meta) = build_rpc();
+        let (handler, meta, _engine) = build_rpc();
         let request = r#"{"jsonrpc":"2.0","id":1,"method":"create","params":[{ "node_type": "Oscillator", "data": { "saw": 0.25, "sine": 0.25, "square": 0.25, "triangle": 0.25, "pitch": 0.0 }}]}"#;
         let response = handler.handle_request_sync(request, meta);
 
@@ -159,7 +262,7 @@ mod tests {
 
     #[test]
     fn test_module_connect() {
-        let (handler, meta) = build_rpc();
+        let (handler, meta, _engine) = build_rpc();
         let request = r#"{"jsonrpc":"2.0","id":1,"method":"connect","params":["/sobaka/0/out-0", "/sobaka/1/in-0"]}"#;
         let response = handler.handle_request_sync(request, meta);
 
@@ -167,4 +270,630 @@ mod tests {
 
         assert_eq!(response, Some(expected.to_owned()));
     }
+
+    #[test]
+    fn test_module_connect_sum_mode() {
+        let (handler, meta, _engine) = build_rpc();
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"connect","params":["/sobaka/0/out-0", "/sobaka/1/in-0", "Max"]}"#;
+        let response = handler.handle_request_sync(request, meta);
+
+        let expected = r#"{"jsonrpc":"2.0","result":0,"id":1}"#;
+
+        assert_eq!(response, Some(expected.to_owned()));
+    }
+
+    #[test]
+    fn test_module_connect_errors() {
+        let (handler, meta, _engine) = build_rpc();
+        let connect = |from: &str, to: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"connect","params":["{}", "{}"]}}"#,
+                from, to
+            );
+            handler.handle_request_sync(&request, meta.clone()).unwrap()
+        };
+
+        assert_eq!(
+            connect("/sobaka/0/out-0", "/sobaka/7/in-0"),
+            r#"{"jsonrpc":"2.0","error":{"code":-32001,"message":"no module at /sobaka/7","data":{"data":"/sobaka/7","kind":"UnknownNode"}},"id":1}"#
+        );
+        assert_eq!(
+            connect("/sobaka/0/out-0", "/sobaka/1/in-3"),
+            r#"{"jsonrpc":"2.0","error":{"code":-32002,"message":"port /sobaka/1/in-3 is out of range, module has 3","data":{"data":{"address":"/sobaka/1/in-3","ports":3},"kind":"PortOutOfRange"}},"id":1}"#
+        );
+        assert_eq!(
+            connect("/sobaka/0/in-0", "/sobaka/1/in-0"),
+            r#"{"jsonrpc":"2.0","error":{"code":-32003,"message":"expected /sobaka/0/in-0 to be an output port","data":{"data":{"address":"/sobaka/0/in-0","expected":"Output"},"kind":"WrongPortDirection"}},"id":1}"#
+        );
+        assert_eq!(
+            connect("/sobaka/0/out-0", "/sobaka/2/0/in-0"),
+            r#"{"jsonrpc":"2.0","error":{"code":-32008,"message":"cannot connect /sobaka/0/out-0 to /sobaka/2/0/in-0 across subpatch boundaries","data":{"data":{"from":"/sobaka/0/out-0","to":"/sobaka/2/0/in-0"},"kind":"CrossSubpatch"}},"id":1}"#
+        );
+    }
+
+    #[test]
+    fn test_message_errors() {
+        let (handler, meta, _engine) = build_rpc();
+        let call = |method: &str, params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            serde_json::from_str::<Value>(&response).unwrap()
+        };
+
+        call(
+            "create",
+            r#"[{ "node_type": "Oscillator", "data": { "saw": 0.25, "sine": 0.25, "square": 0.25, "triangle": 0.25, "pitch": 0.0 }}]"#,
+        );
+
+        let message = |address: &str, command: &str| {
+            call("message", &format!(r#"["{}", {}]"#, address, command))["error"]["data"].clone()
+        };
+        assert_eq!(
+            message(
+                "/sobaka/7",
+                r#"{ "node_type": "Oscillator", "data": { "SetSawLevel": 1.0 }}"#
+            )["kind"],
+            "UnknownNode"
+        );
+        // Tag 0 of a delay is not the saw level of an oscillator
+        assert_eq!(
+            message(
+                "/sobaka/2",
+                r#"{ "node_type": "Delay", "data": { "SetDelay": 1.0 }}"#
+            )["kind"],
+            "CommandMismatch"
+        );
+        assert_eq!(
+            message(
+                "/sobaka/2",
+                r#"{ "node_type": "Quantiser", "data": { "UpdateNotes": [true, false, true, false, true, false, true, false, true, false, true, false] }}"#
+            )["kind"],
+            "CommandMismatch"
+        );
+        assert_eq!(call("get_param", r#"["/sobaka/2", "saw"]"#)["result"], 0.25);
+
+        let set_param = |address: &str, name: &str| {
+            call(
+                "set_param",
+                &format!(r#"["{}", "{}", 1.0, null, null]"#, address, name),
+            )["error"]["data"]
+                .clone()
+        };
+        assert_eq!(set_param("/sobaka/7", "saw")["kind"], "UnknownNode");
+        assert_eq!(
+            set_param("/sobaka/2", "level"),
+            serde_json::json!({
+                "kind": "UnknownParam",
+                "data": { "address": "/sobaka/2", "name": "level" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_patch_snapshot() {
+        let (handler, meta, mut engine) = build_rpc();
+
+        let call = |handler: &PubSubHandler<Arc<Session>>, method: &str, params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            let response: Value = serde_json::from_str(&response).unwrap();
+            response["result"].clone()
+        };
+
+        let parameter =
+            r#"[{ "node_type": "Parameter", "data": { "min": 0.0, "max": 1.0, "default": 1.0 }}]"#;
+        call(&handler, "create", parameter);
+        call(&handler, "create", parameter);
+        call(&handler, "create", r#"[{ "node_type": "Output" }]"#);
+        call(
+            &handler,
+            "connect",
+            r#"["/sobaka/3/out-0", "/sobaka/4/in-1", "Max"]"#,
+        );
+        call(
+            &handler,
+            "message",
+            r#"["/sobaka/3", { "node_type": "Parameter", "data": { "SetParameter": 0.5 }}]"#,
+        );
+        // Leave gaps in module and connection ids
+        call(&handler, "dispose", r#"["/sobaka/2"]"#);
+        call(&handler, "disconnect", r#"[0]"#);
+
+        call(&handler, "set_seed", "[42]");
+        assert_eq!(call(&handler, "get_seed", "[]"), 42);
+
+        let patch = call(&handler, "get_patch", "[]");
+        assert_eq!(patch["version"], 1);
+        assert_eq!(patch["seed"], 42);
+        assert_eq!(patch["modules"][0]["id"], 3);
+        assert_eq!(patch["modules"][0]["module"]["data"]["default"], 0.5);
+        assert_eq!(patch["modules"][1]["sum_modes"][1], "Max");
+
+        let (restored, restored_meta, mut restored_engine) = build_rpc();
+        let params = format!("[{}]", patch);
+        assert_eq!(
+            restored.handle_request_sync(
+                &format!(
+                    r#"{{"jsonrpc":"2.0","id":1,"method":"load_patch","params":{}}}"#,
+                    params
+                ),
+                restored_meta.clone()
+            ),
+            Some(r#"{"jsonrpc":"2.0","result":true,"id":1}"#.to_owned())
+        );
+        let restored_patch: Value = serde_json::from_str(
+            &restored
+                .handle_request_sync(
+                    r#"{"jsonrpc":"2.0","id":1,"method":"get_patch","params":[]}"#,
+                    restored_meta,
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_patch["result"], patch);
+
+        // Both graphs render the same audio, once the restored params faded in
+        let render = |engine: &mut AudioEngine| {
+            let mut left = [0.0; MAX_BUFFER_SIZE];
+            let mut right = [0.0; MAX_BUFFER_SIZE];
+            let mut output = [0.0; MAX_BUFFER_SIZE];
+            for _ in 0..2000 {
+                engine.process(
+                    MAX_BUFFER_SIZE,
+                    &[],
+                    &mut [&mut left, &mut right, &mut output],
+                );
+            }
+            (left, right)
+        };
+        let rendered = render(&mut engine);
+        assert!(rendered.1[0] > 0.0);
+        assert_eq!(rendered, render(&mut restored_engine));
+
+        // Invalid patches are rejected without changing the graph
+        let mut invalid = patch.clone();
+        invalid["version"] = 2.into();
+        assert_eq!(
+            call(&handler, "load_patch", &format!("[{}]", invalid)),
+            Value::Null
+        );
+        let mut invalid = patch.clone();
+        invalid["modules"][0]["id"] = 4_000_000_000_u64.into();
+        assert_eq!(
+            call(&handler, "load_patch", &format!("[{}]", invalid)),
+            Value::Null
+        );
+        assert_eq!(call(&handler, "get_patch", "[]"), patch);
+    }
+
+    #[test]
+    fn test_patch_restores_params() {
+        let (handler, meta, mut engine) = build_rpc();
+        let (restored, restored_meta, mut restored_engine) = build_rpc();
+
+        let call = |handler: &PubSubHandler<Arc<Session>>,
+                    meta: &Arc<Session>,
+                    method: &str,
+                    params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            let response: Value = serde_json::from_str(&response).unwrap();
+            response["result"].clone()
+        };
+
+        call(
+            &handler,
+            &meta,
+            "create",
+            r#"[{ "node_type": "Oscillator", "data": { "saw": 1.0, "sine": 0.0, "square": 0.0, "triangle": 0.0, "pitch": 0.0 }}]"#,
+        );
+        call(
+            &handler,
+            &meta,
+            "create",
+            r#"[{ "node_type": "Parameter", "data": { "min": 0.0, "max": 1.0, "default": 1.0 }}]"#,
+        );
+        call(&handler, &meta, "create", r#"[{ "node_type": "Output" }]"#);
+        call(
+            &handler,
+            &meta,
+            "connect",
+            r#"["/sobaka/3/out-0", "/sobaka/2/in-1", null]"#,
+        );
+        call(
+            &handler,
+            &meta,
+            "connect",
+            r#"["/sobaka/2/out-0", "/sobaka/4/in-0", null]"#,
+        );
+        call(
+            &handler,
+            &meta,
+            "set_param",
+            r#"["/sobaka/2", "pitch", 2.0, null, null]"#,
+        );
+
+        let patch = call(&handler, &meta, "get_patch", "[]");
+        assert_eq!(patch["modules"][0]["module"]["data"]["pitch"], 2.0);
+        assert_eq!(
+            call(
+                &restored,
+                &restored_meta,
+                "load_patch",
+                &format!("[{}]", patch)
+            ),
+            true
+        );
+        assert_eq!(
+            call(
+                &restored,
+                &restored_meta,
+                "get_param",
+                r#"["/sobaka/2", "pitch"]"#
+            ),
+            2.0
+        );
+
+        // The restored oscillator plays at the pitch of the patch
+        let render = |engine: &mut AudioEngine| {
+            let mut left = [0.0; MAX_BUFFER_SIZE];
+            let mut right = [0.0; MAX_BUFFER_SIZE];
+            let mut output = [0.0; MAX_BUFFER_SIZE];
+            (0..10)
+                .flat_map(|_| {
+                    engine.process(
+                        MAX_BUFFER_SIZE,
+                        &[],
+                        &mut [&mut left, &mut right, &mut output],
+                    );
+                    left
+                })
+                .collect::<Vec<_>>()
+        };
+        let rendered = render(&mut engine);
+        assert!(rendered.iter().any(|sample| *sample != 0.0));
+        assert_eq!(rendered, render(&mut restored_engine));
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let (handler, meta, mut engine) = build_rpc();
+
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"apply","params":[[
+            { "op": "Create", "node": { "node_type": "Parameter", "data": { "min": 0.0, "max": 1.0, "default": 0.0 }}, "placeholder": "p" },
+            { "op": "Create", "node": { "node_type": "Output" }, "placeholder": "out" },
+            { "op": "Connect", "from": "$p/out-0", "to": "$out/in-0" },
+            { "op": "Message", "address": "$p", "message": { "node_type": "Parameter", "data": { "SetParameter": 1.0 }}}
+        ]]}"#;
+        let response = handler.handle_request_sync(request, meta.clone());
+        let expected = r#"{"jsonrpc":"2.0","result":["/sobaka/2","/sobaka/3",2,true],"id":1}"#;
+        assert_eq!(response, Some(expected.to_owned()));
+
+        // The whole batch lands in the first block, including the command
+        let mut left = [0.0; MAX_BUFFER_SIZE];
+        let mut right = [0.0; MAX_BUFFER_SIZE];
+        let mut output = [0.0; MAX_BUFFER_SIZE];
+        engine.process(
+            MAX_BUFFER_SIZE,
+            &[],
+            &mut [&mut left, &mut right, &mut output],
+        );
+        assert!(left[0] > 0.0);
+        for _ in 0..100 {
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+        }
+        let settled = left[MAX_BUFFER_SIZE - 1];
+
+        let patch = r#"{"jsonrpc":"2.0","id":1,"method":"get_patch","params":[]}"#;
+        let before = handler.handle_request_sync(patch, meta.clone());
+
+        // A failing operation rolls back the whole batch
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"apply","params":[[
+            { "op": "Disconnect", "id": 2 },
+            { "op": "Dispose", "address": "/sobaka/2" },
+            { "op": "Create", "node": { "node_type": "Noise" }, "placeholder": "noise" },
+            { "op": "Connect", "from": "$noise/out-0", "to": "/sobaka/3/in-5" }
+        ]]}"#;
+        let response = handler.handle_request_sync(request, meta.clone());
+        let expected = r#"{"jsonrpc":"2.0","error":{"code":-32002,"message":"operation 3 failed: port /sobaka/3/in-5 is out of range, module has 2","data":{"data":{"error":{"data":{"address":"/sobaka/3/in-5","ports":2},"kind":"PortOutOfRange"},"index":3},"kind":"Operation"}},"id":1}"#;
+        assert_eq!(response, Some(expected.to_owned()));
+        assert_eq!(handler.handle_request_sync(patch, meta.clone()), before);
+
+        // The parameter is still connected and keeps gliding towards 1.0
+        for _ in 0..100 {
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+        }
+        assert!(left[MAX_BUFFER_SIZE - 1] >= settled);
+
+        // Ids handed out after a rollback still match the audio graph
+        let request =
+            r#"{"jsonrpc":"2.0","id":1,"method":"create","params":[{ "node_type": "Noise" }]}"#;
+        let response = handler.handle_request_sync(request, meta);
+        let expected = r#"{"jsonrpc":"2.0","result":"/sobaka/4","id":1}"#;
+        assert_eq!(response, Some(expected.to_owned()));
+        engine.process(
+            MAX_BUFFER_SIZE,
+            &[],
+            &mut [&mut left, &mut right, &mut output],
+        );
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let (handler, meta, mut engine) = build_rpc();
+
+        let call = |method: &str, params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            let response: Value = serde_json::from_str(&response).unwrap();
+            response["result"].clone()
+        };
+
+        let empty = call("get_patch", "[]");
+        assert_eq!(call("undo", "[]"), false);
+
+        call(
+            "create",
+            r#"[{ "node_type": "Parameter", "data": { "min": 0.0, "max": 1.0, "default": 1.0 }}]"#,
+        );
+        call("create", r#"[{ "node_type": "Output" }]"#);
+        call("connect", r#"["/sobaka/2/out-0", "/sobaka/3/in-0", "Max"]"#);
+        let unchanged = call("get_patch", "[]");
+        call(
+            "message",
+            r#"["/sobaka/2", { "node_type": "Parameter", "data": { "SetParameter": 0.5 }}]"#,
+        );
+        let patched = call("get_patch", "[]");
+        call("dispose", r#"["/sobaka/2"]"#);
+        let disposed = call("get_patch", "[]");
+        assert_eq!(disposed["connections"].as_array().unwrap().len(), 2);
+
+        // The disposed module comes back with its connection and params
+        assert_eq!(call("undo", "[]"), true);
+        assert_eq!(call("get_patch", "[]"), patched);
+
+        let render = |engine: &mut AudioEngine| {
+            let mut left = [0.0; MAX_BUFFER_SIZE];
+            let mut right = [0.0; MAX_BUFFER_SIZE];
+            let mut output = [0.0; MAX_BUFFER_SIZE];
+            engine.process(
+                MAX_BUFFER_SIZE,
+                &[],
+                &mut [&mut left, &mut right, &mut output],
+            );
+            left[0]
+        };
+        assert!(render(&mut engine) > 0.0);
+
+        assert_eq!(call("undo", "[]"), true);
+        assert_eq!(call("get_patch", "[]"), unchanged);
+        assert_eq!(call("redo", "[]"), true);
+        assert_eq!(call("redo", "[]"), true);
+        assert_eq!(call("get_patch", "[]"), disposed);
+        assert_eq!(call("redo", "[]"), false);
+        assert_eq!(render(&mut engine), 0.0);
+
+        // Undo everything, down to the empty patch
+        for _ in 0..5 {
+            assert_eq!(call("undo", "[]"), true);
+        }
+        assert_eq!(call("undo", "[]"), false);
+        assert_eq!(call("get_patch", "[]"), empty);
+
+        // Redoing hands back the same addresses
+        assert_eq!(call("redo", "[]"), true);
+        assert_eq!(call("redo", "[]"), true);
+        assert_eq!(
+            call("connect", r#"["/sobaka/2/out-0", "/sobaka/3/in-1"]"#),
+            2
+        );
+
+        // A new change cannot be followed by a redo
+        assert_eq!(call("redo", "[]"), false);
+        render(&mut engine);
+    }
+
+    #[test]
+    fn test_describe() {
+        let (handler, meta, _engine) = build_rpc();
+
+        let call = |method: &str, params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            let response: Value = serde_json::from_str(&response).unwrap();
+            response["result"].clone()
+        };
+
+        let modules = call("list_modules", "[]");
+        let clock = modules
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|info| info["module"]["node_type"] == "Clock")
+            .unwrap();
+        assert_eq!(clock["module"]["data"]["bpm"], 120.0);
+        assert_eq!(clock["outputs"][4]["name"], "1/16");
+
+        call(
+            "create",
+            r#"[{ "node_type": "Oscillator", "data": { "saw": 0.25, "sine": 0.25, "square": 0.25, "triangle": 0.25, "pitch": 0.0 }}]"#,
+        );
+        call("create", r#"[{ "node_type": "Output" }]"#);
+
+        let graph = call("describe", "[]");
+        assert_eq!(graph["output"], 1);
+        assert_eq!(graph["outputs"].as_array().unwrap().len(), 3);
+
+        let oscillator = &graph["modules"][0];
+        assert_eq!(oscillator["id"], 2);
+        assert_eq!(
+            oscillator["inputs"][1],
+            serde_json::json!({ "name": "pitch_1", "kind": "Pitch", "min": 0.0, "max": 10.0 })
+        );
+        assert_eq!(oscillator["outputs"][0]["kind"], "Audio");
+        assert_eq!(graph["modules"][1]["inputs"][0]["name"], "left");
+        assert_eq!(graph["connections"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_params_by_name() {
+        let (handler, meta, _engine) = build_rpc();
+
+        let request = |method: &str, params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            serde_json::from_str::<Value>(&response).unwrap()
+        };
+        let call = |method: &str, params: &str| request(method, params)["result"].clone();
+
+        let modules = call("list_modules", "[]");
+        let vca = modules
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|info| info["module"]["node_type"] == "Vca")
+            .unwrap();
+        assert_eq!(
+            vca["params"][0],
+            serde_json::json!({
+                "name": "level", "tag": 0, "min": 0.0, "max": 1.0, "default": 0.5,
+                "unit": "Level", "curve": "Linear"
+            })
+        );
+
+        call(
+            "create",
+            r#"[{ "node_type": "Vca", "data": { "value": 0.5 }}]"#,
+        );
+        assert_eq!(call("get_param", r#"["/sobaka/2", "level"]"#), 0.5);
+
+        // Values are clamped to the range of the parameter
+        assert_eq!(call("set_param", r#"["/sobaka/2", "level", 2.0]"#), true);
+        assert_eq!(call("get_param", r#"["/sobaka/2", "level"]"#), 1.0);
+
+        // Commands of the module set the same parameters
+        call(
+            "message",
+            r#"["/sobaka/2", { "node_type": "Vca", "data": { "SetLevel": -1.0 }}]"#,
+        );
+        assert_eq!(call("get_param", r#"["/sobaka/2", "level"]"#), 0.0);
+
+        let error = request("set_param", r#"["/sobaka/2", "pitch", 1.0]"#);
+        assert_eq!(error["error"]["code"], -32014);
+
+        // Parameter changes are undone one at a time
+        assert_eq!(call("undo", "[]"), true);
+        assert_eq!(call("get_param", r#"["/sobaka/2", "level"]"#), 1.0);
+        assert_eq!(call("undo", "[]"), true);
+        assert_eq!(call("get_param", r#"["/sobaka/2", "level"]"#), 0.5);
+    }
+
+    #[test]
+    fn test_presets() {
+        let (handler, meta, _engine) = build_rpc();
+
+        let request = |method: &str, params: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                method, params
+            );
+            let response = handler.handle_request_sync(&request, meta.clone()).unwrap();
+            serde_json::from_str::<Value>(&response).unwrap()
+        };
+        let call = |method: &str, params: &str| request(method, params)["result"].clone();
+
+        let steps = r#"{ "steps": [
+            [true, false, false, false, false, false, false, false],
+            [false, false, false, false, false, false, false, false],
+            [false, false, false, false, false, false, false, false],
+            [false, false, false, false, false, false, false, true]
+        ]}"#;
+        call(
+            "create",
+            &format!(r#"[{{ "node_type": "StepSequencer", "data": {} }}]"#, steps),
+        );
+        call(
+            "create",
+            r#"[{ "node_type": "StepSequencer", "data": { "steps": [
+                [false, false, false, false, false, false, false, false],
+                [false, false, false, false, false, false, false, false],
+                [false, false, false, false, false, false, false, false],
+                [false, false, false, false, false, false, false, false]
+            ]}}]"#,
+        );
+        call(
+            "create",
+            r#"[{ "node_type": "Envelope", "data": { "attack": 0.5, "decay": 0.1, "sustain": 0.8, "release": 2.0 }}]"#,
+        );
+        call(
+            "create",
+            r#"[{ "node_type": "Envelope", "data": { "attack": 0.1, "decay": 0.1, "sustain": 0.1, "release": 0.1 }}]"#,
+        );
+        let before = call("get_patch", "[]");
+
+        // Presets are written like the modules of patch snapshots
+        let preset = call("save_preset", r#"["/sobaka/2", "bookends"]"#);
+        assert_eq!(preset["version"], 1);
+        assert_eq!(
+            preset["module"],
+            serde_json::json!({ "node_type": "StepSequencer", "data": serde_json::from_str::<Value>(steps).unwrap() })
+        );
+        call("save_preset", r#"["/sobaka/4", "pad"]"#);
+
+        assert_eq!(call("apply_preset", r#"["/sobaka/3", "bookends"]"#), true);
+        assert_eq!(call("apply_preset", r#"["/sobaka/5", "pad"]"#), true);
+        let patch = call("get_patch", "[]");
+        assert_eq!(patch["modules"][1]["module"], preset["module"]);
+        assert_eq!(patch["modules"][3]["module"], patch["modules"][2]["module"]);
+
+        // Presets only apply to modules of their type
+        let error = request("apply_preset", r#"["/sobaka/3", "pad"]"#);
+        assert_eq!(error["error"]["code"], -32015);
+
+        // Applying a preset is undone as a whole
+        assert_eq!(call("undo", "[]"), true);
+        assert_eq!(call("undo", "[]"), true);
+        assert_eq!(call("get_patch", "[]"), before);
+
+        let presets = call("list_presets", r#"["Envelope"]"#);
+        assert_eq!(presets.as_array().unwrap().len(), 1);
+        assert_eq!(presets[0]["name"], "pad");
+
+        assert_eq!(call("delete_preset", r#"["Envelope", "pad"]"#), true);
+        assert_eq!(call("delete_preset", r#"["Envelope", "pad"]"#), false);
+        assert_eq!(
+            call("list_presets", r#"["Envelope"]"#),
+            serde_json::json!([])
+        );
+
+        // Listed presets can be loaded in a later session
+        assert_eq!(call("load_presets", &format!("[{}]", presets)), true);
+        assert_eq!(call("list_presets", r#"["Envelope"]"#), presets);
+    }
 }
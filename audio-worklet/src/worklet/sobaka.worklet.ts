@@ -108,8 +108,9 @@ class SobakaProcessor extends AudioWorkletProcessor {
     // Only supports mono inputs for the moment
     const input = inputs[0][0] || new Float32Array()
 
-    // Process data in buffers
-    this.processor.process(input, outputs[0][0], outputs[0][1])
+    // Process data in buffers, timing scheduled commands against the frames of the context
+    // eslint-disable-next-line no-undef
+    this.processor.process(currentFrame, input, outputs[0][0], outputs[0][1])
 
     return true;
   }
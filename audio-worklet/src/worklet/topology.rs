@@ -0,0 +1,634 @@
+//! Mirror of the `Graph32` topology owned by the control thread.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use fundsp::hacker::{AudioUnit32, Tag};
+use futures::channel::{
+    mpsc::{self, UnboundedSender},
+    oneshot,
+};
+use petgraph::{
+    stable_graph::{EdgeIndex, StableGraph},
+    visit::EdgeRef,
+    Direction,
+};
+
+use crate::{
+    context::{GeneralContext, MessageError, ModuleContext, Notify},
+    dsp::param::Ramp,
+    engine::{AudioEngine, GraphEdit},
+    graph::{Graph32, NodeIndex, PortIndex, SumMode},
+    interface::{
+        describe::{GraphDescription, ModuleDescription},
+        patch::{Patch, PatchConnection, PatchModule, MAX_ID, PATCH_VERSION},
+        time::CommandTime,
+    },
+    module::{
+        port::{numbered, SignalKind},
+        AudioModuleCommand, AudioModuleType, ModuleUnit, NoOp,
+    },
+};
+
+/// Control side of a module in the graph.
+pub struct Module {
+    /// Number of inputs of the module unit.
+    pub inputs: usize,
+    /// Number of outputs of the module unit.
+    pub outputs: usize,
+    /// Number of channels carried by each port, more than one for polyphonic modules.
+    /// Channels of a port are consecutive, port `n` starts at channel `n * channels`.
+    pub channels: usize,
+    /// Context for the audio module
+    pub context: GeneralContext,
+    /// Params of the module, kept up to date with the commands it receives.
+    /// The global input and output have none.
+    pub state: Option<AudioModuleType>,
+    /// How the connections to each input port are combined.
+    pub sum_modes: Vec<SumMode>,
+    /// Inner topology when the module is a subpatch.
+    pub subpatch: Option<Topology>,
+}
+
+impl Module {
+    pub fn new(inputs: usize, outputs: usize, context: GeneralContext) -> Self {
+        Self {
+            inputs,
+            outputs,
+            channels: 1,
+            context,
+            state: None,
+            sum_modes: vec![SumMode::default(); inputs],
+            subpatch: None,
+        }
+    }
+}
+
+/// Changes made since `Topology::begin`, so they can be sent together or rolled back.
+struct Transaction {
+    /// Structure of the graph before the transaction.
+    graph: StableGraph<(), (PortIndex, PortIndex)>,
+    /// Edits held back until the transaction is committed.
+    edits: Vec<GraphEdit>,
+    /// Modules added during the transaction.
+    added: Vec<NodeIndex>,
+    /// Modules removed during the transaction.
+    removed: Vec<(NodeIndex, Module)>,
+    /// Params and sum modes of modules before they were changed.
+    changed: Vec<(NodeIndex, Option<AudioModuleType>, Vec<SumMode>)>,
+}
+
+/// Tracks modules and connections without owning any audio units.
+/// Every change is mirrored as a `GraphEdit` to the `AudioEngine` which owns the `Graph32`.
+/// Applying the same sequence of edits to both graphs hands out the same node and
+/// edge indices, so the control thread can validate edits and return ids before the
+/// audio thread has applied them.
+/// The graph only holds the structure, so it can be copied cheaply when a transaction begins.
+pub struct Topology {
+    graph: StableGraph<(), (PortIndex, PortIndex)>,
+    modules: BTreeMap<NodeIndex, Module>,
+    global_input: NodeIndex,
+    global_output: NodeIndex,
+    edits: UnboundedSender<GraphEdit>,
+    /// Cleared by the engine once an edit does not match its graph
+    synced: Arc<AtomicBool>,
+    transaction: Option<Transaction>,
+}
+
+impl Topology {
+    /// Create a topology for `graph`, along with the `AudioEngine` that applies its edits.
+    pub fn new(graph: Graph32) -> (Self, AudioEngine) {
+        let (edits, receiver) = mpsc::unbounded();
+        let synced = Arc::new(AtomicBool::new(true));
+
+        let mut mirror: StableGraph<(), (PortIndex, PortIndex)> = Default::default();
+        let mut modules = BTreeMap::new();
+
+        let global_input = mirror.add_node(());
+        modules.insert(
+            global_input,
+            Module::new(
+                graph.inputs(),
+                graph.inputs(),
+                ModuleContext::<NoOp, NoOp>::default().boxed(),
+            ),
+        );
+        let global_output = mirror.add_node(());
+        modules.insert(
+            global_output,
+            Module::new(
+                graph.outputs(),
+                graph.outputs(),
+                ModuleContext::<NoOp, NoOp>::default().boxed(),
+            ),
+        );
+
+        (
+            Self {
+                graph: mirror,
+                modules,
+                global_input,
+                global_output,
+                edits,
+                synced: synced.clone(),
+                transaction: None,
+            },
+            AudioEngine::new(graph, receiver, synced),
+        )
+    }
+
+    /// Whether the graphs of the engines still match this topology and those of its
+    /// subpatches. Once they do not, ids handed out no longer address the same modules.
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Relaxed)
+            && self
+                .modules
+                .values()
+                .filter_map(|module| module.subpatch.as_ref())
+                .all(Topology::is_synced)
+    }
+
+    pub fn global_input(&self) -> NodeIndex {
+        self.global_input
+    }
+
+    pub fn global_output(&self) -> NodeIndex {
+        self.global_output
+    }
+
+    pub fn get(&self, id: NodeIndex) -> Option<&Module> {
+        self.modules.get(&id)
+    }
+
+    /// Find the topology of a nested subpatch.
+    /// `path` lists the ids of the subpatch modules, outermost first.
+    pub fn subpatch_mut(&mut self, path: &[usize]) -> Option<&mut Topology> {
+        path.iter().try_fold(self, |topology, &id| {
+            topology
+                .modules
+                .get_mut(&NodeIndex::new(id))?
+                .subpatch
+                .as_mut()
+        })
+    }
+
+    pub fn add(&mut self, module: Module, unit: ModuleUnit) -> NodeIndex {
+        let id = self.graph.add_node(());
+        self.modules.insert(id, module);
+        if let Some(transaction) = self.transaction.as_mut() {
+            transaction.added.push(id);
+        }
+        self.send(GraphEdit::Add(id, unit));
+        id
+    }
+
+    pub fn remove(&mut self, id: NodeIndex) -> bool {
+        let removed = self.graph.remove_node(id).is_some();
+        if removed {
+            let module = self.modules.remove(&id).expect("module is in the graph");
+            if let Some(transaction) = self.transaction.as_mut() {
+                transaction.removed.push((id, module));
+            }
+            self.send(GraphEdit::Remove(id));
+        }
+        removed
+    }
+
+    /// Send a command to a module and update its params.
+    /// Parameters changed by the command ramp to their new value when `ramp` is given.
+    /// During a transaction the command is held back with the other edits,
+    /// and delivered by the audio thread when they are applied.
+    pub fn message(
+        &mut self,
+        id: NodeIndex,
+        message: AudioModuleCommand,
+        ramp: Option<Ramp>,
+    ) -> Result<(), MessageError> {
+        let notify = self.prepare(id, message, ramp)?;
+        if self.transaction.is_some() {
+            self.send(GraphEdit::Notify(notify));
+        } else {
+            notify();
+        }
+
+        Ok(())
+    }
+
+    /// Prepare a command for a module to deliver later with `schedule`.
+    /// Params are updated straight away.
+    pub fn prepare(
+        &mut self,
+        id: NodeIndex,
+        message: AudioModuleCommand,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError> {
+        let module = self.modules.get(&id).ok_or(MessageError::UnknownNode)?;
+
+        if let Some((tag, value)) = message.param() {
+            // Tags are only unique within a module type
+            let takes = module
+                .state
+                .as_ref()
+                .map_or(false, |state| state.type_name() == message.type_name());
+            if !takes {
+                return Err(MessageError::CommandMismatch);
+            }
+
+            return self.prepare_param(id, tag, value, ramp);
+        }
+
+        let notify = module.context.try_prepare(message.clone(), ramp)?;
+
+        self.change(id, |module| {
+            if let Some(state) = module.state.as_mut() {
+                state.update(&message);
+            }
+        });
+
+        Ok(notify)
+    }
+
+    /// Set a tagged parameter of a module, clamped to its range, and update its params.
+    /// During a transaction the change is held back with the other edits.
+    pub fn set_param(
+        &mut self,
+        id: NodeIndex,
+        tag: Tag,
+        value: f64,
+        ramp: Option<Ramp>,
+    ) -> Result<(), MessageError> {
+        let notify = self.prepare_param(id, tag, value, ramp)?;
+        if self.transaction.is_some() {
+            self.send(GraphEdit::Notify(notify));
+        } else {
+            notify();
+        }
+
+        Ok(())
+    }
+
+    /// Prepare a change of a tagged parameter to deliver later with `schedule`.
+    /// Params are updated straight away.
+    pub fn prepare_param(
+        &mut self,
+        id: NodeIndex,
+        tag: Tag,
+        value: f64,
+        ramp: Option<Ramp>,
+    ) -> Result<Notify, MessageError> {
+        let module = self.modules.get(&id).ok_or(MessageError::UnknownNode)?;
+        let param = module
+            .state
+            .as_ref()
+            .and_then(|state| state.param(tag))
+            .ok_or(MessageError::UnknownParam(tag))?;
+        let value = param.clamp(value);
+        let notify = module.context.try_prepare_param(tag, value, ramp)?;
+
+        self.change(id, |module| {
+            if let Some(state) = module.state.as_mut() {
+                state.set_param(tag, value);
+            }
+        });
+
+        Ok(notify)
+    }
+
+    /// Deliver a prepared command right before the frame at `time` is processed.
+    /// The engine of the root topology keeps the clock, so commands to modules inside
+    /// subpatches are scheduled there too.
+    pub fn schedule(&mut self, time: CommandTime, notify: Notify) {
+        self.send(GraphEdit::Schedule(time, notify));
+    }
+
+    /// Connect a port to another, carrying all channels of polyphonic ports.
+    pub fn connect(
+        &mut self,
+        source: NodeIndex,
+        source_port: PortIndex,
+        target: NodeIndex,
+        target_port: PortIndex,
+    ) -> EdgeIndex {
+        let source_channels = self.channels(source);
+        let target_channels = self.channels(target);
+
+        let id = self
+            .graph
+            .add_edge(source, target, (source_port, target_port));
+        self.send(GraphEdit::Connect(
+            id,
+            (source, (source_port * source_channels, source_channels)),
+            (target, (target_port * target_channels, target_channels)),
+        ));
+        id
+    }
+
+    pub fn disconnect(&mut self, edge: EdgeIndex) -> bool {
+        let removed = self.graph.remove_edge(edge).is_some();
+        if removed {
+            self.send(GraphEdit::Disconnect(edge));
+        }
+        removed
+    }
+
+    /// Set how connections to a port are combined, on each of its channels.
+    pub fn set_sum_mode(&mut self, node: NodeIndex, port: PortIndex, mode: SumMode) {
+        self.change(node, |module| {
+            if let Some(sum_mode) = module.sum_modes.get_mut(port) {
+                *sum_mode = mode;
+            }
+        });
+
+        let channels = self.channels(node);
+        for channel in port * channels..(port + 1) * channels {
+            self.send(GraphEdit::SetSumMode((node, channel), mode));
+        }
+    }
+
+    /// Add a module with the given id, which must not be in use nor above `MAX_ID`.
+    /// Placeholders are added to the mirror until the id is handed out, then removed in
+    /// reverse order so that the ids handed out next are unchanged.
+    /// The engine does the same with `Graph32::add_at`.
+    pub fn add_at(&mut self, id: NodeIndex, module: Module, unit: ModuleUnit) -> bool {
+        if id.index() >= MAX_ID || self.graph.contains_node(id) {
+            return false;
+        }
+
+        let mut placeholders = vec![];
+        loop {
+            let added = self.graph.add_node(());
+            if added == id {
+                break;
+            }
+            placeholders.push(added);
+        }
+        for placeholder in placeholders.into_iter().rev() {
+            self.graph.remove_node(placeholder);
+        }
+
+        self.modules.insert(id, module);
+        if let Some(transaction) = self.transaction.as_mut() {
+            transaction.added.push(id);
+        }
+        self.send(GraphEdit::Add(id, unit));
+
+        true
+    }
+
+    /// Connect two ports with the given connection id, which must not be in use.
+    /// Works like `add_at`, with placeholder connections looping on the global input.
+    pub fn connect_at(
+        &mut self,
+        id: EdgeIndex,
+        source: NodeIndex,
+        source_port: PortIndex,
+        target: NodeIndex,
+        target_port: PortIndex,
+    ) -> bool {
+        if id.index() >= MAX_ID || self.graph.edge_weight(id).is_some() {
+            return false;
+        }
+
+        let mut placeholders = vec![];
+        loop {
+            let added = self
+                .graph
+                .add_edge(self.global_input, self.global_input, (0, 0));
+            if added == id {
+                break;
+            }
+            placeholders.push(added);
+        }
+
+        // Removed ids are handed out again last in first out
+        self.graph.remove_edge(id);
+        let added = self.connect(source, source_port, target, target_port);
+
+        for placeholder in placeholders.into_iter().rev() {
+            self.graph.remove_edge(placeholder);
+        }
+
+        added == id
+    }
+
+    /// Seed the random sources of every module, including those in subpatches.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.send(GraphEdit::Seed(seed));
+    }
+
+    /// Remove all modules and connections, except the global input and output.
+    pub fn clear(&mut self) {
+        for edge in self.graph.edge_indices().collect::<Vec<_>>() {
+            self.disconnect(edge);
+        }
+
+        for node in self.graph.node_indices().collect::<Vec<_>>() {
+            if node != self.global_input && node != self.global_output {
+                self.remove(node);
+            }
+        }
+    }
+
+    /// Snapshot of the modules and connections, with subpatches nested inside.
+    pub fn patch(&self) -> Patch {
+        let modules = self
+            .modules
+            .keys()
+            .filter_map(|&id| self.module_patch(id))
+            .collect();
+
+        let mut connections: Vec<PatchConnection> = self
+            .graph
+            .edge_indices()
+            .filter_map(|edge| self.connection(edge))
+            .collect();
+        connections.sort_by_key(|connection| connection.id);
+
+        Patch {
+            version: PATCH_VERSION,
+            modules,
+            connections,
+            seed: None,
+        }
+    }
+
+    /// The modules and connections along with the ports of every module,
+    /// with subpatches nested inside.
+    pub fn describe(&self) -> GraphDescription {
+        let global_input = &self.modules[&self.global_input];
+        let global_output = &self.modules[&self.global_output];
+
+        let modules = self
+            .modules
+            .iter()
+            .filter_map(|(id, module)| {
+                let state = module.state.as_ref()?;
+                let (inputs, outputs) = state.ports();
+
+                Some(ModuleDescription {
+                    id: id.index(),
+                    module: state.clone(),
+                    channels: module.channels,
+                    inputs,
+                    outputs,
+                    params: state.params(),
+                    sum_modes: module.sum_modes.clone(),
+                    graph: module.subpatch.as_ref().map(Topology::describe),
+                })
+            })
+            .collect();
+
+        GraphDescription {
+            input: self.global_input.index(),
+            inputs: numbered("input", SignalKind::Audio, global_input.outputs),
+            output: self.global_output.index(),
+            outputs: numbered("output", SignalKind::Audio, global_output.inputs),
+            modules,
+            connections: self.patch().connections,
+        }
+    }
+
+    /// Snapshot of a module, none for the global input and output.
+    pub fn module_patch(&self, id: NodeIndex) -> Option<PatchModule> {
+        let module = self.modules.get(&id)?;
+
+        module.state.as_ref().map(|state| PatchModule {
+            id: id.index(),
+            module: state.clone(),
+            sum_modes: module.sum_modes.clone(),
+            patch: module.subpatch.as_ref().map(Topology::patch),
+        })
+    }
+
+    /// Snapshot of a connection.
+    pub fn connection(&self, edge: EdgeIndex) -> Option<PatchConnection> {
+        let (source, target) = self.graph.edge_endpoints(edge)?;
+        let &(source_port, target_port) = self.graph.edge_weight(edge)?;
+
+        Some(PatchConnection {
+            id: edge.index(),
+            from: (source.index(), source_port),
+            to: (target.index(), target_port),
+        })
+    }
+
+    /// Snapshots of the connections from and to a module, in order of id.
+    pub fn connections(&self, node: NodeIndex) -> Vec<PatchConnection> {
+        let mut connections: Vec<PatchConnection> = self
+            .graph
+            .edges_directed(node, Direction::Outgoing)
+            .chain(self.graph.edges_directed(node, Direction::Incoming))
+            .filter_map(|edge| self.connection(edge.id()))
+            .collect();
+        connections.sort_by_key(|connection| connection.id);
+        // Connections from a module to itself are listed twice
+        connections.dedup();
+        connections
+    }
+
+    /// Hold back edits, including those of subpatches, until `commit`.
+    /// Changes can be undone with `rollback` until then.
+    pub fn begin(&mut self) {
+        self.transaction = Some(Transaction {
+            graph: self.graph.clone(),
+            edits: vec![],
+            added: vec![],
+            removed: vec![],
+            changed: vec![],
+        });
+
+        for module in self.modules.values_mut() {
+            if let Some(subpatch) = module.subpatch.as_mut() {
+                subpatch.begin();
+            }
+        }
+    }
+
+    /// Send all edits since `begin` as one batch, so they are applied at the same block boundary.
+    pub fn commit(&mut self) {
+        if let Some(edits) = self.finish() {
+            let _ = self.edits.unbounded_send(edits);
+        }
+    }
+
+    /// Undo all changes since `begin`. Held back edits are dropped.
+    pub fn rollback(&mut self) {
+        if let Some(transaction) = self.transaction.take() {
+            self.graph = transaction.graph;
+
+            for id in transaction.added.into_iter().rev() {
+                self.modules.remove(&id);
+            }
+
+            for (id, module) in transaction.removed.into_iter().rev() {
+                self.modules.insert(id, module);
+            }
+
+            for (id, state, sum_modes) in transaction.changed.into_iter().rev() {
+                if let Some(module) = self.modules.get_mut(&id) {
+                    module.state = state;
+                    module.sum_modes = sum_modes;
+                }
+            }
+        }
+
+        for module in self.modules.values_mut() {
+            if let Some(subpatch) = module.subpatch.as_mut() {
+                subpatch.rollback();
+            }
+        }
+    }
+
+    /// End the transaction, collecting the held back edits into a batch.
+    /// Batches of subpatches are delivered by the audio thread while applying this batch,
+    /// so they are applied before the subpatches are next processed.
+    /// Their channels are set up here, delivering them does not allocate.
+    fn finish(&mut self) -> Option<GraphEdit> {
+        let mut edits = self.transaction.take()?.edits;
+
+        for module in self.modules.values_mut() {
+            if let Some(subpatch) = module.subpatch.as_mut() {
+                if let Some(batch) = subpatch.finish() {
+                    let (sender, receiver) = oneshot::channel();
+                    let _ = subpatch.edits.unbounded_send(GraphEdit::Pending(receiver));
+                    edits.push(GraphEdit::Notify(Box::new(move || {
+                        let _ = sender.send(batch);
+                    })));
+                }
+            }
+        }
+
+        Some(GraphEdit::Batch(edits))
+    }
+
+    /// Change a module, remembering its params and sum modes during a transaction.
+    fn change(&mut self, id: NodeIndex, f: impl FnOnce(&mut Module)) {
+        if let Some(module) = self.modules.get_mut(&id) {
+            if let Some(transaction) = self.transaction.as_mut() {
+                transaction
+                    .changed
+                    .push((id, module.state.clone(), module.sum_modes.clone()));
+            }
+            f(module);
+        }
+    }
+
+    fn channels(&self, node: NodeIndex) -> usize {
+        self.get(node).map_or(1, |module| module.channels)
+    }
+
+    fn send(&mut self, edit: GraphEdit) {
+        match self.transaction.as_mut() {
+            Some(transaction) => transaction.edits.push(edit),
+            // Edits are dropped once the audio engine is gone, there is nothing left to update.
+            None => {
+                let _ = self.edits.unbounded_send(edit);
+            }
+        }
+    }
+}
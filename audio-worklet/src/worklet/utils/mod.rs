@@ -1,5 +1,4 @@
 pub mod atomic_float;
-pub mod get_random;
 pub mod id_provider;
 pub mod observer;
 pub mod post_message_transport;
@@ -6,24 +6,32 @@ use fundsp::{hacker::AudioUnit32, MAX_BUFFER_SIZE};
 use jsonrpc_pubsub::{PubSubHandler, Session};
 
 use crate::{
-    rpc::interface::SobakaGraphRpc, rpc::AudioProcessorRpc,
+    engine::AudioEngine, rpc::interface::SobakaGraphRpc, rpc::AudioProcessorRpc,
     utils::post_message_transport::PostMessageTransport, AudioProcessor,
 };
 
 #[wasm_bindgen]
-pub struct SobakaAudioWorkletProcessor(Arc<AudioProcessor>);
+pub struct SobakaAudioWorkletProcessor {
+    processor: Arc<AudioProcessor>,
+    engine: AudioEngine,
+}
 
 #[wasm_bindgen]
 impl SobakaAudioWorkletProcessor {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
-        SobakaAudioWorkletProcessor(Arc::new(AudioProcessor::new()))
+        let (processor, engine) = AudioProcessor::new();
+
+        SobakaAudioWorkletProcessor {
+            processor: Arc::new(processor),
+            engine,
+        }
     }
 
     pub fn init_messaging(&mut self, port: MessagePort) {
         let mut io = PubSubHandler::default();
 
-        let rpc = AudioProcessorRpc::new(self.0.clone());
+        let rpc = AudioProcessorRpc::new(self.processor.clone());
 
         io.extend_with(rpc.to_delegate());
 
@@ -33,18 +41,27 @@ impl SobakaAudioWorkletProcessor {
     }
 
     pub fn set_sample_rate(&mut self, sample_rate: f64) {
-        self.0.set_sample_rate(sample_rate)
+        self.processor.set_sample_rate(sample_rate);
+        self.engine.reset(Some(sample_rate));
     }
 
-    pub fn process(&mut self, input: &[f32], output_l: &mut [f32], output_r: &mut [f32]) {
-        let mut graph = self.0.graph_mut().unwrap();
+    /// Process a render quantum starting at `frame`, `currentFrame` of the AudioWorklet.
+    pub fn process(
+        &mut self,
+        frame: f64,
+        input: &[f32],
+        output_l: &mut [f32],
+        output_r: &mut [f32],
+    ) {
+        let engine = &mut self.engine;
+        engine.set_frame(frame as u64);
         // When no input is provided
         if input.is_empty() {
             for (l, r) in output_l
                 .chunks_mut(MAX_BUFFER_SIZE)
                 .zip(output_r.chunks_mut(MAX_BUFFER_SIZE))
             {
-                graph.process(MAX_BUFFER_SIZE, &[], &mut [l, r]);
+                engine.process(MAX_BUFFER_SIZE, &[], &mut [l, r]);
             }
         } else {
             // When input is provided
@@ -53,7 +70,7 @@ impl SobakaAudioWorkletProcessor {
                 .zip(output_r.chunks_mut(MAX_BUFFER_SIZE))
                 .zip(input.chunks(MAX_BUFFER_SIZE))
             {
-                graph.process(MAX_BUFFER_SIZE, &[i], &mut [l, r]);
+                engine.process(MAX_BUFFER_SIZE, &[i], &mut [l, r]);
             }
         }
     }
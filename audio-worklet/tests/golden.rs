@@ -0,0 +1,594 @@
+//! Golden audio tests. Modules are fed deterministic inputs and their outputs are compared
+//! against reference renders in `tests/golden`, so changes to the DSP cannot silently change
+//! the sound of existing patches.
+//!
+//! After an intended change to the sound, write the references again with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and listen to the new files.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use fundsp::MAX_BUFFER_SIZE;
+use sobaka_sample_audio_worklet::{
+    context::GeneralContext,
+    module::{
+        clock::ClockParams,
+        delay::DelayParams,
+        envelope::EnvelopeParams,
+        filter::FilterParams,
+        lfo::LfoParams,
+        oscillator::OscillatorParams,
+        parameter::ParameterParams,
+        port::{PortInfo, SignalKind},
+        quantiser::QuantiserParams,
+        reverb::ReverbParams,
+        sequencer::SequencerParams,
+        step_sequencer::StepSequencerParams,
+        string::StringParams,
+        vca::VcaParams,
+        AudioModuleType, ModuleUnit,
+    },
+};
+
+const SAMPLE_RATE: f64 = 44100.0;
+/// Samples rendered per module, unless a test asks for more
+const LENGTH: usize = 4096;
+/// Largest difference to the reference allowed for a sample
+const TOLERANCE: f32 = 1e-4;
+
+/// Deterministic signal fed to an input of a module.
+#[derive(Clone, Copy)]
+enum Stimulus {
+    Constant(f32),
+    /// High for `width` samples at the start of every `period` samples
+    Gate {
+        period: usize,
+        width: usize,
+    },
+    /// Straight line from `from` to `to` over the whole render
+    Ramp {
+        from: f32,
+        to: f32,
+    },
+    /// White noise between `-amplitude` and `amplitude`
+    Noise {
+        seed: u32,
+        amplitude: f32,
+    },
+}
+
+impl Stimulus {
+    /// Usual signal for a port of the given kind. Control voltages are left at zero,
+    /// as they are offsets to the params of the module.
+    fn for_port(port: &PortInfo, index: usize) -> Self {
+        match port.kind {
+            SignalKind::Audio => Stimulus::Noise {
+                seed: index as u32 + 1,
+                amplitude: 0.5,
+            },
+            SignalKind::Pitch => Stimulus::Ramp { from: 2.0, to: 5.0 },
+            SignalKind::Gate => Stimulus::Gate {
+                period: 2048,
+                width: 1024,
+            },
+            SignalKind::Unipolar | SignalKind::Bipolar => Stimulus::Constant(0.0),
+        }
+    }
+
+    fn render(&self, length: usize) -> Vec<f32> {
+        match *self {
+            Stimulus::Constant(value) => vec![value; length],
+            Stimulus::Gate { period, width } => (0..length)
+                .map(|i| if i % period < width { 1.0 } else { 0.0 })
+                .collect(),
+            Stimulus::Ramp { from, to } => (0..length)
+                .map(|i| from + (to - from) * i as f32 / length as f32)
+                .collect(),
+            Stimulus::Noise { seed, amplitude } => {
+                // xorshift32, which must not start from zero
+                let mut state = seed.max(1);
+                (0..length)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 17;
+                        state ^= state << 5;
+                        (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * amplitude
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A module rendered against its reference.
+struct Golden {
+    name: &'static str,
+    module: AudioModuleType,
+    inputs: Vec<(PortInfo, Stimulus)>,
+    length: usize,
+}
+
+impl Golden {
+    fn new(name: &'static str, module: AudioModuleType) -> Self {
+        assert_eq!(
+            module.channels(),
+            1,
+            "only single channel modules are supported"
+        );
+
+        let (inputs, _) = module.ports();
+        let inputs = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, port)| {
+                let stimulus = Stimulus::for_port(&port, index);
+                (port, stimulus)
+            })
+            .collect();
+
+        Self {
+            name,
+            module,
+            inputs,
+            length: LENGTH,
+        }
+    }
+
+    /// Feed `stimulus` to the input port named `name`.
+    fn input(mut self, name: &str, stimulus: Stimulus) -> Self {
+        let module = self.name;
+        let input = self
+            .inputs
+            .iter_mut()
+            .find(|(port, _)| port.name == name)
+            .unwrap_or_else(|| panic!("`{}` has no input named `{}`", module, name));
+        input.1 = stimulus;
+        self
+    }
+
+    fn length(self, length: usize) -> Self {
+        Self { length, ..self }
+    }
+
+    /// Render every output of the module.
+    fn render(&self) -> Vec<Vec<f32>> {
+        let (mut unit, _context): (ModuleUnit, GeneralContext) = (&self.module).into();
+        unit.reset(Some(SAMPLE_RATE));
+        assert_eq!(unit.inputs(), self.inputs.len());
+
+        let inputs: Vec<Vec<f32>> = self
+            .inputs
+            .iter()
+            .map(|(_, stimulus)| stimulus.render(self.length))
+            .collect();
+        let mut outputs = vec![vec![0.0; self.length]; unit.outputs()];
+
+        for start in (0..self.length).step_by(MAX_BUFFER_SIZE) {
+            let end = (start + MAX_BUFFER_SIZE).min(self.length);
+            let input: Vec<&[f32]> = inputs.iter().map(|input| &input[start..end]).collect();
+            let mut output: Vec<&mut [f32]> = outputs
+                .iter_mut()
+                .map(|output| &mut output[start..end])
+                .collect();
+            unit.process(end - start, &input, &mut output);
+        }
+
+        outputs
+    }
+
+    /// Compare the render with the reference, or write the reference when `UPDATE_GOLDEN` is set.
+    fn check(self) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.wav", self.name));
+        let actual = self.render();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            write_wav(&path, &actual).unwrap();
+            return;
+        }
+
+        let expected = read_wav(&path).unwrap_or_else(|error| {
+            panic!(
+                "cannot read reference {}: {}\nrun with UPDATE_GOLDEN=1 to create it",
+                path.display(),
+                error
+            )
+        });
+
+        if let Some(report) = self.compare(&expected, &actual) {
+            panic!(
+                "`{}` does not match {}\n{}run with UPDATE_GOLDEN=1 to accept the new output",
+                self.name,
+                path.display(),
+                report
+            );
+        }
+    }
+
+    /// Readable report of the differences, if any sample is off by more than the tolerance.
+    fn compare(&self, expected: &[Vec<f32>], actual: &[Vec<f32>]) -> Option<String> {
+        if expected.len() != actual.len() {
+            return Some(format!(
+                "reference has {} outputs, module has {}\n",
+                expected.len(),
+                actual.len()
+            ));
+        }
+
+        let (_, ports) = self.module.ports();
+        let mut report = String::new();
+        for (port, (expected, actual)) in ports.iter().zip(expected.iter().zip(actual)) {
+            if expected.len() != actual.len() {
+                writeln!(
+                    report,
+                    "output `{}`: reference has {} samples, render has {}",
+                    port.name,
+                    expected.len(),
+                    actual.len()
+                )
+                .unwrap();
+                continue;
+            }
+
+            let diff: Vec<f32> = expected
+                .iter()
+                .zip(actual)
+                .map(|(expected, actual)| (actual - expected).abs())
+                .collect();
+            // NaN counts as a difference
+            let off = |diff: f32| diff > TOLERANCE || diff.is_nan();
+            let first = match diff.iter().position(|&diff| off(diff)) {
+                Some(first) => first,
+                None => continue,
+            };
+
+            let count = diff.iter().filter(|&&diff| off(diff)).count();
+            let (worst, max) =
+                diff.iter()
+                    .enumerate()
+                    .fold((first, 0.0), |(worst, max), (i, &diff)| {
+                        if diff > max {
+                            (i, diff)
+                        } else {
+                            (worst, max)
+                        }
+                    });
+            let rms = (diff.iter().map(|diff| diff * diff).sum::<f32>() / diff.len() as f32).sqrt();
+
+            writeln!(
+                report,
+                "output `{}`: {} of {} samples off by more than {}, largest {:.6} at sample {}, rms {:.6}",
+                port.name,
+                count,
+                diff.len(),
+                TOLERANCE,
+                max,
+                worst,
+                rms
+            )
+            .unwrap();
+            writeln!(
+                report,
+                "  {:>8} {:>12} {:>12} {:>12}",
+                "sample", "expected", "actual", "diff"
+            )
+            .unwrap();
+            for i in first.saturating_sub(2)..(first + 6).min(diff.len()) {
+                writeln!(
+                    report,
+                    "  {:>8} {:>12.6} {:>12.6} {:>12.6}{}",
+                    i,
+                    expected[i],
+                    actual[i],
+                    actual[i] - expected[i],
+                    if off(diff[i]) { "  <" } else { "" }
+                )
+                .unwrap();
+            }
+        }
+
+        if report.is_empty() {
+            None
+        } else {
+            Some(report)
+        }
+    }
+}
+
+/// Write channels as a 32 bit float WAV file.
+fn write_wav(path: &Path, channels: &[Vec<f32>]) -> std::io::Result<()> {
+    let length = channels.first().map_or(0, Vec::len);
+    let data_length = (length * channels.len() * 4) as u32;
+    let channel_count = channels.len() as u16;
+
+    let mut bytes = Vec::with_capacity(44 + data_length as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    // Format 3 is IEEE float
+    bytes.extend_from_slice(&3u16.to_le_bytes());
+    bytes.extend_from_slice(&channel_count.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE as u32).to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE as u32 * channel_count as u32 * 4).to_le_bytes());
+    bytes.extend_from_slice(&(channel_count * 4).to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_length.to_le_bytes());
+    for i in 0..length {
+        for channel in channels {
+            bytes.extend_from_slice(&channel[i].to_le_bytes());
+        }
+    }
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, bytes)
+}
+
+/// Read channels of a 32 bit float WAV file, as written by `write_wav`.
+fn read_wav(path: &Path) -> Result<Vec<Vec<f32>>, String> {
+    let bytes = fs::read(path).map_err(|error| error.to_string())?;
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize
+    };
+
+    if bytes.len() < 44 || &bytes[0..4] != b"RIFF" || &bytes[8..16] != b"WAVEfmt " {
+        return Err("not a WAV file".to_owned());
+    }
+    if u16_at(20) != 3 || u16_at(34) != 32 {
+        return Err("samples are not 32 bit floats".to_owned());
+    }
+    if &bytes[36..40] != b"data" || 44 + u32_at(40) > bytes.len() {
+        return Err("data is missing or truncated".to_owned());
+    }
+
+    let channel_count = u16_at(22) as usize;
+    let mut channels = vec![vec![]; channel_count];
+    for (i, sample) in bytes[44..44 + u32_at(40)].chunks_exact(4).enumerate() {
+        let sample = f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+        channels[i % channel_count].push(sample);
+    }
+    Ok(channels)
+}
+
+#[test]
+fn test_stimulus_is_deterministic() {
+    let noise = Stimulus::Noise {
+        seed: 1,
+        amplitude: 0.5,
+    };
+    assert_eq!(noise.render(64), noise.render(64));
+    assert!(noise.render(64).iter().all(|sample| sample.abs() <= 0.5));
+
+    let gate = Stimulus::Gate {
+        period: 4,
+        width: 1,
+    };
+    assert_eq!(gate.render(8), [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn test_compare_reports_first_difference() {
+    let golden = Golden::new("vca", AudioModuleType::Vca(VcaParams { value: 0.5 }));
+    let expected = vec![vec![0.0; 16]];
+    let mut actual = expected.clone();
+    actual[0][10] = 0.5;
+
+    assert!(golden.compare(&expected, &expected).is_none());
+    let report = golden.compare(&expected, &actual).unwrap();
+    assert!(report.contains("output `output`: 1 of 16 samples off"));
+    assert!(report.contains("largest 0.500000 at sample 10"));
+}
+
+#[test]
+fn test_delay() {
+    Golden::new("delay", AudioModuleType::Delay(DelayParams { time: 0.01 }))
+        .input("reset", Stimulus::Constant(0.0))
+        .check();
+}
+
+#[test]
+fn test_envelope() {
+    Golden::new(
+        "envelope",
+        AudioModuleType::Envelope(EnvelopeParams {
+            attack: 0.005,
+            decay: 0.005,
+            sustain: 0.5,
+            release: 0.01,
+        }),
+    )
+    .check();
+}
+
+#[test]
+fn test_filter() {
+    Golden::new(
+        "filter",
+        AudioModuleType::Filter(FilterParams {
+            frequency: 6.0,
+            q: 1.0,
+        }),
+    )
+    .input(
+        "cutoff_cv",
+        Stimulus::Ramp {
+            from: -2.0,
+            to: 3.0,
+        },
+    )
+    .check();
+}
+
+#[test]
+fn test_clock() {
+    Golden::new(
+        "clock",
+        AudioModuleType::Clock(ClockParams {
+            bpm: 480.0,
+            ..Default::default()
+        }),
+    )
+    .length(8192)
+    .check();
+}
+
+#[test]
+fn test_noise() {
+    Golden::new("noise", AudioModuleType::Noise).check();
+}
+
+#[test]
+fn test_parameter() {
+    Golden::new(
+        "parameter",
+        AudioModuleType::Parameter(ParameterParams {
+            min: 0.0,
+            max: 10.0,
+            default: 3.0,
+        }),
+    )
+    .check();
+}
+
+#[test]
+fn test_oscillator() {
+    Golden::new(
+        "oscillator",
+        AudioModuleType::Oscillator(OscillatorParams {
+            pitch: 0.0,
+            saw: 0.5,
+            sine: 0.5,
+            square: 0.25,
+            triangle: 0.25,
+        }),
+    )
+    .input("pitch_2", Stimulus::Constant(0.0))
+    .input("pitch_3", Stimulus::Constant(0.0))
+    .input("pitch_4", Stimulus::Constant(0.0))
+    .check();
+}
+
+#[test]
+fn test_quantiser() {
+    // C major
+    let notes = [
+        true, false, true, false, true, true, false, true, false, true, false, true,
+    ];
+    Golden::new(
+        "quantiser",
+        AudioModuleType::Quantiser(QuantiserParams { notes }),
+    )
+    .input("signal_2", Stimulus::Ramp { from: 5.0, to: 2.0 })
+    .input("signal_3", Stimulus::Constant(3.3))
+    .input("signal_4", Stimulus::Constant(0.0))
+    .check();
+}
+
+#[test]
+fn test_string() {
+    Golden::new(
+        "string",
+        AudioModuleType::String(StringParams {
+            gain_per_second: 0.5,
+            damping: 0.5,
+        }),
+    )
+    .input("pitch", Stimulus::Constant(4.0))
+    .check();
+}
+
+#[test]
+fn test_reverb() {
+    Golden::new(
+        "reverb",
+        AudioModuleType::Reverb(ReverbParams {
+            wet: 0.5,
+            length: 0.5,
+        }),
+    )
+    .check();
+}
+
+#[test]
+fn test_sample_and_hold() {
+    Golden::new("sample_and_hold", AudioModuleType::SampleAndHold)
+        .input(
+            "signal",
+            Stimulus::Ramp {
+                from: -1.0,
+                to: 1.0,
+            },
+        )
+        .input(
+            "gate",
+            Stimulus::Gate {
+                period: 512,
+                width: 256,
+            },
+        )
+        .check();
+}
+
+#[test]
+fn test_sequencer() {
+    Golden::new(
+        "sequencer",
+        AudioModuleType::Sequencer(SequencerParams {
+            steps: [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0],
+        }),
+    )
+    .input(
+        "gate",
+        Stimulus::Gate {
+            period: 512,
+            width: 256,
+        },
+    )
+    .input("reset", Stimulus::Constant(0.0))
+    .check();
+}
+
+#[test]
+fn test_step_sequencer() {
+    let mut steps = [[false; 8]; 4];
+    for (row, steps) in steps.iter_mut().enumerate() {
+        for (step, on) in steps.iter_mut().enumerate() {
+            *on = step % (row + 1) == 0;
+        }
+    }
+
+    Golden::new(
+        "step_sequencer",
+        AudioModuleType::StepSequencer(StepSequencerParams { steps }),
+    )
+    .input(
+        "gate",
+        Stimulus::Gate {
+            period: 512,
+            width: 256,
+        },
+    )
+    .input("reset", Stimulus::Constant(0.0))
+    .check();
+}
+
+#[test]
+fn test_lfo() {
+    Golden::new("lfo", AudioModuleType::Lfo(LfoParams { bpm: 600.0 }))
+        .input("reset", Stimulus::Constant(0.0))
+        .length(8192)
+        .check();
+}
+
+#[test]
+fn test_vca() {
+    Golden::new("vca", AudioModuleType::Vca(VcaParams { value: 0.5 }))
+        .input("cv", Stimulus::Ramp { from: 0.0, to: 0.5 })
+        .check();
+}